@@ -0,0 +1,60 @@
+//! FIRST and FOLLOW sets of every rule of a long grammar, with and without the cache
+//!
+//! The crate has no library target so the modules are included by path
+
+#![feature(let_chains)]
+// Only part of the modules is used here, and their test imports are compiled without the tests
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src"]
+mod src {
+    pub mod diagnostic;
+    pub mod grammar;
+    pub mod lexer;
+    pub mod parser;
+    pub mod ring;
+    pub mod source;
+    pub mod span;
+    pub mod token;
+}
+
+use src::{diagnostic, grammar, lexer, parser, ring, source, span, token};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// A chain of `rules` rules where each one may start with the next
+fn source(rules: usize) -> String {
+    let mut source = String::new();
+    for i in 0..rules {
+        source.push_str(&format!("R{i} = R{}? 't{i}' | 'u{i}' R{} 'v{i}'\n", i + 1, i + 1));
+    }
+    source.push_str(&format!("R{rules} = 'end'\n"));
+    source
+}
+
+fn all_sets(grammar: &grammar::Grammar) {
+    for name in grammar.rules.keys() {
+        std::hint::black_box(grammar.first_set(name));
+        std::hint::black_box(grammar.follow_set(name));
+    }
+}
+
+fn bench_sets(c: &mut Criterion) {
+    let source = source(60);
+    let mut parser = parser::Parser::new(&source);
+    parser.parse();
+    let mut grammar = grammar::GrammarBuilder::new(&source, parser.tree()).build();
+
+    c.bench_function("sets uncached", |b| {
+        b.iter(|| {
+            grammar.clear_cache();
+            all_sets(&grammar);
+        })
+    });
+
+    all_sets(&grammar);
+    c.bench_function("sets cached", |b| b.iter(|| all_sets(&grammar)));
+}
+
+criterion_group!(benches, bench_sets);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+//! Lexes, parses and builds arbitrary input, any panic is a bug
+//!
+//! The crate has no library target so the modules are included by path
+
+#![no_main]
+#![feature(let_chains)]
+// Only part of the modules is used here, and their test imports are compiled without the tests
+#![allow(dead_code, unused_imports)]
+
+#[path = "../../src"]
+mod src {
+    pub mod diagnostic;
+    pub mod grammar;
+    pub mod lexer;
+    pub mod parser;
+    pub mod ring;
+    pub mod source;
+    pub mod span;
+    pub mod token;
+}
+
+use src::{diagnostic, grammar, lexer, parser, ring, source, span, token};
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    lexer::Lexer::<1>::new(source).for_each(drop);
+
+    let mut parser = parser::Parser::new(source);
+    parser.parse();
+    for diagnostic in parser.diagnostics() {
+        diagnostic.render(source);
+    }
+    let grammar = grammar::GrammarBuilder::new(source, parser.tree()).build();
+    grammar.to_ebnf_string();
+});
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
@@ -10,23 +10,102 @@ pub struct Args {
 #[derive(Debug, Subcommand)]
 pub enum Command {
     Lex {
+        /// Grammar file, `-` reads it from stdin
         path: PathBuf,
     },
     Tree {
+        /// Grammar file, `-` reads it from stdin
         path: PathBuf,
     },
     Parse {
+        /// Grammar file, `-` reads it from stdin
         path: PathBuf,
     },
     First {
+        /// Grammar file, `-` reads it from stdin
         path: PathBuf,
         non_terminal: Option<String>,
     },
     Follow {
+        /// Grammar file, `-` reads it from stdin
         path: PathBuf,
         non_terminal: Option<String>,
         /// Do not add FIRST(self) when self repeats i.e Fn*
         #[clap(long, short)]
         strict: bool,
     },
+    /// Print which non-terminals can derive the empty string
+    Nullable {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        non_terminal: Option<String>,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Pretty print the grammar, comments are not preserved
+    Format {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        /// Rewrite the file instead of printing it
+        #[clap(long, short)]
+        inplace: bool,
+        /// Sort the alternatives of every choice
+        #[clap(long)]
+        sort: bool,
+    },
+    /// Print the LL(1) parse table, exits with 1 when there are conflicts
+    Table {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        #[clap(long, conflicts_with = "csv")]
+        json: bool,
+        #[clap(long)]
+        csv: bool,
+    },
+    /// Run every grammar check, exits with 1 on errors and 2 on warnings only
+    Validate {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        /// Treat warnings as errors
+        #[clap(long, short)]
+        strict: bool,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print size and complexity metrics of the grammar
+    Stats {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Print the grammar as a Graphviz digraph
+    Dot {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        /// Only include the rules reachable from this rule
+        #[clap(long)]
+        start: Option<String>,
+        /// Write the graph to this file instead of stdout
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Generate a parser skeleton in Rust with a function per rule
+    Generate {
+        /// Grammar file, `-` reads it from stdin
+        path: PathBuf,
+        /// Write the parser to this file instead of stdout
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+        #[clap(long, value_enum, default_value_t = GenStyle::RecursiveDescent)]
+        style: GenStyle,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GenStyle {
+    /// Functions choosing the alternatives by the next terminal
+    RecursiveDescent,
+    /// Functions combining the parsers of a `Combinators` trait
+    ParserCombinator,
 }
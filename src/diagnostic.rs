@@ -0,0 +1,168 @@
+//! Structured errors pointing at the source
+
+use crate::{source::SourceFile, span::Span};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub severity: Severity,
+    pub message: String,
+    pub notes: Vec<(Span, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            severity,
+            message: message.into(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: Span, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, span, message)
+    }
+
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.notes.push((span, message.into()));
+        self
+    }
+
+    /// Formats the diagnostic like rustc does, without a file name:
+    ///
+    /// ```text
+    /// error: Expected identifier, got equals sign
+    ///  --> 2:1
+    ///   |
+    /// 2 | = 'b'
+    ///   | ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        self.render_with_name(source, None)
+    }
+
+    /// Like [`Diagnostic::render`] with the file name before the location, `--> a.ungram:2:1`
+    pub fn render_in(&self, file: &SourceFile) -> String {
+        self.render_with_name(&file.content, Some(&file.name))
+    }
+
+    fn render_with_name(&self, source: &str, name: Option<&str>) -> String {
+        let mut out = String::new();
+        let snippet = |out: &mut String, severity, span, message: &str| {
+            render_snippet(out, source, name, severity, span, message)
+        };
+        snippet(&mut out, self.severity, self.span, &self.message);
+        for (span, message) in &self.notes {
+            snippet(&mut out, Severity::Note, *span, message);
+        }
+        out
+    }
+}
+
+fn render_snippet(
+    out: &mut String,
+    source: &str,
+    name: Option<&str>,
+    severity: Severity,
+    span: Span,
+    message: &str,
+) {
+    let location = span.location(source);
+    let line = source.lines().nth(location.line - 1).unwrap_or_default();
+    let gutter = " ".repeat(location.line.to_string().len());
+
+    // Spans running past the line are only underlined up to its end
+    let line_start = span.start - source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let width = source[span.start..span.end.max(span.start)]
+        .lines()
+        .next()
+        .map_or(0, |text| text.chars().count())
+        .max(1);
+    let padding = line[..line_start.min(line.len())].chars().count();
+
+    out.push_str(&format!("{severity}: {message}\n"));
+    match name {
+        Some(name) => out.push_str(&format!("{gutter}--> {name}:{location:?}\n")),
+        None => out.push_str(&format!("{gutter}--> {location:?}\n")),
+    }
+    out.push_str(&format!("{gutter} |\n"));
+    out.push_str(&format!("{} | {line}\n", location.line));
+    out.push_str(&format!(
+        "{gutter} | {}{}\n",
+        " ".repeat(padding),
+        "^".repeat(width)
+    ));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let source = "A = 'a'\nB = 'b' C\n";
+        let diagnostic = Diagnostic::error(Span::new(16, 17), "undefined rule `C`")
+            .with_note(Span::new(8, 9), "in rule `B`");
+        assert_eq!(
+            diagnostic.render(source),
+            "error: undefined rule `C`\n \
+             --> 2:9\n  \
+               |\n\
+             2 | B = 'b' C\n  \
+               |         ^\n\
+             note: in rule `B`\n \
+             --> 2:1\n  \
+               |\n\
+             2 | B = 'b' C\n  \
+               | ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_wide_span() {
+        let source = "A = 'a' |\n  'b'";
+        let diagnostic = Diagnostic::warning(Span::new(4, 12), "odd");
+        let rendered = diagnostic.render(source);
+        assert!(rendered.starts_with("warning: odd\n"));
+        assert!(rendered.ends_with("1 | A = 'a' |\n  |     ^^^^^\n"));
+
+        let eof = Diagnostic::error(Span::new(15, 15), "Expected identifier, got end of file");
+        assert!(eof.render(source).ends_with("2 |   'b'\n  |      ^\n"));
+    }
+
+    #[test]
+    fn test_render_in() {
+        let file = SourceFile::new("example.ungram", "A = 'a'\nB = 'b' C\n".to_string());
+        let diagnostic = Diagnostic::error(Span::new(16, 17), "undefined rule `C`")
+            .with_note(Span::new(8, 9), "in rule `B`");
+        let rendered = diagnostic.render_in(&file);
+        assert!(rendered.starts_with("error: undefined rule `C`\n --> example.ungram:2:9\n"));
+        assert!(rendered.contains("note: in rule `B`\n --> example.ungram:2:1\n"));
+        assert_eq!(
+            rendered.replace("example.ungram:", ""),
+            diagnostic.render(&file.content)
+        );
+    }
+}
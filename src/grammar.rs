@@ -1,22 +1,464 @@
-use std::ops::Not;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    ops::{ControlFlow, Not},
+    sync::{Mutex, OnceLock},
+};
 
 use indexmap::{IndexMap, IndexSet};
 
 use crate::{
-    parser::{Child, Kind, Tree},
+    diagnostic::Diagnostic,
+    parser::{Child, Kind, Parser, Tree},
+    span::Span,
     token,
 };
 
+mod codegen;
+mod export;
+mod language;
+mod lr;
+mod railroad;
+mod stats;
+mod table;
+mod transform;
+
+/// Terminal following the start rule in FOLLOW sets, the end of the input
+pub const END_OF_INPUT: &str = "$";
+
+#[derive(Clone)]
 pub struct Grammar<'src> {
+    /// Changing the rules directly leaves the cached sets stale, see [`Grammar::clear_cache`]
     pub rules: IndexMap<&'src str, Expr<'src>>,
+    /// Rules annotated with `// @allow_left_recursion`, [`Grammar::validate`] does not report
+    /// their left recursion
+    pub allowed_left_recursion: IndexSet<&'src str>,
+    cache: RefCell<SetCache<'src>>,
+}
+
+/// FIRST and FOLLOW sets already computed, FOLLOW sets are keyed by rule and strictness
+#[derive(Clone, Default)]
+struct SetCache<'src> {
+    first: HashMap<&'src str, IndexSet<&'src str>>,
+    follow: HashMap<(&'src str, bool), IndexSet<&'src str>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LeftRecursion {
+    /// `A = A 'x'`
+    Direct,
+    /// `A = B 'x'`, `B = A 'y'`
+    Indirect,
+}
+
+/// Side on which the rule references of a linear production are, see [`Grammar::is_regular`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Linearity {
+    /// `A = 'x' B`
+    Right,
+    /// `A = B 'x'`
+    Left,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegularityResult {
+    Regular(Linearity),
+    /// The first rule that is neither right nor left linear, or when every rule is one of
+    /// them the first one that is not right linear
+    NotRegular {
+        counterexample_rule: String,
+    },
+}
+
+/// Alternatives of a choice whose PREDICT sets overlap
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ll1Conflict<'src> {
+    pub rule: &'src str,
+    /// Child indices leading from the rule body to the choice, empty for the top level choice
+    pub path: Vec<usize>,
+    pub branches: Vec<usize>,
+    pub tokens: IndexSet<&'src str>,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport<'src> {
+    pub errors: Vec<ValidationError<'src>>,
+    pub warnings: Vec<ValidationWarning<'src>>,
+}
+
+impl ValidationReport<'_> {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// 0 when clean, 1 with errors and 2 with only warnings. `strict` treats warnings as errors
+    pub fn exit_code(&self, strict: bool) -> i32 {
+        match (self.errors.is_empty(), self.warnings.is_empty()) {
+            (true, true) => 0,
+            (true, false) if !strict => 2,
+            _ => 1,
+        }
+    }
+
+    /// `{"errors":[{"rule":..,"message":..}],"warnings":[..]}`
+    pub fn to_json(&self) -> String {
+        let entry = |rule: &str, message: String| {
+            format!(
+                r#"{{"rule":{},"message":{}}}"#,
+                json_string(rule),
+                json_string(&message)
+            )
+        };
+        let errors = self
+            .errors
+            .iter()
+            .map(|error| entry(error.rule(), error.to_string()))
+            .collect::<Vec<_>>();
+        let warnings = self
+            .warnings
+            .iter()
+            .map(|warning| entry(warning.rule(), warning.to_string()))
+            .collect::<Vec<_>>();
+        format!(
+            r#"{{"errors":[{}],"warnings":[{}]}}"#,
+            errors.join(","),
+            warnings.join(",")
+        )
+    }
+}
+
+/// Strongly connected components of a graph of rules, following Tarjan's algorithm
+struct Tarjan<'a, 'src> {
+    edges: &'a IndexMap<&'src str, IndexSet<&'src str>>,
+    index: HashMap<&'src str, usize>,
+    low_link: HashMap<&'src str, usize>,
+    /// Rules visited and not yet assigned to a component
+    stack: IndexSet<&'src str>,
+    components: Vec<Vec<&'src str>>,
+}
+
+impl<'src> Tarjan<'_, 'src> {
+    fn visit(&mut self, rule: &'src str) {
+        let index = self.index.len();
+        self.index.insert(rule, index);
+        self.low_link.insert(rule, index);
+        self.stack.insert(rule);
+
+        for &next in &self.edges[rule] {
+            if !self.index.contains_key(next) {
+                self.visit(next);
+                self.low_link
+                    .insert(rule, self.low_link[rule].min(self.low_link[next]));
+            } else if self.stack.contains(next) {
+                self.low_link
+                    .insert(rule, self.low_link[rule].min(self.index[next]));
+            }
+        }
+
+        if self.low_link[rule] == index {
+            let start = self.stack.get_index_of(rule).unwrap();
+            self.components.push(self.stack.drain(start..).collect());
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationError<'src> {
+    /// Rules deriving themselves through ε, see [`Grammar::epsilon_cycles`]
+    EpsilonCycle {
+        rules: Vec<&'src str>,
+    },
+    /// Rules expanding only into each other, see [`Grammar::livelock_check`]
+    Livelock {
+        rules: Vec<&'src str>,
+    },
+    UndefinedRule {
+        rule: &'src str,
+    },
+    UnproductiveRule {
+        rule: &'src str,
+    },
+    LeftRecursion {
+        rule: &'src str,
+        kind: LeftRecursion,
+    },
+}
+
+impl<'src> ValidationError<'src> {
+    pub fn rule(&self) -> &'src str {
+        match self {
+            ValidationError::UndefinedRule { rule }
+            | ValidationError::UnproductiveRule { rule }
+            | ValidationError::LeftRecursion { rule, .. } => rule,
+            ValidationError::EpsilonCycle { rules } | ValidationError::Livelock { rules } => {
+                rules[0]
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::UndefinedRule { rule } => {
+                write!(f, "rule `{rule}` is referenced but never defined")
+            }
+            ValidationError::UnproductiveRule { rule } => {
+                write!(f, "rule `{rule}` cannot derive any string of terminals")
+            }
+            ValidationError::LeftRecursion { rule, kind } => {
+                let kind = match kind {
+                    LeftRecursion::Direct => "directly",
+                    LeftRecursion::Indirect => "indirectly",
+                };
+                write!(f, "rule `{rule}` is {kind} left recursive")
+            }
+            ValidationError::EpsilonCycle { rules } if rules.len() == 1 => {
+                write!(
+                    f,
+                    "rule `{}` derives itself through empty strings",
+                    rules[0]
+                )
+            }
+            ValidationError::EpsilonCycle { rules } => {
+                let rules = rules
+                    .iter()
+                    .map(|rule| format!("`{rule}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(f, "rules {rules} derive themselves through empty strings")
+            }
+            ValidationError::Livelock { rules } if rules.len() == 1 => {
+                write!(
+                    f,
+                    "rule `{}` only expands into itself and never reaches a terminal",
+                    rules[0]
+                )
+            }
+            ValidationError::Livelock { rules } => {
+                let rules = rules
+                    .iter()
+                    .map(|rule| format!("`{rule}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                write!(
+                    f,
+                    "rules {rules} only expand into each other and never reach a terminal"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationWarning<'src> {
+    UnreachableRule {
+        rule: &'src str,
+    },
+    /// `detail` describes the conflict as [`Grammar::describe_conflict`] does
+    Ll1Conflict {
+        conflict: Ll1Conflict<'src>,
+        detail: String,
+    },
+}
+
+impl<'src> ValidationWarning<'src> {
+    pub fn rule(&self) -> &'src str {
+        match self {
+            ValidationWarning::UnreachableRule { rule } => rule,
+            ValidationWarning::Ll1Conflict { conflict, .. } => conflict.rule,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationWarning<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationWarning::UnreachableRule { rule } => {
+                write!(f, "rule `{rule}` is unreachable from the start rule")
+            }
+            ValidationWarning::Ll1Conflict { detail, .. } => write!(f, "{detail}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InlineError {
+    /// The rule references itself, inlining it would never end
+    Recursive,
+    UnknownRule,
+}
+
+impl std::fmt::Display for InlineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InlineError::Recursive => write!(f, "cannot inline a rule that references itself"),
+            InlineError::UnknownRule => write!(f, "rule not found"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeError<'src> {
+    DuplicateRule { name: &'src str },
+}
+
+impl std::fmt::Display for MergeError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeError::DuplicateRule { name } => write!(f, "rule `{name}` is defined twice"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateRuleError<'src> {
+    pub name: &'src str,
+}
+
+impl std::fmt::Display for DuplicateRuleError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rule `{}` is already defined", self.name)
+    }
+}
+
+/// Part of the syntax tree that could not be turned into a rule
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuildError<'src> {
+    UnexpectedChild {
+        kind: Kind,
+    },
+    UnexpectedToken {
+        kind: token::Kind,
+    },
+    /// `?`, `*` or `+` without the repeated expression
+    EmptyChild {
+        kind: Kind,
+    },
+    DuplicateRule {
+        name: &'src str,
+    },
+    /// The rule has syntax errors and was left out
+    SyntaxError {
+        name: &'src str,
+    },
+}
+
+impl std::fmt::Display for BuildError<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::UnexpectedChild { kind } => write!(f, "unexpected {kind:?}"),
+            BuildError::UnexpectedToken { kind } => write!(f, "unexpected token {kind}"),
+            BuildError::EmptyChild { kind } => write!(f, "empty {kind:?}"),
+            BuildError::DuplicateRule { name } => write!(f, "rule `{name}` is defined twice"),
+            BuildError::SyntaxError { name } => {
+                write!(f, "rule `{name}` skipped because of syntax errors")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct UnknownRuleError;
+
+impl std::fmt::Display for UnknownRuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rule not found")
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameError {
+    UnknownRule,
+    /// The new name is taken by another rule
+    AlreadyExists,
+}
+
+impl std::fmt::Display for RenameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenameError::UnknownRule => write!(f, "rule not found"),
+            RenameError::AlreadyExists => write!(f, "a rule with the new name already exists"),
+        }
+    }
 }
 
 impl<'src> Grammar<'src> {
+    pub fn new(rules: IndexMap<&'src str, Expr<'src>>) -> Self {
+        Self {
+            rules,
+            allowed_left_recursion: IndexSet::new(),
+            cache: RefCell::default(),
+        }
+    }
+
+    /// Forgets the computed FIRST and FOLLOW sets, needed after changing `rules` directly
+    pub fn clear_cache(&mut self) {
+        *self.cache.get_mut() = SetCache::default();
+    }
+
+    /// Runs every check without panicking, the first rule is taken as the start rule.
+    ///
+    /// LL(1) conflicts are only computed when every referenced rule is defined and there is no
+    /// left recursion, FIRST sets do not terminate otherwise
+    pub fn validate(&self) -> ValidationReport<'src> {
+        let mut report = ValidationReport::default();
+
+        let undefined = self.undefined_rules();
+        report.errors.extend(
+            undefined
+                .iter()
+                .map(|&rule| ValidationError::UndefinedRule { rule }),
+        );
+        report.errors.extend(
+            self.unproductive_rules()
+                .into_iter()
+                .map(|rule| ValidationError::UnproductiveRule { rule }),
+        );
+        report.errors.extend(
+            self.livelock_check()
+                .map(|rules| ValidationError::Livelock { rules }),
+        );
+        let left_recursive = self.left_recursive_rules();
+        report.errors.extend(
+            left_recursive
+                .iter()
+                .filter(|(rule, _)| !self.allowed_left_recursion.contains(*rule))
+                .map(|(&rule, &kind)| ValidationError::LeftRecursion { rule, kind }),
+        );
+
+        report.errors.extend(
+            self.epsilon_cycles()
+                .into_iter()
+                .map(|rules| ValidationError::EpsilonCycle { rules }),
+        );
+
+        if let Some(start) = self.start_symbol() {
+            report.warnings.extend(
+                self.unreachable_rules(start)
+                    .into_iter()
+                    .map(|rule| ValidationWarning::UnreachableRule { rule }),
+            );
+        }
+        if undefined.is_empty() && left_recursive.is_empty() {
+            report
+                .warnings
+                .extend(self.ll1_conflicts().into_iter().map(|conflict| {
+                    ValidationWarning::Ll1Conflict {
+                        detail: self.describe_conflict(&conflict),
+                        conflict,
+                    }
+                }));
+        }
+
+        report
+    }
+
     pub fn follow_set_impl(
-        &'src self,
+        &self,
         of: &str,
         parent: &'src str,
-        expr: &'src Expr,
+        expr: &Expr<'src>,
         productions: &mut IndexSet<&'src str>,
         // Whether self repetitions add FIRST(self) to FOLLOW(self)
         strict: bool,
@@ -43,12 +485,15 @@ impl<'src> Grammar<'src> {
                     let next = iter.clone().next();
 
                     // Computes the first set of self in cases where repetition of self is possible
-                    // i.e `Fn*` may produce `Fn Fn`, hence FIRST(Fn) must be added to FOLLOW(Fn)
-                    if let Expr::Repeat(rep) = expr
+                    // i.e `Fn*` or `Fn+` may produce `Fn Fn`, hence FIRST(Fn) must be added to
+                    // FOLLOW(Fn)
+                    if let Expr::Repeat(rep) | Expr::OneOrMore(rep) = expr
                         && rep.produces_at_end(&Expr::Rule(of))
                         && !strict
                     {
-                        set.extend(self.first_set_impl(rep, &mut IndexSet::new()));
+                        let mut first = self.first_set_impl(rep, &mut IndexSet::new());
+                        first.swap_remove("ε");
+                        set.extend(first);
                     }
 
                     // - We compute the FIRST set of the following expression β
@@ -88,170 +533,1219 @@ impl<'src> Grammar<'src> {
         set
     }
 
-    pub fn first_set(&'src self, name: &'src str) -> IndexSet<&'src str> {
+    /// FIRST sets of every rule without ε, computed together as a fixed point
+    fn first_terminal_sets(
+        &self,
+        nullable: &IndexSet<&str>,
+    ) -> IndexMap<&'src str, IndexSet<&'src str>> {
+        let mut first = self
+            .rules
+            .keys()
+            .map(|name| (*name, IndexSet::new()))
+            .collect::<IndexMap<_, _>>();
+        loop {
+            let mut changed = false;
+            for (name, expr) in &self.rules {
+                let set = expr.first_terminals(&first, nullable);
+                let rule = &mut first[name];
+                if set.len() > rule.len() {
+                    *rule = set;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        first
+    }
+
+    pub fn first_set(&self, name: &'src str) -> IndexSet<&'src str> {
+        if let Some(set) = self.cache.borrow().first.get(name) {
+            return set.clone();
+        }
         let expr = self
             .rules
             .get(name)
             .expect(&format!("rule not found {name:?}"));
-        self.first_set_impl(expr, &mut IndexSet::from([name]))
+        let set = self.first_set_impl(expr, &mut IndexSet::from([name]));
+        self.cache.borrow_mut().first.insert(name, set.clone());
+        set
     }
 
-    pub fn non_terminals(&self) -> IndexSet<&str> {
-        self.rules.keys().copied().collect()
+    /// FOLLOW sets of every rule computed together as a fixed point, the start symbol is
+    /// followed by the end of the input [`END_OF_INPUT`]. Unlike [`Grammar::follow_set`] the
+    /// sets of every rule come out of a single computation
+    pub fn compute_follow_sets(&self) -> IndexMap<&'src str, IndexSet<&'src str>> {
+        self.full_follow_sets(self.start_symbol().unwrap_or_default())
     }
 
-    pub fn first_set_impl(
-        &'src self,
-        expr: &'src Expr,
-        productions: &mut IndexSet<&'src str>,
-    ) -> IndexSet<&'src str> {
-        let mut set: IndexSet<&str> = IndexSet::new();
+    /// Like [`Grammar::compute_follow_sets`] with `start` as the start symbol, so FOLLOW(start)
+    /// holds [`END_OF_INPUT`]. No rule is followed by it when `start` is not defined
+    pub fn full_follow_sets(&self, start: &str) -> IndexMap<&'src str, IndexSet<&'src str>> {
+        let nullable = self.nullable_set();
+        let first = self.first_terminal_sets(&nullable);
 
-        match expr {
-            Expr::Literal(lit) => {
-                set.insert(lit);
-            }
-            Expr::Rule(rule) => {
-                if !productions.insert(rule) {
-                    return set;
-                }
-                let expr = self
-                    .rules
-                    .get(rule)
-                    .expect(&format!("rule not found {rule:?}"));
-                set.extend(self.first_set_impl(expr, productions));
+        let mut follow = self
+            .rules
+            .keys()
+            .map(|name| (*name, IndexSet::new()))
+            .collect::<IndexMap<_, _>>();
+        if let Some(set) = follow.get_mut(start) {
+            set.insert(END_OF_INPUT);
+        }
+        loop {
+            let before = follow.values().map(IndexSet::len).sum::<usize>();
+            for (name, expr) in &self.rules {
+                let after = follow[name].clone();
+                expr.add_follow(&after, &first, &nullable, &mut follow);
             }
-            Expr::Sequence(exprs) => {
-                let mut iter = exprs.iter();
-                loop {
-                    let Some(curr) = iter.next() else {
-                        set.insert("ε"); // May have nothing as first
-                        break;
-                    };
-
-                    match curr {
-                        Expr::Optional(expr) | Expr::Repeat(expr) => {
-                            set.extend(self.first_set_impl(expr, productions));
-                        }
-                        Expr::Rule(rule)
-                            if productions.iter().all(|r| {
-                                self.rules
-                                    .get(rule)
-                                    .unwrap()
-                                    .is_alias(&Expr::Rule(r), &self.rules)
-                            }) && curr.may_miss(&self.rules) =>
-                        {
-                            set.extend(self.first_set_impl(curr, productions));
-                        }
-                        _ => {
-                            set.extend(self.first_set_impl(curr, productions));
-                            break;
-                        }
-                    }
-                }
+            if follow.values().map(IndexSet::len).sum::<usize>() == before {
+                break;
             }
-            Expr::Choice(exprs) => exprs.into_iter().for_each(|expr| {
-                set.extend(self.first_set_impl(expr, productions));
-            }),
-            Expr::Optional(expr) => return self.first_set_impl(expr, productions),
-            Expr::Repeat(expr) => return self.first_set_impl(expr, productions),
         }
 
-        set
+        follow
     }
-}
 
-impl<'src> std::fmt::Debug for Grammar<'src> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if f.alternate() {
-            writeln!(f, "Grammar {{")?;
-            for (name, expr) in &self.rules {
-                writeln!(f, "  {}: {:?}", name, expr)?;
-            }
-            writeln!(f, "}}")
-        } else {
-            write!(f, "Grammar")
-        }
+    /// FOLLOW set of `name`, repetitions like `Fn*` add FIRST(Fn) to FOLLOW(Fn)
+    pub fn follow_set(&self, name: &str) -> IndexSet<&'src str> {
+        self.follow_set_with(name, false)
     }
-}
 
-pub struct GrammarBuilder<'src> {
-    source: &'src str,
-    tree: Tree,
-}
+    /// FOLLOW set of `name` without FIRST(self) for repetitions of self
+    pub fn follow_set_strict(&self, name: &str) -> IndexSet<&'src str> {
+        self.follow_set_with(name, true)
+    }
 
-impl<'src> GrammarBuilder<'src> {
-    pub fn new(source: &'src str, tree: Tree) -> Self {
-        Self { source, tree }
+    /// FIRST+(A), the terminals that select `name` when deriving it: FIRST(A) plus FOLLOW(A)
+    /// when `name` is nullable, as given by [`Grammar::compute_follow_sets`]
+    pub fn first_plus(&self, name: &str) -> IndexSet<&'src str> {
+        let nullable = self.nullable_set();
+        let mut set = self
+            .first_terminal_sets(&nullable)
+            .swap_remove(name)
+            .unwrap_or_else(|| panic!("rule not found {name:?}"));
+        if nullable.contains(name) {
+            set.extend(&self.compute_follow_sets()[name]);
+        }
+        set
     }
 
-    pub fn build(self) -> Grammar<'src> {
-        let mut rules = IndexMap::new();
-        for child in &self.tree.children {
-            let Child::Tree(Tree {
-                kind: Kind::Rule,
-                children,
-            }) = child
-            else {
-                panic!("expected rule found {:?}", child);
-            };
+    /// PREDICT(A → α) for the whole body of `name`, FIRST(α) plus FOLLOW(A) when α may be empty
+    pub fn predict_set(&self, name: &str) -> IndexSet<&'src str> {
+        let (name, expr) = self
+            .rules
+            .get_key_value(name)
+            .unwrap_or_else(|| panic!("rule not found {name:?}"));
+        self.predict_set_impl(name, expr)
+    }
 
-            let name = match &children[0] {
-                Child::Token(token) => match token.kind {
-                    token::Kind::Ident => &self.source[token.span.range()],
-                    _ => panic!("expected ident"),
-                },
-                _ => panic!("expected token"),
-            };
-            let expr = self.parse_expr(&children[1]);
-            rules.insert(name, expr);
-        }
-        Grammar { rules }
+    /// PREDICT set of a single alternative of `name`, a rule without choices has only branch 0
+    pub fn predict_set_branch(&self, name: &str, branch_index: usize) -> IndexSet<&'src str> {
+        let (name, expr) = self
+            .rules
+            .get_key_value(name)
+            .unwrap_or_else(|| panic!("rule not found {name:?}"));
+        let branch = expr
+            .branches()
+            .get(branch_index)
+            .unwrap_or_else(|| panic!("branch {branch_index} not found in {name:?}"));
+        self.predict_set_impl(name, branch)
     }
 
-    fn parse_expr(&self, child: &Child) -> Expr<'src> {
-        match child {
-            Child::Token(token) => match token.kind {
-                token::Kind::Literal => {
-                    Expr::Literal(&self.source[token.span.start + 1..token.span.end - 1])
-                }
-                token::Kind::Ident => Expr::Rule(&self.source[token.span.range()]),
-                _ => panic!("unexpected token kind"),
-            },
-            Child::Tree(tree) => match tree.kind {
-                Kind::Sequence => {
-                    let mut exprs = Vec::new();
-                    for child in &tree.children {
-                        exprs.push(self.parse_expr(child));
-                    }
-                    Expr::Sequence(exprs)
-                }
-                Kind::Branch => {
-                    if tree.children.len() == 1 {
-                        return self.parse_expr(&tree.children[0]);
-                    }
+    fn predict_set_impl(&self, name: &'src str, expr: &Expr<'src>) -> IndexSet<&'src str> {
+        let mut set = self.first_set_impl(expr, &mut IndexSet::from([name]));
+        if set.swap_remove("ε") {
+            set.extend(self.follow_set(name));
+        }
+        set
+    }
 
-                    let mut exprs = Vec::new();
-                    for child in &tree.children {
-                        exprs.push(self.parse_expr(child));
-                    }
-                    Expr::Choice(exprs)
-                }
-                Kind::Optional => {
-                    let child = &tree.children[0];
-                    Expr::Optional(Box::new(self.parse_expr(child)))
-                }
-                Kind::ZeroOrMore => {
-                    let child = &tree.children[0];
-                    Expr::Repeat(Box::new(self.parse_expr(child)))
-                }
-                _ => panic!("unexpected tree kind"),
-            },
+    /// Identifiers referenced by some rule body but never defined
+    pub fn undefined_rules(&self) -> IndexSet<&'src str> {
+        let mut references = IndexSet::new();
+        for expr in self.rules.values() {
+            expr.rule_references(&mut references);
         }
+        references.retain(|name| !self.rules.contains_key(name));
+        references
     }
-}
 
-#[derive(Debug, PartialEq, Eq)]
+    /// Rules referenced by the body of each rule, undefined ones included, in order of
+    /// appearance
+    pub fn rule_dependencies(&self) -> IndexMap<&'src str, IndexSet<&'src str>> {
+        self.rules
+            .iter()
+            .map(|(name, expr)| (*name, expr.non_terminals().collect()))
+            .collect()
+    }
+
+    /// [`Grammar::rule_dependencies`] as `(rule, referenced)` edges
+    pub fn rule_dependency_graph(&self) -> Vec<(&'src str, &'src str)> {
+        self.rule_dependencies()
+            .into_iter()
+            .flat_map(|(name, references)| references.into_iter().map(move |rule| (name, rule)))
+            .collect()
+    }
+
+    /// Rules reachable from `start` ordered so every rule comes after the rules it references,
+    /// found with Kahn's algorithm. Otherwise the rules on a cycle of references, recursion
+    /// included, in declaration order
+    pub fn topo_sort(&self, start: &str) -> Result<Vec<&'src str>, Vec<&'src str>> {
+        let reachable = self.reachable_rules(start);
+        let mut dependencies = self.rule_dependencies();
+        dependencies.retain(|name, _| reachable.contains(name));
+        for references in dependencies.values_mut() {
+            references.retain(|rule| reachable.contains(rule));
+        }
+
+        let mut dependents = IndexMap::<&str, Vec<&str>>::new();
+        for (name, references) in &dependencies {
+            for rule in references {
+                dependents.entry(rule).or_default().push(name);
+            }
+        }
+        let mut pending = dependencies
+            .iter()
+            .map(|(name, references)| (*name, references.len()))
+            .collect::<IndexMap<_, _>>();
+        let mut queue = pending
+            .iter()
+            .filter_map(|(name, count)| (*count == 0).then_some(*name))
+            .collect::<VecDeque<_>>();
+
+        let mut order = Vec::new();
+        while let Some(name) = queue.pop_front() {
+            order.push(name);
+            for dependent in dependents.get(name).into_iter().flatten() {
+                let count = &mut pending[dependent];
+                *count -= 1;
+                if *count == 0 {
+                    queue.push_back(dependent);
+                }
+            }
+        }
+
+        if order.len() == dependencies.len() {
+            return Ok(order);
+        }
+        // Rules left behind are on a cycle or depend on one
+        dependencies.retain(|name, _| pending[name] > 0);
+        Err(self.cycles(&dependencies).into_iter().flatten().collect())
+    }
+
+    /// Rules that cannot be reached from `start` through rule references. Callers without a
+    /// specific start, like [`Grammar::validate`], pass [`Grammar::start_symbol`] so the order
+    /// of the rules matters
+    pub fn unreachable_rules(&self, start: &str) -> IndexSet<&'src str> {
+        let reachable = self.reachable_rules(start);
+        self.rules
+            .keys()
+            .copied()
+            .filter(|name| !reachable.contains(name))
+            .collect()
+    }
+
+    /// Defined rules reachable from `start` through rule references, `start` included, in
+    /// declaration order. Empty when `start` is not defined
+    pub fn reachable_rules(&self, start: &str) -> IndexSet<&'src str> {
+        let mut reachable = IndexSet::new();
+        let mut stack = Vec::new();
+        if let Some((name, _)) = self.rules.get_key_value(start) {
+            reachable.insert(*name);
+            stack.push(*name);
+        }
+
+        while let Some(name) = stack.pop() {
+            let Some(expr) = self.rules.get(name) else {
+                continue;
+            };
+
+            let mut references = IndexSet::new();
+            expr.rule_references(&mut references);
+            for rule in references {
+                if reachable.insert(rule) {
+                    stack.push(rule);
+                }
+            }
+        }
+
+        self.rules
+            .keys()
+            .copied()
+            .filter(|name| reachable.contains(name))
+            .collect()
+    }
+
+    /// Literals of the rules reachable from `start`, in order of appearance
+    pub fn reachable_terminals(&self, start: &str) -> IndexSet<&'src str> {
+        self.reachable_rules(start)
+            .into_iter()
+            .flat_map(|name| self.rules[name].terminals())
+            .collect()
+    }
+
+    /// Rules that cannot derive any string of terminals, computed as the complement of the
+    /// productive fixed point. References to undefined rules are never productive
+    pub fn unproductive_rules(&self) -> IndexSet<&'src str> {
+        let mut productive = IndexSet::new();
+        loop {
+            let before = productive.len();
+            for (name, expr) in self.rules.iter() {
+                if !productive.contains(name) && expr.is_productive(&productive) {
+                    productive.insert(*name);
+                }
+            }
+
+            if productive.len() == before {
+                break;
+            }
+        }
+
+        self.rules
+            .keys()
+            .copied()
+            .filter(|name| !productive.contains(name))
+            .collect()
+    }
+
+    /// Rules `A` such that `A ⇒* Aα`, nullable prefixes are taken into account
+    pub fn left_recursive_rules(&self) -> IndexMap<&'src str, LeftRecursion> {
+        let nullable = self.nullable_set();
+        let corners = self
+            .rules
+            .iter()
+            .map(|(name, expr)| {
+                let mut set = IndexSet::new();
+                expr.left_corners(&nullable, &mut set);
+                (*name, set)
+            })
+            .collect::<IndexMap<_, _>>();
+
+        let mut recursive = IndexMap::new();
+        for (name, direct) in corners.iter() {
+            if direct.contains(name) {
+                recursive.insert(*name, LeftRecursion::Direct);
+                continue;
+            }
+
+            let mut visited = direct.clone();
+            let mut stack = direct.iter().copied().collect::<Vec<_>>();
+            while let Some(rule) = stack.pop() {
+                if rule == *name {
+                    recursive.insert(*name, LeftRecursion::Indirect);
+                    break;
+                }
+                for next in corners.get(rule).into_iter().flatten() {
+                    if visited.insert(next) {
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+
+        recursive
+    }
+
+    /// Whether some rule derives itself with every other symbol deriving ε, `A ⇒+ A`
+    pub fn has_epsilon_cycles(&self) -> bool {
+        !self.epsilon_cycles().is_empty()
+    }
+
+    /// Groups of rules deriving each other with every other symbol deriving ε, i.e `A = B 'x'?`
+    /// and `B = A | 'y'`. Each group is a strongly connected component of those derivations
+    /// with its rules in declaration order
+    pub fn epsilon_cycles(&self) -> Vec<Vec<&'src str>> {
+        let nullable = self.nullable_set();
+        let edges = self
+            .rules
+            .iter()
+            .map(|(name, expr)| {
+                let mut set = IndexSet::new();
+                expr.epsilon_references(&nullable, &mut set);
+                set.retain(|rule| self.rules.contains_key(rule));
+                (*name, set)
+            })
+            .collect::<IndexMap<_, _>>();
+        self.cycles(&edges)
+    }
+
+    /// Rules expanding only into each other, so none of them ever reaches a terminal string,
+    /// i.e `A = B` and `B = A 'a'`. The first group of such rules in declaration order, a
+    /// stronger form of [`Grammar::unproductive_rules`]
+    pub fn livelock_check(&self) -> Option<Vec<&'src str>> {
+        let unproductive = self.unproductive_rules();
+        // An unproductive rule depends on every unproductive rule it references, its other
+        // references are productive and cannot be what blocks it
+        let edges = unproductive
+            .iter()
+            .map(|name| {
+                let set = self.rules[name]
+                    .non_terminals()
+                    .filter(|rule| unproductive.contains(rule))
+                    .collect();
+                (*name, set)
+            })
+            .collect::<IndexMap<_, _>>();
+        self.cycles(&edges).into_iter().next()
+    }
+
+    /// Strongly connected components of `edges` that contain a cycle, each one sorted in
+    /// declaration order
+    fn cycles(&self, edges: &IndexMap<&'src str, IndexSet<&'src str>>) -> Vec<Vec<&'src str>> {
+        let mut tarjan = Tarjan {
+            edges,
+            index: HashMap::new(),
+            low_link: HashMap::new(),
+            stack: IndexSet::new(),
+            components: Vec::new(),
+        };
+        for name in edges.keys() {
+            if !tarjan.index.contains_key(name) {
+                tarjan.visit(name);
+            }
+        }
+
+        let mut cycles = tarjan
+            .components
+            .into_iter()
+            .filter(|component| match component[..] {
+                [rule] => edges[rule].contains(rule),
+                _ => true,
+            })
+            .map(|mut component| {
+                component.sort_by_key(|rule| self.rules.get_index_of(rule));
+                component
+            })
+            .collect::<Vec<_>>();
+        cycles.sort_by_key(|component| self.rules.get_index_of(component[0]));
+        cycles
+    }
+
+    /// Whether every rule is right linear or every rule is left linear, which makes the
+    /// language regular. Repetitions and optionals only made of literals count as literals.
+    ///
+    /// Grammars mixing both may still generate a regular language but are reported as not
+    /// regular, grammars without rule references are right linear
+    pub fn is_regular(&self) -> RegularityResult {
+        let right = |expr: &Expr| expr.is_linear(Linearity::Right);
+        let left = |expr: &Expr| expr.is_linear(Linearity::Left);
+
+        if self.rules.values().all(right) {
+            return RegularityResult::Regular(Linearity::Right);
+        }
+        if self.rules.values().all(left) {
+            return RegularityResult::Regular(Linearity::Left);
+        }
+
+        let (name, _) = self
+            .rules
+            .iter()
+            .find(|(_, expr)| !right(expr) && !left(expr))
+            .or_else(|| self.rules.iter().find(|(_, expr)| !right(expr)))
+            .unwrap();
+        RegularityResult::NotRegular {
+            counterexample_rule: name.to_string(),
+        }
+    }
+
+    /// Every choice, nested ones included, whose alternatives share a token in their PREDICT sets
+    pub fn ll1_conflicts(&self) -> Vec<Ll1Conflict<'src>> {
+        let nullable = self.nullable_set();
+        let mut conflicts = Vec::new();
+        for name in self.rules.keys() {
+            let _ = self.rule_ll1_conflicts(name, &nullable, &mut |conflict| {
+                conflicts.push(conflict);
+                ControlFlow::Continue(())
+            });
+        }
+        conflicts
+    }
+
+    /// A sentence describing each of [`Grammar::ll1_conflicts`]
+    pub fn conflict_detail(&self) -> Vec<String> {
+        self.ll1_conflicts()
+            .iter()
+            .map(|conflict| self.describe_conflict(conflict))
+            .collect()
+    }
+
+    /// i.e "In rule 'S', alternatives 0 and 1 both predict token 'a': branch 0 starts with 'a'
+    /// 'b' and branch 1 starts with 'a' 'c'." Branches are shown up to their third element
+    pub fn describe_conflict(&self, conflict: &Ll1Conflict) -> String {
+        let mut choice = &self.rules[conflict.rule];
+        for &i in &conflict.path {
+            choice = match choice {
+                Expr::Sequence(exprs) | Expr::Choice(exprs) => &exprs[i],
+                Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => x,
+                Expr::Literal(_) | Expr::Rule(_) => unreachable!("paths lead to a choice"),
+            };
+        }
+        let branches = choice.branches();
+
+        let place = match conflict.path.is_empty() {
+            true => format!("In rule '{}'", conflict.rule),
+            false => format!("In a nested choice of rule '{}'", conflict.rule),
+        };
+        let alternatives = join_and(conflict.branches.iter().map(|i| i.to_string()));
+        let all = match conflict.branches.len() {
+            2 => "both",
+            _ => "all",
+        };
+        let token = match conflict.tokens.len() {
+            1 => "token",
+            _ => "tokens",
+        };
+        let tokens = conflict
+            .tokens
+            .iter()
+            .map(|token| format!("'{token}'"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let starts = join_and(conflict.branches.iter().map(|&i| {
+            let prefix = match &branches[i] {
+                Expr::Sequence(exprs) => Expr::Sequence(exprs.iter().take(3).cloned().collect()),
+                branch => branch.clone(),
+            };
+            format!("branch {i} starts with {prefix}")
+        }));
+        format!("{place}, alternatives {alternatives} {all} predict {token} {tokens}: {starts}.")
+    }
+
+    /// Whether no rule has an LL(1) conflict, stops at the first one found. Left recursive
+    /// grammars are never LL(1)
+    pub fn is_ll1(&self) -> bool {
+        if !self.left_recursive_rules().is_empty() {
+            return false;
+        }
+        let nullable = self.nullable_set();
+        self.rules.keys().all(|name| {
+            self.rule_ll1_conflicts(name, &nullable, &mut |_| ControlFlow::Break(()))
+                .is_continue()
+        })
+    }
+
+    /// Whether the choices of `name` have no LL(1) conflict, stops at the first one found
+    pub fn is_ll1_for_rule(&self, name: &str) -> bool {
+        let Some((name, _)) = self.rules.get_key_value(name) else {
+            panic!("rule not found {name:?}");
+        };
+        if self.left_recursive_rules().contains_key(name) {
+            return false;
+        }
+        self.rule_ll1_conflicts(name, &self.nullable_set(), &mut |_| ControlFlow::Break(()))
+            .is_continue()
+    }
+
+    /// Passes every conflict of the rule to `on_conflict` until it breaks
+    fn rule_ll1_conflicts(
+        &self,
+        name: &'src str,
+        nullable: &IndexSet<&'src str>,
+        on_conflict: &mut dyn FnMut(Ll1Conflict<'src>) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        let follow = self.follow_set(name);
+        self.ll1_conflicts_impl(
+            name,
+            &self.rules[name],
+            &follow,
+            nullable,
+            &mut Vec::new(),
+            on_conflict,
+        )
+    }
+
+    fn ll1_conflicts_impl(
+        &self,
+        name: &'src str,
+        expr: &Expr<'src>,
+        // Tokens that may appear right after `expr`
+        follow: &IndexSet<&'src str>,
+        nullable: &IndexSet<&'src str>,
+        path: &mut Vec<usize>,
+        on_conflict: &mut dyn FnMut(Ll1Conflict<'src>) -> ControlFlow<()>,
+    ) -> ControlFlow<()> {
+        match expr {
+            Expr::Literal(_) | Expr::Rule(_) => {}
+            Expr::Sequence(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    let rest = &exprs[i + 1..];
+                    let mut rest_follow = self.sequence_first(name, rest, nullable);
+                    if rest.iter().all(|x| x.derives_empty(nullable)) {
+                        rest_follow.extend(follow);
+                    }
+
+                    path.push(i);
+                    self.ll1_conflicts_impl(name, expr, &rest_follow, nullable, path, on_conflict)?;
+                    path.pop();
+                }
+            }
+            Expr::Choice(branches) => {
+                let predicts = branches
+                    .iter()
+                    .map(|branch| {
+                        let mut set =
+                            self.sequence_first(name, std::slice::from_ref(branch), nullable);
+                        if branch.derives_empty(nullable) {
+                            set.extend(follow);
+                        }
+                        set
+                    })
+                    .collect::<Vec<_>>();
+
+                // Group the overlapping tokens by the set of branches predicting them
+                let mut overlaps: IndexMap<Vec<usize>, IndexSet<&str>> = IndexMap::new();
+                for token in predicts.iter().flatten() {
+                    let predicted_by = predicts
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, set)| set.contains(token).then_some(i))
+                        .collect::<Vec<_>>();
+                    if predicted_by.len() > 1 {
+                        overlaps.entry(predicted_by).or_default().insert(token);
+                    }
+                }
+
+                for (branches, tokens) in overlaps {
+                    on_conflict(Ll1Conflict {
+                        rule: name,
+                        path: path.clone(),
+                        branches,
+                        tokens,
+                    })?;
+                }
+
+                for (i, branch) in branches.iter().enumerate() {
+                    path.push(i);
+                    self.ll1_conflicts_impl(name, branch, follow, nullable, path, on_conflict)?;
+                    path.pop();
+                }
+            }
+            Expr::Optional(expr) => {
+                path.push(0);
+                self.ll1_conflicts_impl(name, expr, follow, nullable, path, on_conflict)?;
+                path.pop();
+            }
+            Expr::Repeat(expr) | Expr::OneOrMore(expr) => {
+                // The repeated expression may be followed by itself
+                let mut repeat_follow =
+                    self.sequence_first(name, std::slice::from_ref(expr), nullable);
+                repeat_follow.extend(follow);
+
+                path.push(0);
+                self.ll1_conflicts_impl(name, expr, &repeat_follow, nullable, path, on_conflict)?;
+                path.pop();
+            }
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// FIRST set of the sequence `exprs` as seen from rule `name`, without ε
+    fn sequence_first(
+        &self,
+        name: &'src str,
+        exprs: &[Expr<'src>],
+        nullable: &IndexSet<&'src str>,
+    ) -> IndexSet<&'src str> {
+        let mut set = IndexSet::new();
+        for expr in exprs {
+            set.extend(self.first_set_impl(expr, &mut IndexSet::from([name])));
+            if !expr.derives_empty(nullable) {
+                break;
+            }
+        }
+        set.swap_remove("ε");
+        set
+    }
+
+    fn follow_set_with(&self, of: &str, strict: bool) -> IndexSet<&'src str> {
+        // Only defined rules are cached, their names live as long as the grammar
+        let key = self
+            .rules
+            .get_key_value(of)
+            .map(|(name, _)| (*name, strict));
+        if let Some(key) = key
+            && let Some(set) = self.cache.borrow().follow.get(&key)
+        {
+            return set.clone();
+        }
+
+        let mut set = IndexSet::new();
+        for (name, rule) in self.rules.iter() {
+            set.extend(self.follow_set_impl(of, name, rule, &mut IndexSet::from([*name]), strict));
+        }
+        if let Some(key) = key {
+            self.cache.borrow_mut().follow.insert(key, set.clone());
+        }
+        set
+    }
+
+    pub fn non_terminals(&self) -> IndexSet<&str> {
+        self.rules.keys().copied().collect()
+    }
+
+    /// Non-terminals that may derive ε, computed as a fixed point so indirect
+    /// nullability (`A = B C` with both `B` and `C` nullable) is also found
+    pub fn nullable_set(&self) -> IndexSet<&'src str> {
+        nullable_rules(&self.rules)
+    }
+
+    pub fn first_set_impl(
+        &self,
+        expr: &Expr<'src>,
+        productions: &mut IndexSet<&'src str>,
+    ) -> IndexSet<&'src str> {
+        let mut set: IndexSet<&str> = IndexSet::new();
+
+        match expr {
+            Expr::Literal(lit) => {
+                set.insert(lit);
+            }
+            Expr::Rule(rule) => {
+                if !productions.insert(rule) {
+                    return set;
+                }
+                let expr = self
+                    .rules
+                    .get(rule)
+                    .expect(&format!("rule not found {rule:?}"));
+                set.extend(self.first_set_impl(expr, productions));
+            }
+            Expr::Sequence(exprs) => {
+                let mut iter = exprs.iter();
+                loop {
+                    let Some(curr) = iter.next() else {
+                        set.insert("ε"); // May have nothing as first
+                        break;
+                    };
+
+                    match curr {
+                        Expr::Optional(expr) | Expr::Repeat(expr) => {
+                            set.extend(self.first_set_impl(expr, productions));
+                        }
+                        // Unlike `*` at least one repetition is required, the next expression
+                        // only contributes when the repeated one may be empty
+                        Expr::OneOrMore(expr) => {
+                            let mut first = self.first_set_impl(expr, productions);
+                            let may_be_empty = first.swap_remove("ε");
+                            set.extend(first);
+                            if !may_be_empty {
+                                break;
+                            }
+                        }
+                        Expr::Rule(rule)
+                            if productions.iter().all(|r| {
+                                self.rules
+                                    .get(rule)
+                                    .unwrap()
+                                    .is_alias(&Expr::Rule(r), &self.rules)
+                            }) && curr.may_miss(&self.rules) =>
+                        {
+                            set.extend(self.first_set_impl(curr, productions));
+                        }
+                        _ => {
+                            set.extend(self.first_set_impl(curr, productions));
+                            break;
+                        }
+                    }
+                }
+            }
+            Expr::Choice(exprs) => exprs.into_iter().for_each(|expr| {
+                set.extend(self.first_set_impl(expr, productions));
+            }),
+            Expr::Optional(expr) => return self.first_set_impl(expr, productions),
+            Expr::Repeat(expr) => return self.first_set_impl(expr, productions),
+            Expr::OneOrMore(expr) => return self.first_set_impl(expr, productions),
+        }
+
+        set
+    }
+
+    /// Serializes every rule as `<Name> ::= ...`, optionals become `( X | ε )` and repetitions
+    /// `( X )*` or `( X )+`
+    pub fn to_bnf_string(&self) -> String {
+        let mut out = String::new();
+        for (name, expr) in &self.rules {
+            out.push_str(&format!("<{name}> ::= "));
+            expr.write_bnf(&mut out, false);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Serializes every rule back into ungram notation, parsing the output yields the same rules
+    pub fn to_ebnf_string(&self) -> String {
+        let mut out = String::new();
+        for (name, expr) in &self.rules {
+            out.push_str(&format!("{name} = {expr}\n"));
+        }
+        out
+    }
+
+    /// Graphviz digraph of the grammar, every rule is a node and rule references are edges
+    /// to it. Choices are diamonds, sequences boxes with numbered edges, repetitions and
+    /// optionals ellipses labeled with their modifier and terminals plain text leaves. Rules
+    /// are drawn in navy and terminals in green
+    pub fn to_dot_string(&self) -> String {
+        self.dot_string_impl(self.rules.keys().copied())
+    }
+
+    /// Same as [`Grammar::to_dot_string`] but only with the rules reachable from `start`
+    pub fn to_dot_string_from(&self, start: &str) -> String {
+        let unreachable = self.unreachable_rules(start);
+        self.dot_string_impl(
+            self.rules
+                .keys()
+                .copied()
+                .filter(|name| !unreachable.contains(name)),
+        )
+    }
+
+    fn dot_string_impl(&self, rules: impl Iterator<Item = &'src str>) -> String {
+        let mut out = String::from("digraph grammar {\n");
+        let mut next_id = 0;
+        for name in rules {
+            let rule = dot_escape(name);
+            out.push_str(&format!(
+                "  \"{rule}\" [label=\"{rule}\", shape=box, style=bold, color=navy];\n"
+            ));
+            let child = self.rules[name].write_dot(&mut out, &mut next_id);
+            out.push_str(&format!("  \"{rule}\" -> {child};\n"));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// JSON object mapping every rule name to its expression, i.e `{"type":"Rule","name":"A"}`
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{");
+        for (i, (name, expr)) in self.rules.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push_str(&format!("{}:", json_string(name)));
+            expr.write_json(&mut out);
+        }
+        out.push('}');
+        out
+    }
+
+    /// Rule-for-rule equality ignoring grouping that does not change the structure, i.e `('a')`
+    /// and `'a'`
+    pub fn rules_eq(&self, other: &Grammar) -> bool {
+        self.rules.len() == other.rules.len()
+            && self.rules.iter().all(|(name, expr)| {
+                other
+                    .rules
+                    .get(name)
+                    .is_some_and(|other| expr.structurally_eq(other))
+            })
+    }
+
+    /// The start symbol is by convention the first rule, `None` for an empty grammar
+    pub fn start_symbol(&self) -> Option<&'src str> {
+        self.rules.keys().next().copied()
+    }
+
+    /// The start rule and its body
+    pub fn first_rule(&self) -> Option<(&'src str, &Expr<'src>)> {
+        self.rules.first().map(|(name, expr)| (*name, expr))
+    }
+
+    /// Moves `name` to the front so it becomes the start symbol, the order of the other rules
+    /// is kept
+    pub fn set_start_symbol(&mut self, name: &str) -> Result<(), UnknownRuleError> {
+        let index = self.rules.get_index_of(name).ok_or(UnknownRuleError)?;
+        self.rules.move_index(index, 0);
+        Ok(())
+    }
+
+    /// Sorts the alternatives of every choice by their notation, nested ones included
+    pub fn sort_alternatives(&mut self) {
+        self.clear_cache();
+        for expr in self.rules.values_mut() {
+            expr.sort_alternatives();
+        }
+    }
+
+    /// Appends the rule, failing if a rule with the same name exists
+    pub fn add_rule(
+        &mut self,
+        name: &'src str,
+        expr: Expr<'src>,
+    ) -> Result<(), DuplicateRuleError<'src>> {
+        if self.rules.contains_key(name) {
+            return Err(DuplicateRuleError { name });
+        }
+        self.clear_cache();
+        self.rules.insert(name, expr);
+        Ok(())
+    }
+
+    /// Removes the rule keeping the order of the others, references to it are left as they are
+    pub fn remove_rule(&mut self, name: &str) -> Option<Expr<'src>> {
+        self.clear_cache();
+        self.rules.shift_remove(name)
+    }
+
+    /// Renames the rule in place and updates every reference to it
+    pub fn rename_rule(&mut self, old: &str, new: &'src str) -> Result<(), RenameError> {
+        let index = self
+            .rules
+            .get_index_of(old)
+            .ok_or(RenameError::UnknownRule)?;
+        if old != new && self.rules.contains_key(new) {
+            return Err(RenameError::AlreadyExists);
+        }
+        self.clear_cache();
+
+        let (_, expr) = self.rules.shift_remove_index(index).unwrap();
+        self.rules.shift_insert(index, new, expr);
+        let replacement = Expr::Rule(new);
+        for expr in self.rules.values_mut() {
+            expr.replace_rule(old, &replacement);
+        }
+        Ok(())
+    }
+
+    /// Replaces every reference to `target` with `replacement`, the body of `target` included.
+    /// Unlike [`Grammar::inline_rule`] the rule itself is kept
+    pub fn substitute_rule(&mut self, target: &str, replacement: &Expr<'src>) {
+        self.clear_cache();
+        for expr in self.rules.values_mut() {
+            expr.replace_rule(target, replacement);
+        }
+    }
+
+    /// Rules of both grammars, `self` first. Fails on the first rule defined in both
+    pub fn merge(mut self, other: Grammar<'src>) -> Result<Grammar<'src>, MergeError<'src>> {
+        if let Some(name) = other
+            .rules
+            .keys()
+            .find(|name| self.rules.contains_key(*name))
+        {
+            return Err(MergeError::DuplicateRule { name });
+        }
+        self.clear_cache();
+        self.rules.extend(other.rules);
+        Ok(self)
+    }
+
+    /// Rules of both grammars, `self` first. Rules defined in both take the body from `other`
+    /// and keep their position in `self`
+    pub fn merge_override(mut self, other: Grammar<'src>) -> Grammar<'src> {
+        self.clear_cache();
+        self.rules.extend(other.rules);
+        self
+    }
+
+    /// Copy of the grammar no longer borrowing the source, names and literals are interned
+    /// so converting the same grammar twice does not allocate them again
+    pub fn into_owned(self) -> Grammar<'static> {
+        let mut grammar = Grammar::new(
+            self.rules
+                .iter()
+                .map(|(name, expr)| (intern(name), expr.to_static()))
+                .collect(),
+        );
+        grammar.allowed_left_recursion = self
+            .allowed_left_recursion
+            .iter()
+            .map(|name| intern(name))
+            .collect();
+        grammar
+    }
+}
+
+impl<'src> std::fmt::Debug for Grammar<'src> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Grammar {{")?;
+            for (name, expr) in &self.rules {
+                writeln!(f, "  {}: {:?}", name, expr)?;
+            }
+            writeln!(f, "}}")
+        } else {
+            write!(f, "Grammar")
+        }
+    }
+}
+
+pub struct GrammarBuilder<'src> {
+    source: &'src str,
+    tree: Tree,
+}
+
+impl<'src> GrammarBuilder<'src> {
+    pub fn new(source: &'src str, tree: Tree) -> Self {
+        Self { source, tree }
+    }
+
+    /// Builds a tree holding a single rule body, as parsed by [`Parser::parse_rule_body`]
+    pub(crate) fn build_expr(&self) -> Option<Expr<'src>> {
+        self.parse_tree(&self.tree, &mut Vec::new())
+    }
+
+    pub fn build(self) -> Grammar<'src> {
+        self.build_with_diagnostics().0
+    }
+
+    /// Parses and builds `source` into a grammar that does not borrow it, so it can be
+    /// returned from where the source was read. Broken rules are left out like in `build`
+    pub fn build_owned(source: String) -> Grammar<'static> {
+        let mut parser = Parser::new(&source);
+        parser.parse();
+        GrammarBuilder::new(&source, parser.tree())
+            .build()
+            .into_owned()
+    }
+
+    /// Builds the grammar reporting malformed parts of the tree instead of panicking, rules
+    /// that cannot be built are left out and later definitions replace earlier ones
+    pub fn build_with_diagnostics(self) -> (Grammar<'src>, Vec<Diagnostic>) {
+        let (grammar, errors) = self.build_impl();
+        let diagnostics = errors
+            .into_iter()
+            .map(|(span, error)| match error {
+                BuildError::SyntaxError { .. } => Diagnostic::warning(span, error.to_string()),
+                _ => Diagnostic::error(span, error.to_string()),
+            })
+            .collect();
+        (grammar, diagnostics)
+    }
+
+    /// Builds the grammar only if every rule could be built and none is defined twice
+    pub fn build_checked(self) -> Result<Grammar<'src>, Vec<BuildError<'src>>> {
+        match self.build_impl() {
+            (grammar, errors) if errors.is_empty() => Ok(grammar),
+            (_, errors) => Err(errors.into_iter().map(|(_, error)| error).collect()),
+        }
+    }
+
+    fn build_impl(self) -> (Grammar<'src>, Vec<(Span, BuildError<'src>)>) {
+        let mut rules = IndexMap::new();
+        let mut allowed_left_recursion = IndexSet::new();
+        let mut errors = Vec::new();
+        // Trivia since the previous rule, only present when the parser kept it
+        let mut comments = Vec::new();
+        for child in &self.tree.children {
+            if let Child::Token(token) = child
+                && token.kind.is_trivia()
+            {
+                comments.push(token);
+                continue;
+            }
+            let preceding = std::mem::take(&mut comments);
+
+            let children = match child {
+                Child::Tree(Tree {
+                    kind: Kind::Rule,
+                    children,
+                }) => significant(children),
+                Child::Tree(Tree {
+                    kind: Kind::Error, ..
+                }) => continue,
+                Child::Tree(tree) => {
+                    errors.push((
+                        child.span().unwrap_or_default(),
+                        BuildError::UnexpectedChild { kind: tree.kind },
+                    ));
+                    continue;
+                }
+                Child::Token(token) => {
+                    errors.push((token.span, BuildError::UnexpectedToken { kind: token.kind }));
+                    continue;
+                }
+            };
+
+            let name = match children.first() {
+                Some(Child::Token(token)) if token.kind == token::Kind::Ident => {
+                    &self.source[token.span.range()]
+                }
+                // Rules missing their name were already reported by the parser
+                _ => continue,
+            };
+
+            if self.allows_left_recursion(child, preceding) {
+                allowed_left_recursion.insert(name);
+            }
+
+            if children.len() < 2 || children.iter().any(|child| child.is_error()) {
+                errors.push((
+                    child.span().unwrap_or_default(),
+                    BuildError::SyntaxError { name },
+                ));
+                continue;
+            }
+
+            if let Some(expr) = self.parse_expr(children[1], &mut errors)
+                && rules.insert(name, expr).is_some()
+            {
+                errors.push((
+                    child.span().unwrap_or_default(),
+                    BuildError::DuplicateRule { name },
+                ));
+            }
+        }
+        let mut grammar = Grammar::new(rules);
+        grammar.allowed_left_recursion = allowed_left_recursion;
+        (grammar, errors)
+    }
+
+    /// Whether a `// @allow_left_recursion` comment comes right before the rule, looking at
+    /// the trivia kept by [`ParseOptions::preserve_trivia`] or [`ParseOptions::full_fidelity`]
+    ///
+    /// [`ParseOptions::preserve_trivia`]: crate::parser::ParseOptions::preserve_trivia
+    /// [`ParseOptions::full_fidelity`]: crate::parser::ParseOptions::full_fidelity
+    fn allows_left_recursion(&self, rule: &Child, preceding: Vec<&token::Token>) -> bool {
+        let Child::Tree(rule) = rule else {
+            return false;
+        };
+        let mut trivia = preceding;
+        for child in &rule.children {
+            match child {
+                Child::Token(token) if token.kind.is_trivia() => trivia.push(token),
+                Child::Token(token) => {
+                    trivia.extend(&token.leading_trivia);
+                    break;
+                }
+                Child::Tree(_) => break,
+            }
+        }
+
+        // Comments at the end of the line of the previous rule belong to it
+        trivia.into_iter().any(|token| {
+            let line_start = self.source[..token.span.start]
+                .rfind('\n')
+                .map_or(0, |i| i + 1);
+            let text = &self.source[token.span.range()];
+            let text = text.strip_prefix("//").or(text.strip_prefix('#'));
+            token.kind == token::Kind::Comment
+                && self.source[line_start..token.span.start].trim().is_empty()
+                && text.is_some_and(|text| text.trim() == "@allow_left_recursion")
+        })
+    }
+
+    fn parse_expr(
+        &self,
+        child: &Child,
+        errors: &mut Vec<(Span, BuildError<'src>)>,
+    ) -> Option<Expr<'src>> {
+        let expr = match child {
+            Child::Token(token) => match token.kind {
+                token::Kind::Literal => {
+                    Expr::Literal(&self.source[token.span.start + 1..token.span.end - 1])
+                }
+                token::Kind::Ident => Expr::Rule(&self.source[token.span.range()]),
+                kind => {
+                    errors.push((token.span, BuildError::UnexpectedToken { kind }));
+                    return None;
+                }
+            },
+            Child::Tree(tree) => return self.parse_tree(tree, errors),
+        };
+        Some(expr)
+    }
+
+    fn parse_tree(
+        &self,
+        tree: &Tree,
+        errors: &mut Vec<(Span, BuildError<'src>)>,
+    ) -> Option<Expr<'src>> {
+        let expr = match tree.kind {
+            Kind::Sequence => {
+                let mut exprs = Vec::new();
+                for child in significant(&tree.children) {
+                    if !child.is_error() {
+                        exprs.push(self.parse_expr(child, errors)?);
+                    }
+                }
+                Expr::Sequence(exprs)
+            }
+            Kind::Branch => {
+                let children = significant(&tree.children);
+                if children.len() == 1 {
+                    return self.parse_expr(children[0], errors);
+                }
+
+                let mut exprs = Vec::new();
+                for child in children.into_iter().filter(|child| !child.is_error()) {
+                    exprs.push(self.parse_expr(child, errors)?);
+                }
+                Expr::Choice(exprs)
+            }
+            Kind::Optional | Kind::ZeroOrMore | Kind::OneOrMore => {
+                let Some(child) = significant(&tree.children).first().copied() else {
+                    errors.push((
+                        tree.span().unwrap_or_default(),
+                        BuildError::EmptyChild { kind: tree.kind },
+                    ));
+                    return None;
+                };
+                let inner = Box::new(self.parse_expr(child, errors)?);
+                match tree.kind {
+                    Kind::Optional => Expr::Optional(inner),
+                    Kind::ZeroOrMore => Expr::Repeat(inner),
+                    _ => Expr::OneOrMore(inner),
+                }
+            }
+            kind => {
+                errors.push((
+                    tree.span().unwrap_or_default(),
+                    BuildError::UnexpectedChild { kind },
+                ));
+                return None;
+            }
+        };
+        Some(expr)
+    }
+}
+
+/// Children without the trivia tokens kept by [`ParseOptions::preserve_trivia`]
+///
+/// [`ParseOptions::preserve_trivia`]: crate::parser::ParseOptions::preserve_trivia
+fn significant(children: &[Child]) -> Vec<&Child> {
+    children.iter().filter(|child| !child.is_trivia()).collect()
+}
+
+/// Leaks `text` once so owned grammars can keep borrowing `&'static str` like parsed ones do.
+/// Every distinct string lives until the process exits, equal names share one allocation
+fn intern(text: &str) -> &'static str {
+    static INTERNED: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut interned = INTERNED.get_or_init(Default::default).lock().unwrap();
+    if let Some(text) = interned.get(text) {
+        return text;
+    }
+
+    let text: &'static str = text.to_owned().leak();
+    interned.insert(text);
+    text
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum Expr<'src> {
     Literal(&'src str),
     Rule(&'src str),
@@ -259,17 +1753,654 @@ pub enum Expr<'src> {
     Choice(Vec<Self>),
     Optional(Box<Self>),
     Repeat(Box<Self>),
+    OneOrMore(Box<Self>),
+}
+
+/// Ungram notation of the expression, as it would be written in a rule body. Sequences
+/// bind tighter than `|` so only groups used as a term are parenthesized
+impl std::fmt::Display for Expr<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut out = String::new();
+        self.write_ebnf_body(&mut out);
+        f.write_str(&out)
+    }
+}
+
+/// Rules that may derive ε in declaration order, see [`Grammar::nullable_set`]
+fn nullable_rules<'src>(rules: &IndexMap<&'src str, Expr>) -> IndexSet<&'src str> {
+    let mut nullable = IndexSet::new();
+    loop {
+        let before = nullable.len();
+        for (name, expr) in rules.iter() {
+            if !nullable.contains(name) && expr.derives_empty(&nullable) {
+                nullable.insert(*name);
+            }
+        }
+
+        if nullable.len() == before {
+            break;
+        }
+    }
+
+    rules
+        .keys()
+        .copied()
+        .filter(|name| nullable.contains(name))
+        .collect()
 }
 
-impl<'src> Expr<'src> {
-    fn may_miss(&self, rules: &IndexMap<&str, Expr>) -> bool {
+impl<'src> Expr<'src> {
+    /// Whether the expression may derive ε, `rules` gives the bodies of the rules it references
+    pub fn is_nullable(&self, rules: &IndexMap<&str, Expr>) -> bool {
+        self.derives_empty(&nullable_rules(rules))
+    }
+
+    /// FIRST set of the expression as if it were the body of a rule of `grammar`, with ε when it
+    /// may derive the empty string. Rules `grammar` does not define contribute nothing
+    pub fn first_set<'a>(&'a self, grammar: &'a Grammar<'a>) -> IndexSet<&'a str> {
+        let nullable = grammar.nullable_set();
+        let mut set = self.first_terminals(&grammar.first_terminal_sets(&nullable), &nullable);
+        if self.derives_empty(&nullable) {
+            set.insert("ε");
+        }
+        set
+    }
+
+    fn may_miss(&self, rules: &IndexMap<&str, Expr>) -> bool {
+        self.may_miss_impl(rules, &mut HashSet::new())
+    }
+
+    /// Rules already in `visited` add nothing, so recursive rules end
+    fn may_miss_impl<'a>(
+        &'a self,
+        rules: &'a IndexMap<&str, Expr>,
+        visited: &mut HashSet<&'a str>,
+    ) -> bool {
+        match self {
+            Expr::Literal(_) => false,
+            Expr::Rule(rule) => {
+                visited.insert(rule) && rules.get(rule).unwrap().may_miss_impl(rules, visited)
+            }
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                exprs.iter().any(|x| x.may_miss_impl(rules, visited))
+            }
+            Expr::Optional(_) => true,
+            Expr::Repeat(_) => true,
+            Expr::OneOrMore(x) => x.may_miss_impl(rules, visited),
+        }
+    }
+
+    /// Writes the expression in BNF notation, `in_sequence` tells if a choice needs parenthesis
+    fn write_bnf(&self, out: &mut String, in_sequence: bool) {
+        match self {
+            Expr::Literal(lit) => out.push_str(&format!("'{lit}'")),
+            Expr::Rule(rule) => out.push_str(&format!("<{rule}>")),
+            Expr::Sequence(exprs) => {
+                let group = in_sequence && exprs.len() > 1;
+                if group {
+                    out.push_str("( ");
+                }
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    expr.write_bnf(out, true);
+                }
+                if group {
+                    out.push_str(" )");
+                }
+            }
+            Expr::Choice(branches) => {
+                if in_sequence {
+                    out.push_str("( ");
+                }
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(" | ");
+                    }
+                    branch.write_bnf(out, false);
+                }
+                if in_sequence {
+                    out.push_str(" )");
+                }
+            }
+            Expr::Optional(expr) => {
+                out.push_str("( ");
+                expr.write_bnf(out, false);
+                out.push_str(" | ε )");
+            }
+            Expr::Repeat(expr) => {
+                out.push_str("( ");
+                expr.write_bnf(out, false);
+                out.push_str(" )*");
+            }
+            Expr::OneOrMore(expr) => {
+                out.push_str("( ");
+                expr.write_bnf(out, false);
+                out.push_str(" )+");
+            }
+        }
+    }
+
+    /// Writes a rule body or the contents of a parenthesized group, where the parser produces
+    /// either a sequence or a choice of sequences
+    fn write_ebnf_body(&self, out: &mut String) {
+        match self {
+            Expr::Sequence(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    expr.write_ebnf_term(out);
+                }
+            }
+            Expr::Choice(branches) => {
+                for (i, branch) in branches.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(" | ");
+                    }
+                    branch.write_ebnf_body(out);
+                }
+            }
+            expr => expr.write_ebnf_term(out),
+        }
+    }
+
+    /// Writes a single element of a sequence
+    fn write_ebnf_term(&self, out: &mut String) {
+        let (expr, suffix) = match self {
+            Expr::Literal(lit) => return out.push_str(&format!("'{lit}'")),
+            Expr::Rule(rule) => return out.push_str(rule),
+            Expr::Sequence(_) | Expr::Choice(_) => {
+                out.push('(');
+                self.write_ebnf_body(out);
+                return out.push(')');
+            }
+            Expr::Optional(expr) => (expr, '?'),
+            Expr::Repeat(expr) => (expr, '*'),
+            Expr::OneOrMore(expr) => (expr, '+'),
+        };
+
+        match expr.as_ref() {
+            expr @ (Expr::Literal(_) | Expr::Rule(_)) => expr.write_ebnf_term(out),
+            expr => {
+                out.push('(');
+                expr.write_ebnf_body(out);
+                out.push(')');
+            }
+        }
+        out.push(suffix);
+    }
+
+    /// Writes the nodes and edges of the expression, returning the id of its root node
+    fn write_dot(&self, out: &mut String, next_id: &mut usize) -> String {
+        let mut node = |out: &mut String, label: &str, attributes: &str| {
+            let id = format!("n{next_id}");
+            *next_id += 1;
+            out.push_str(&format!("  {id} [label=\"{label}\", {attributes}];\n"));
+            id
+        };
+
+        match self.ungrouped() {
+            Expr::Literal(lit) => node(
+                out,
+                &dot_escape(&format!("'{lit}'")),
+                "shape=plaintext, fontcolor=darkgreen",
+            ),
+            Expr::Rule(rule) => format!("\"{}\"", dot_escape(rule)),
+            Expr::Sequence(exprs) => {
+                let id = node(out, "seq", "shape=box");
+                for (i, expr) in exprs.iter().enumerate() {
+                    let child = expr.write_dot(out, next_id);
+                    out.push_str(&format!("  {id} -> {child} [label=\"{i}\"];\n"));
+                }
+                id
+            }
+            Expr::Choice(branches) => {
+                let id = node(out, "|", "shape=diamond");
+                for branch in branches {
+                    let child = branch.write_dot(out, next_id);
+                    out.push_str(&format!("  {id} -> {child};\n"));
+                }
+                id
+            }
+            Expr::Optional(expr) | Expr::Repeat(expr) | Expr::OneOrMore(expr) => {
+                let modifier = match self.ungrouped() {
+                    Expr::Optional(_) => "?",
+                    Expr::Repeat(_) => "*",
+                    _ => "+",
+                };
+                let id = node(out, modifier, "shape=ellipse");
+                let child = expr.write_dot(out, next_id);
+                out.push_str(&format!("  {id} -> {child};\n"));
+                id
+            }
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Expr::Literal(lit) => out.push_str(&format!(
+                r#"{{"type":"Literal","value":{}}}"#,
+                json_string(lit)
+            )),
+            Expr::Rule(rule) => out.push_str(&format!(
+                r#"{{"type":"Rule","name":{}}}"#,
+                json_string(rule)
+            )),
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                let (kind, key) = match self {
+                    Expr::Sequence(_) => ("Sequence", "items"),
+                    _ => ("Choice", "branches"),
+                };
+                out.push_str(&format!(r#"{{"type":"{kind}","{key}":["#));
+                for (i, expr) in exprs.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    expr.write_json(out);
+                }
+                out.push_str("]}");
+            }
+            Expr::Optional(expr) | Expr::Repeat(expr) | Expr::OneOrMore(expr) => {
+                let kind = match self {
+                    Expr::Optional(_) => "Optional",
+                    Expr::Repeat(_) => "Repeat",
+                    _ => "OneOrMore",
+                };
+                out.push_str(&format!(r#"{{"type":"{kind}","expr":"#));
+                expr.write_json(out);
+                out.push('}');
+            }
+        }
+    }
+
+    /// Looks through sequences of a single element
+    fn ungrouped(&self) -> &Self {
+        match self {
+            Expr::Sequence(exprs) if exprs.len() == 1 => exprs[0].ungrouped(),
+            expr => expr,
+        }
+    }
+
+    fn structurally_eq(&self, other: &Expr) -> bool {
+        match (self.ungrouped(), other.ungrouped()) {
+            (Expr::Literal(a), Expr::Literal(b)) | (Expr::Rule(a), Expr::Rule(b)) => a == b,
+            (Expr::Sequence(a), Expr::Sequence(b)) | (Expr::Choice(a), Expr::Choice(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.structurally_eq(b))
+            }
+            (Expr::Optional(a), Expr::Optional(b))
+            | (Expr::Repeat(a), Expr::Repeat(b))
+            | (Expr::OneOrMore(a), Expr::OneOrMore(b)) => a.structurally_eq(b),
+            _ => false,
+        }
+    }
+
+    /// Replaces every reference to `name` with a copy of `replacement`
+    fn replace_rule(&mut self, name: &str, replacement: &Expr<'src>) {
+        *self = self.map_rules(|rule| match rule == name {
+            true => replacement.clone(),
+            false => Expr::Rule(rule),
+        });
+    }
+
+    /// Copy of the expression with every rule reference replaced by `f(name)`
+    pub fn map_rules<'new, F>(&self, f: F) -> Expr<'new>
+    where
+        F: Fn(&'src str) -> Expr<'new>,
+        'src: 'new,
+    {
+        self.map_leaves(|leaf| match leaf {
+            Expr::Rule(rule) => f(rule),
+            leaf => leaf.clone(),
+        })
+    }
+
+    /// Copy of the expression with every literal replaced by `f(literal)`
+    pub fn map_literals<'new, F>(&self, f: F) -> Expr<'new>
+    where
+        F: Fn(&'src str) -> &'new str,
+        'src: 'new,
+    {
+        self.map_leaves(|leaf| match leaf {
+            Expr::Literal(lit) => Expr::Literal(f(lit)),
+            leaf => leaf.clone(),
+        })
+    }
+
+    /// Rebuilds the expression replacing literals and rules with `leaf`. Walks with an explicit
+    /// stack so deeply nested expressions do not overflow it
+    fn map_leaves<'new>(&self, leaf: impl Fn(&Expr<'src>) -> Expr<'new>) -> Expr<'new> {
+        enum Step<'a, 'src> {
+            Enter(&'a Expr<'src>),
+            /// Every child of the expression is already on the `done` stack
+            Exit(&'a Expr<'src>),
+        }
+
+        let mut steps = vec![Step::Enter(self)];
+        let mut done = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Enter(expr @ (Expr::Literal(_) | Expr::Rule(_))) => done.push(leaf(expr)),
+                Step::Enter(expr) => {
+                    steps.push(Step::Exit(expr));
+                    match expr {
+                        Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                            steps.extend(exprs.iter().rev().map(Step::Enter))
+                        }
+                        Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                            steps.push(Step::Enter(x))
+                        }
+                        Expr::Literal(_) | Expr::Rule(_) => unreachable!(),
+                    }
+                }
+                Step::Exit(expr) => {
+                    let expr = match expr {
+                        Expr::Sequence(exprs) => {
+                            Expr::Sequence(done.split_off(done.len() - exprs.len()))
+                        }
+                        Expr::Choice(exprs) => {
+                            Expr::Choice(done.split_off(done.len() - exprs.len()))
+                        }
+                        Expr::Optional(_) => Expr::Optional(Box::new(done.pop().unwrap())),
+                        Expr::Repeat(_) => Expr::Repeat(Box::new(done.pop().unwrap())),
+                        Expr::OneOrMore(_) => Expr::OneOrMore(Box::new(done.pop().unwrap())),
+                        Expr::Literal(_) | Expr::Rule(_) => unreachable!(),
+                    };
+                    done.push(expr);
+                }
+            }
+        }
+        done.pop().unwrap()
+    }
+
+    /// Copies the expression with every string interned
+    fn to_static(&self) -> Expr<'static> {
+        match self {
+            Expr::Literal(lit) => Expr::Literal(intern(lit)),
+            Expr::Rule(rule) => Expr::Rule(intern(rule)),
+            Expr::Sequence(exprs) => Expr::Sequence(exprs.iter().map(Expr::to_static).collect()),
+            Expr::Choice(exprs) => Expr::Choice(exprs.iter().map(Expr::to_static).collect()),
+            Expr::Optional(x) => Expr::Optional(Box::new(x.to_static())),
+            Expr::Repeat(x) => Expr::Repeat(Box::new(x.to_static())),
+            Expr::OneOrMore(x) => Expr::OneOrMore(Box::new(x.to_static())),
+        }
+    }
+
+    /// Top level alternatives, an expression that is not a choice is its only branch
+    fn branches(&self) -> &[Self] {
+        match self {
+            Expr::Choice(branches) => branches,
+            expr => std::slice::from_ref(expr),
+        }
+    }
+
+    /// Nesting depth of the expression, literals and rule references are 0
+    pub fn depth(&self) -> usize {
+        match self {
+            Expr::Literal(_) | Expr::Rule(_) => 0,
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                1 + exprs.iter().map(Expr::depth).max().unwrap_or(0)
+            }
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => 1 + x.depth(),
+        }
+    }
+
+    /// Number of nodes in the expression, itself included
+    pub fn size(&self) -> usize {
+        match self {
+            Expr::Literal(_) | Expr::Rule(_) => 1,
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                1 + exprs.iter().map(Expr::size).sum::<usize>()
+            }
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => 1 + x.size(),
+        }
+    }
+
+    fn sort_alternatives(&mut self) {
+        match self {
+            Expr::Literal(_) | Expr::Rule(_) => {}
+            Expr::Sequence(exprs) => exprs.iter_mut().for_each(Expr::sort_alternatives),
+            Expr::Choice(branches) => {
+                branches.iter_mut().for_each(Expr::sort_alternatives);
+                branches.sort_by_cached_key(|branch| branch.to_string());
+            }
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => x.sort_alternatives(),
+        }
+    }
+
+    /// Whether rule references only appear at the end of the expression, or at its start for
+    /// [`Linearity::Left`], so it stands for productions like `A = 'x' B`
+    fn is_linear(&self, linearity: Linearity) -> bool {
+        let literals_only = |expr: &Expr| expr.non_terminals().next().is_none();
+        match self {
+            Expr::Literal(_) | Expr::Rule(_) => true,
+            Expr::Sequence(exprs) => {
+                let edge = match linearity {
+                    Linearity::Right => exprs.split_last(),
+                    Linearity::Left => exprs.split_first(),
+                };
+                let Some((edge, rest)) = edge else {
+                    return true;
+                };
+                edge.is_linear(linearity) && rest.iter().all(literals_only)
+            }
+            Expr::Choice(branches) => branches.iter().all(|x| x.is_linear(linearity)),
+            Expr::Optional(x) => x.is_linear(linearity),
+            Expr::Repeat(x) | Expr::OneOrMore(x) => literals_only(x),
+        }
+    }
+
+    /// Whether `name` is referenced anywhere in the expression
+    pub fn contains_rule(&self, name: &str) -> bool {
+        match self {
+            Expr::Literal(_) => false,
+            Expr::Rule(rule) => *rule == name,
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                exprs.iter().any(|x| x.contains_rule(name))
+            }
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => x.contains_rule(name),
+        }
+    }
+
+    /// Whether the literal `lit` appears anywhere in the expression
+    pub fn contains_literal(&self, lit: &str) -> bool {
+        match self {
+            Expr::Literal(literal) => *literal == lit,
+            Expr::Rule(_) => false,
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                exprs.iter().any(|x| x.contains_literal(lit))
+            }
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => x.contains_literal(lit),
+        }
+    }
+
+    /// Every literal in the expression, once and in order of appearance
+    pub fn terminals(&self) -> impl Iterator<Item = &'src str> {
+        let mut set = IndexSet::new();
+        self.literal_references(&mut set);
+        set.into_iter()
+    }
+
+    /// Every rule referenced in the expression, once and in order of appearance
+    pub fn non_terminals(&self) -> impl Iterator<Item = &'src str> {
+        let mut set = IndexSet::new();
+        self.rule_references(&mut set);
+        set.into_iter()
+    }
+
+    fn literal_references(&self, set: &mut IndexSet<&'src str>) {
+        match self {
+            Expr::Literal(lit) => {
+                set.insert(lit);
+            }
+            Expr::Rule(_) => {}
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                exprs.iter().for_each(|x| x.literal_references(set))
+            }
+            Expr::Optional(x) => x.literal_references(set),
+            Expr::Repeat(x) => x.literal_references(set),
+            Expr::OneOrMore(x) => x.literal_references(set),
+        }
+    }
+
+    fn rule_references(&self, set: &mut IndexSet<&'src str>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Rule(rule) => {
+                set.insert(rule);
+            }
+            Expr::Sequence(exprs) | Expr::Choice(exprs) => {
+                exprs.iter().for_each(|x| x.rule_references(set))
+            }
+            Expr::Optional(x) => x.rule_references(set),
+            Expr::Repeat(x) => x.rule_references(set),
+            Expr::OneOrMore(x) => x.rule_references(set),
+        }
+    }
+
+    /// Rules that may appear as the leftmost symbol of the expression
+    fn left_corners(&self, nullable: &IndexSet<&str>, set: &mut IndexSet<&'src str>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Rule(rule) => {
+                set.insert(rule);
+            }
+            Expr::Sequence(exprs) => {
+                for expr in exprs {
+                    expr.left_corners(nullable, set);
+                    if !expr.derives_empty(nullable) {
+                        break;
+                    }
+                }
+            }
+            Expr::Choice(branches) => branches.iter().for_each(|x| x.left_corners(nullable, set)),
+            Expr::Optional(x) => x.left_corners(nullable, set),
+            Expr::Repeat(x) => x.left_corners(nullable, set),
+            Expr::OneOrMore(x) => x.left_corners(nullable, set),
+        }
+    }
+
+    /// Terminals that may start the expression given the FIRST sets of the rules known so far,
+    /// without ε
+    fn first_terminals(
+        &self,
+        first: &IndexMap<&str, IndexSet<&'src str>>,
+        nullable: &IndexSet<&str>,
+    ) -> IndexSet<&'src str> {
+        match self {
+            Expr::Literal(lit) => IndexSet::from([*lit]),
+            Expr::Rule(rule) => first.get(rule).cloned().unwrap_or_default(),
+            Expr::Sequence(exprs) => {
+                let mut set = IndexSet::new();
+                for expr in exprs {
+                    set.extend(expr.first_terminals(first, nullable));
+                    if !expr.derives_empty(nullable) {
+                        break;
+                    }
+                }
+                set
+            }
+            Expr::Choice(branches) => branches
+                .iter()
+                .flat_map(|x| x.first_terminals(first, nullable))
+                .collect(),
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                x.first_terminals(first, nullable)
+            }
+        }
+    }
+
+    /// Adds `after`, the terminals that may follow the expression, to the FOLLOW sets of the
+    /// rules it references at the positions where nothing else of it comes next
+    fn add_follow(
+        &self,
+        after: &IndexSet<&'src str>,
+        first: &IndexMap<&str, IndexSet<&'src str>>,
+        nullable: &IndexSet<&str>,
+        follow: &mut IndexMap<&'src str, IndexSet<&'src str>>,
+    ) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Rule(rule) => {
+                if let Some(set) = follow.get_mut(rule) {
+                    set.extend(after.iter().copied());
+                }
+            }
+            Expr::Sequence(exprs) => {
+                let mut after = after.clone();
+                for expr in exprs.iter().rev() {
+                    expr.add_follow(&after, first, nullable, follow);
+                    let mut starts = expr.first_terminals(first, nullable);
+                    if expr.derives_empty(nullable) {
+                        starts.extend(after);
+                    }
+                    after = starts;
+                }
+            }
+            Expr::Choice(branches) => branches
+                .iter()
+                .for_each(|x| x.add_follow(after, first, nullable, follow)),
+            Expr::Optional(x) => x.add_follow(after, first, nullable, follow),
+            // A repetition may be followed by itself
+            Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                let mut after = after.clone();
+                after.extend(x.first_terminals(first, nullable));
+                x.add_follow(&after, first, nullable, follow);
+            }
+        }
+    }
+
+    /// Rules the expression may derive alone, every other part of it deriving ε
+    fn epsilon_references(&self, nullable: &IndexSet<&str>, set: &mut IndexSet<&'src str>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Rule(rule) => {
+                set.insert(rule);
+            }
+            Expr::Sequence(exprs) => {
+                for (i, expr) in exprs.iter().enumerate() {
+                    let rest_empty = exprs
+                        .iter()
+                        .enumerate()
+                        .all(|(j, other)| i == j || other.derives_empty(nullable));
+                    if rest_empty {
+                        expr.epsilon_references(nullable, set);
+                    }
+                }
+            }
+            Expr::Choice(branches) => branches
+                .iter()
+                .for_each(|x| x.epsilon_references(nullable, set)),
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                x.epsilon_references(nullable, set)
+            }
+        }
+    }
+
+    /// Whether the expression derives a terminal string given the currently known productive rules
+    fn is_productive(&self, productive: &IndexSet<&str>) -> bool {
+        match self {
+            Expr::Literal(_) => true,
+            Expr::Rule(rule) => productive.contains(rule),
+            Expr::Sequence(exprs) => exprs.iter().all(|x| x.is_productive(productive)),
+            Expr::Choice(exprs) => exprs.iter().any(|x| x.is_productive(productive)),
+            Expr::Optional(_) => true,
+            Expr::Repeat(_) => true,
+            Expr::OneOrMore(x) => x.is_productive(productive),
+        }
+    }
+
+    /// Whether the expression derives ε given the currently known nullable rules
+    fn derives_empty(&self, nullable: &IndexSet<&str>) -> bool {
         match self {
             Expr::Literal(_) => false,
-            Expr::Rule(rule) => rules.get(rule).unwrap().may_miss(rules),
-            Expr::Sequence(exprs) => exprs.iter().any(|x| x.may_miss(rules)),
-            Expr::Choice(exprs) => exprs.iter().any(|x| x.may_miss(rules)),
+            Expr::Rule(rule) => nullable.contains(rule),
+            Expr::Sequence(exprs) => exprs.iter().all(|x| x.derives_empty(nullable)),
+            Expr::Choice(exprs) => exprs.iter().any(|x| x.derives_empty(nullable)),
             Expr::Optional(_) => true,
             Expr::Repeat(_) => true,
+            Expr::OneOrMore(x) => x.derives_empty(nullable),
         }
     }
 
@@ -291,6 +2422,7 @@ impl<'src> Expr<'src> {
             Expr::Choice(branches) => branches.iter().any(|x| x.is_alias(expr, rules)),
             Expr::Optional(x) => x.is_alias(expr, rules),
             Expr::Repeat(x) => x.is_alias(expr, rules),
+            Expr::OneOrMore(x) => x.is_alias(expr, rules),
             _ => false,
         }
     }
@@ -303,6 +2435,1162 @@ impl<'src> Expr<'src> {
             Expr::Choice(branches) => branches.iter().any(|x| x.produces_at_end(expr)),
             Expr::Optional(x) => x.produces_at_end(expr),
             Expr::Repeat(x) => x.produces_at_end(expr),
+            Expr::OneOrMore(x) => x.produces_at_end(expr),
+        }
+    }
+}
+
+/// `a`, `a and b` or `a, b and c`
+fn join_and(items: impl Iterator<Item = String>) -> String {
+    let mut items = items.collect::<Vec<_>>();
+    match items.pop() {
+        Some(last) if !items.is_empty() => format!("{} and {last}", items.join(", ")),
+        last => last.unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use expect_test::expect;
+    use indexmap::{IndexMap, IndexSet};
+
+    use crate::diagnostic::Diagnostic;
+    use crate::grammar::{
+        BuildError, DuplicateRuleError, Expr, Grammar, GrammarBuilder, LeftRecursion, Linearity,
+        Ll1Conflict, MergeError, RegularityResult, RenameError, UnknownRuleError, ValidationError,
+        ValidationWarning, END_OF_INPUT,
+    };
+    use crate::lexer::Lexer;
+    use crate::parser::{Child, Kind, ParseOptions, Parser, Tree};
+    use crate::span::Span;
+    use crate::token::{self, Token};
+
+    pub(super) fn grammar(source: &str) -> Grammar<'_> {
+        let mut parser = Parser::new(source);
+        parser.parse();
+        GrammarBuilder::new(source, parser.tree()).build()
+    }
+
+    #[test]
+    fn test_nullable_set() {
+        let grammar = grammar(
+            "
+            S = A B 'x'
+            A = 'a'?
+            B = 'b'*
+            C = A B
+            D = C | 'd'
+            E = 'e' A
+            ",
+        );
+
+        let nullable = grammar.nullable_set();
+        assert_eq!(
+            nullable.into_iter().collect::<Vec<_>>(),
+            ["A", "B", "C", "D"]
+        );
+
+        for nt in grammar.nullable_set() {
+            assert!(grammar.first_set(nt).contains("ε"), "{nt}");
+        }
+    }
+
+    #[test]
+    fn test_map_rules() {
+        let grammar = grammar("S = A ('a' | B)* A? 'b'+");
+        let expr = &grammar.rules["S"];
+
+        let renamed = expr.map_rules(|rule| Expr::Rule(if rule == "A" { "X" } else { rule }));
+        assert_eq!(renamed.to_string(), "X ('a' | B)* X? 'b'+");
+        let inlined = expr.map_rules(|rule| match rule {
+            "B" => Expr::Sequence(vec![Expr::Literal("c"), Expr::Literal("d")]),
+            rule => Expr::Rule(rule),
+        });
+        assert_eq!(inlined.to_string(), "A ('a' | ('c' 'd'))* A? 'b'+");
+
+        let upper = expr.map_literals(|lit| if lit == "a" { "A" } else { lit });
+        assert_eq!(upper.to_string(), "A ('A' | B)* A? 'b'+");
+
+        let mut deep = Expr::Rule("A");
+        for _ in 0..1_000 {
+            deep = Expr::Optional(Box::new(Expr::Sequence(vec![deep])));
+        }
+        let mut mapped = deep.map_rules(|_| Expr::Literal("a"));
+        loop {
+            mapped = match mapped {
+                Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => *x,
+                Expr::Sequence(mut exprs) => exprs.pop().unwrap(),
+                leaf => break assert_eq!(leaf, Expr::Literal("a")),
+            };
+        }
+    }
+
+    #[test]
+    fn test_expr_first_set() {
+        let grammar = grammar("S = A 'x' | 'y'\nA = 'a'? B\nB = 'b'*");
+        let Expr::Choice(branches) = &grammar.rules["S"] else {
+            panic!("expected a choice");
+        };
+
+        assert_eq!(
+            branches[0].first_set(&grammar),
+            IndexSet::from(["a", "b", "x"])
+        );
+        assert_eq!(branches[1].first_set(&grammar), IndexSet::from(["y"]));
+        assert_eq!(
+            grammar.rules["A"].first_set(&grammar),
+            grammar.first_set("A")
+        );
+
+        assert!(!branches[0].is_nullable(&grammar.rules));
+        assert!(grammar.rules["A"].is_nullable(&grammar.rules));
+        assert!(Expr::Rule("B").is_nullable(&grammar.rules));
+        assert!(
+            !Expr::Sequence(vec![Expr::Rule("B"), Expr::Literal("x")]).is_nullable(&grammar.rules)
+        );
+    }
+
+    #[test]
+    fn test_predict_set() {
+        let grammar = grammar(
+            "
+            S = 'a' | 'b'
+            T = A 'x'
+            A = 'a'?
+            ",
+        );
+
+        assert_eq!(grammar.predict_set("S"), IndexSet::from(["a", "b"]));
+        assert_eq!(grammar.predict_set_branch("S", 0), IndexSet::from(["a"]));
+        assert_eq!(grammar.predict_set_branch("S", 1), IndexSet::from(["b"]));
+        assert_eq!(grammar.predict_set("A"), IndexSet::from(["a", "x"]));
+        assert_eq!(
+            grammar.predict_set_branch("A", 0),
+            IndexSet::from(["a", "x"])
+        );
+    }
+
+    #[test]
+    fn test_first_plus() {
+        let grammar = grammar(
+            "
+            S = A B 'c'
+            A = 'a'?
+            B = 'b'*
+            ",
+        );
+
+        assert_eq!(grammar.first_plus("S"), IndexSet::from(["a", "b", "c"]));
+        assert_eq!(grammar.first_plus("A"), IndexSet::from(["a", "b", "c"]));
+        assert_eq!(grammar.first_plus("B"), IndexSet::from(["b", "c"]));
+
+        // The start rule may be followed by the end of the input
+        let nullable = self::grammar("S = 'a'?");
+        assert_eq!(nullable.first_plus("S"), IndexSet::from(["a", "$"]));
+    }
+
+    #[test]
+    fn test_predict_set_left_recursive() {
+        let grammar = grammar("Expr = Expr '+' Term | Term\nTerm = 'n'");
+        assert_eq!(grammar.predict_set_branch("Expr", 1), IndexSet::from(["n"]));
+        assert_eq!(grammar.ll1_table().rows["Term"]["n"], [0]);
+    }
+
+    #[test]
+    fn test_set_snapshots() {
+        let grammar = grammar(
+            "
+            Expr = Term ('+' Term)*
+            Term = Factor ('*' Factor)*
+            Factor = 'num' | '(' Expr ')' | '-' Factor
+            ",
+        );
+
+        let sets = grammar
+            .rules
+            .keys()
+            .map(|name| {
+                format!(
+                    "{name}\n  first: {:?}\n  follow: {:?}\n  predict: {:?}\n",
+                    grammar.first_set(name),
+                    grammar.follow_set(name),
+                    grammar.predict_set(name),
+                )
+            })
+            .collect::<String>();
+        expect![[r#"
+            Expr
+              first: {"num", "(", "-"}
+              follow: {")"}
+              predict: {"num", "(", "-"}
+            Term
+              first: {"num", "(", "-"}
+              follow: {"+", ")"}
+              predict: {"num", "(", "-"}
+            Factor
+              first: {"num", "(", "-"}
+              follow: {"*", "+", ")"}
+              predict: {"num", "(", "-"}
+        "#]]
+        .assert_eq(&sets);
+    }
+
+    #[test]
+    fn test_compute_follow_sets() {
+        let grammar = grammar(
+            "
+            S = File '#'
+            File = Fn*
+            Fn = 'fn' 'name' ParamList ('->' 'type')? Block
+            ParamList = '(' Param* ')'
+            Param = 'name' ':' 'type' ','?
+            Block = '{' 'statements' '}'
+            ",
+        );
+
+        let follow = grammar.compute_follow_sets();
+        assert_eq!(follow["S"], IndexSet::from(["$"]));
+        for (name, set) in follow.iter().skip(1) {
+            assert_eq!(set, &grammar.follow_set(name), "{name}");
+        }
+
+        let follow = self::grammar("E = T ('+' T)*\nT = F ('*' F)*\nF = 'n' | '(' E ')'")
+            .compute_follow_sets();
+        assert_eq!(follow["E"], IndexSet::from(["$", ")"]));
+        assert_eq!(follow["T"], IndexSet::from(["+", "$", ")"]));
+        assert_eq!(follow["F"], IndexSet::from(["*", "+", "$", ")"]));
+    }
+
+    #[test]
+    fn test_full_follow_sets() {
+        let grammar = grammar("S = 'a' S 'b' | ()");
+        let follow = grammar.full_follow_sets("S");
+        assert_eq!(follow["S"], IndexSet::from([END_OF_INPUT, "b"]));
+
+        let grammar = self::grammar("S = A 'x'\nA = 'a' B\nB = 'b'?");
+        let follow = grammar.full_follow_sets("A");
+        assert_eq!(follow["A"], IndexSet::from(["x", "$"]));
+        assert_eq!(follow["B"], IndexSet::from(["x", "$"]));
+        assert!(follow["S"].is_empty());
+        assert_eq!(grammar.full_follow_sets("S")["A"], IndexSet::from(["x"]));
+        assert!(grammar
+            .full_follow_sets("Missing")
+            .values()
+            .all(|set| !set.contains(END_OF_INPUT)));
+    }
+
+    #[test]
+    fn test_follow_set() {
+        let grammar = grammar(
+            "
+            S = File '#'
+            File = Fn*
+            Fn = 'fn' 'name' Block
+            Block = '{' '}'
+            ",
+        );
+
+        assert_eq!(grammar.follow_set("File"), IndexSet::from(["#"]));
+        assert_eq!(grammar.follow_set("Fn"), IndexSet::from(["fn", "#"]));
+        assert_eq!(grammar.follow_set_strict("Fn"), IndexSet::from(["#"]));
+        assert_eq!(grammar.follow_set("Block"), IndexSet::from(["fn", "#"]));
+    }
+
+    #[test]
+    fn test_ll1_conflicts() {
+        let grammar = grammar(
+            "
+            S = 'a' 'b' | 'a' 'c' | 'd'
+            T = 'x' ('y' | 'y' 'z')
+            U = 'u' | 'v'
+            ",
+        );
+
+        assert_eq!(
+            grammar.ll1_conflicts(),
+            [
+                Ll1Conflict {
+                    rule: "S",
+                    path: vec![],
+                    branches: vec![0, 1],
+                    tokens: IndexSet::from(["a"]),
+                },
+                Ll1Conflict {
+                    rule: "T",
+                    path: vec![1],
+                    branches: vec![0, 1],
+                    tokens: IndexSet::from(["y"]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_conflict_detail() {
+        let grammar = grammar(
+            "
+            Stmt = 'if' 'while' Expr 'do' | 'x' | 'if' 'for' Expr 'do'
+            Expr = 'a' | 'a' 'b' | ('a' | 'c') 'd'
+            T = 'x' ('y' | 'y' 'z')
+            ",
+        );
+
+        assert_eq!(
+            grammar.conflict_detail(),
+            [
+                "In rule 'Stmt', alternatives 0 and 2 both predict token 'if': branch 0 starts \
+                 with 'if' 'while' Expr and branch 2 starts with 'if' 'for' Expr.",
+                "In rule 'Expr', alternatives 0, 1 and 2 all predict token 'a': branch 0 starts \
+                 with 'a', branch 1 starts with 'a' 'b' and branch 2 starts with ('a' | 'c') 'd'.",
+                "In a nested choice of rule 'T', alternatives 0 and 1 both predict token 'y': \
+                 branch 0 starts with 'y' and branch 1 starts with 'y' 'z'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ll1_conflicts_nullable_branch() {
+        assert!(grammar("S = 'a' | 'b'").ll1_conflicts().is_empty());
+
+        let grammar = grammar(
+            "
+            S = A 'a'
+            A = 'a' | 'b'?
+            ",
+        );
+
+        assert_eq!(
+            grammar.ll1_conflicts(),
+            [Ll1Conflict {
+                rule: "A",
+                path: vec![],
+                branches: vec![0, 1],
+                tokens: IndexSet::from(["a"]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_is_ll1() {
+        assert!(grammar("S = 'a' B | 'b'\nB = 'c'?").is_ll1());
+
+        let grammar = grammar("S = A 'a' | 'c'\nA = 'a' | 'b'?\nT = T 'x' | 'y'");
+        assert!(!grammar.is_ll1());
+        assert!(grammar.is_ll1_for_rule("S"));
+        assert!(!grammar.is_ll1_for_rule("A"));
+        assert!(!grammar.is_ll1_for_rule("T"));
+    }
+
+    #[test]
+    fn test_epsilon_cycles() {
+        let grammar = grammar(
+            "
+            S = A 'x' | C
+            A = B 'y'? | 'a'
+            B = A
+            C = 'c'? C 'd'?
+            D = 'd' D | 'e'
+            ",
+        );
+        assert!(grammar.has_epsilon_cycles());
+        assert_eq!(grammar.epsilon_cycles(), [vec!["A", "B"], vec!["C"]]);
+
+        let report = grammar.validate();
+        assert!(report
+            .errors
+            .contains(&ValidationError::EpsilonCycle { rules: vec!["C"] }));
+        assert_eq!(
+            ValidationError::EpsilonCycle {
+                rules: vec!["A", "B"]
+            }
+            .to_string(),
+            "rules `A`, `B` derive themselves through empty strings"
+        );
+
+        assert!(!self::grammar("S = A B\nA = 'a'?\nB = 'b' S?").has_epsilon_cycles());
+    }
+
+    #[test]
+    fn test_livelock_check() {
+        let grammar = grammar(
+            "
+            S = A | 'x'
+            A = B 'a'
+            B = C | A
+            C = 'c'? D
+            D = D
+            ",
+        );
+        assert_eq!(grammar.livelock_check(), Some(vec!["A", "B"]));
+        assert!(grammar
+            .validate()
+            .errors
+            .contains(&ValidationError::Livelock {
+                rules: vec!["A", "B"]
+            }));
+        assert_eq!(
+            ValidationError::Livelock { rules: vec!["D"] }.to_string(),
+            "rule `D` only expands into itself and never reaches a terminal"
+        );
+
+        // Unproductive only because of an undefined rule
+        assert_eq!(
+            self::grammar("S = A\nA = 'a' Missing").livelock_check(),
+            None
+        );
+        assert_eq!(self::grammar("S = 'a' S | 'b'").livelock_check(), None);
+    }
+
+    #[test]
+    fn test_is_regular() {
+        let regularity = grammar("S = 'a' S | 'b' A\nA = ('c' 'd')* 'e'? B?\nB = 'f'").is_regular();
+        assert_eq!(regularity, RegularityResult::Regular(Linearity::Right));
+
+        let regularity = grammar("S = S 'a' | A 'b'\nA = 'c'+ | A? 'd'").is_regular();
+        assert_eq!(regularity, RegularityResult::Regular(Linearity::Left));
+
+        // Every rule is linear on its own, but not on the same side
+        let regularity = grammar("S = 'a' A\nA = A 'b' | 'c'").is_regular();
+        assert_eq!(
+            regularity,
+            RegularityResult::NotRegular {
+                counterexample_rule: "A".to_string()
+            }
+        );
+
+        let regularity = grammar("S = 'a' A\nA = '(' A ')' | 'x'").is_regular();
+        assert_eq!(
+            regularity,
+            RegularityResult::NotRegular {
+                counterexample_rule: "A".to_string()
+            }
+        );
+
+        let regularity = grammar("S = 'a'* 'b'").is_regular();
+        assert_eq!(regularity, RegularityResult::Regular(Linearity::Right));
+    }
+
+    #[test]
+    fn test_left_recursive_rules() {
+        let grammar = grammar(
+            "
+            Expr = Expr '+' Term | Term
+            Term = 'n'
+            A = B 'x'
+            B = C? A 'y' | 'b'
+            C = 'c'
+            D = 'd' D
+            ",
+        );
+
+        assert_eq!(
+            grammar.left_recursive_rules(),
+            IndexMap::from([
+                ("Expr", LeftRecursion::Direct),
+                ("A", LeftRecursion::Indirect),
+                ("B", LeftRecursion::Indirect),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_undefined_rules() {
+        let grammar = grammar(
+            "
+            S = A B* | (C 'x')?
+            A = 'a' D
+            ",
+        );
+
+        assert_eq!(grammar.undefined_rules(), IndexSet::from(["B", "C", "D"]));
+    }
+
+    #[test]
+    fn test_unreachable_rules() {
+        let grammar = grammar(
+            "
+            S = A | Missing
+            A = 'a' B?
+            B = 'b'
+            C = D
+            D = 'd' C
+            ",
+        );
+
+        assert_eq!(grammar.unreachable_rules("S"), IndexSet::from(["C", "D"]));
+        assert_eq!(
+            grammar.unreachable_rules("C"),
+            IndexSet::from(["S", "A", "B"])
+        );
+        assert_eq!(grammar.unreachable_rules("Missing").len(), 5);
+    }
+
+    #[test]
+    fn test_rule_dependencies() {
+        let grammar = grammar("S = A B | A 'x'\nA = 'a' A?\nB = Missing");
+        let dependencies = grammar.rule_dependencies();
+        assert_eq!(dependencies["S"], IndexSet::from(["A", "B"]));
+        assert_eq!(dependencies["A"], IndexSet::from(["A"]));
+        assert_eq!(dependencies["B"], IndexSet::from(["Missing"]));
+
+        assert_eq!(
+            grammar.rule_dependency_graph(),
+            [("S", "A"), ("S", "B"), ("A", "A"), ("B", "Missing")]
+        );
+    }
+
+    #[test]
+    fn test_topo_sort() {
+        let grammar = grammar(
+            "
+            S = A B | C
+            A = 'a' C
+            B = C 'b' | Missing
+            C = 'c'
+            D = S
+            ",
+        );
+        assert_eq!(grammar.topo_sort("S"), Ok(vec!["C", "A", "B", "S"]));
+        assert_eq!(grammar.topo_sort("C"), Ok(vec!["C"]));
+        assert_eq!(grammar.topo_sort("Missing"), Ok(vec![]));
+
+        let cyclic = self::grammar(
+            "
+            S = A 'x'
+            A = 'a' B | L
+            B = 'b' A
+            L = L 'l' | 'l'
+            ",
+        );
+        assert_eq!(cyclic.topo_sort("S"), Err(vec!["A", "B", "L"]));
+        assert_eq!(cyclic.topo_sort("L"), Err(vec!["L"]));
+    }
+
+    #[test]
+    fn test_reachable_rules() {
+        let grammar = grammar(
+            "
+            S = A | Missing
+            A = 'a' B? 'c'
+            B = 'b' A
+            C = D
+            D = 'd' C 'a'
+            ",
+        );
+
+        assert_eq!(grammar.reachable_rules("B"), IndexSet::from(["A", "B"]));
+        assert_eq!(grammar.reachable_rules("C"), IndexSet::from(["C", "D"]));
+        assert!(grammar.reachable_rules("Missing").is_empty());
+
+        assert_eq!(
+            grammar.reachable_terminals("S"),
+            IndexSet::from(["a", "c", "b"])
+        );
+        assert_eq!(grammar.reachable_terminals("C"), IndexSet::from(["d", "a"]));
+    }
+
+    #[test]
+    fn test_unproductive_rules() {
+        let grammar = grammar(
+            "
+            S = A | B
+            A = 'a' A | 'a'
+            B = 'b' B
+            C = D
+            D = C | Missing
+            E = C? 'e'
+            ",
+        );
+
+        assert_eq!(
+            grammar.unproductive_rules(),
+            IndexSet::from(["B", "C", "D"])
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let grammar = grammar(
+            "
+            S = A | 'a' B
+            A = A 'x' | 'a'
+            B = B 'b'
+            C = Missing
+            ",
+        );
+
+        let report = grammar.validate();
+        assert!(!report.is_ok());
+        assert_eq!(
+            report.errors,
+            [
+                ValidationError::UndefinedRule { rule: "Missing" },
+                ValidationError::UnproductiveRule { rule: "B" },
+                ValidationError::UnproductiveRule { rule: "C" },
+                ValidationError::Livelock { rules: vec!["B"] },
+                ValidationError::LeftRecursion {
+                    rule: "A",
+                    kind: LeftRecursion::Direct
+                },
+                ValidationError::LeftRecursion {
+                    rule: "B",
+                    kind: LeftRecursion::Direct
+                },
+            ]
+        );
+        assert_eq!(
+            report.warnings,
+            [ValidationWarning::UnreachableRule { rule: "C" }]
+        );
+        assert_eq!(
+            report.errors[0].to_string(),
+            "rule `Missing` is referenced but never defined"
+        );
+        assert_eq!(report.exit_code(false), 1);
+
+        let json = serde_json::from_str::<serde_json::Value>(&report.to_json()).unwrap();
+        assert_eq!(json["errors"].as_array().unwrap().len(), 6);
+        assert_eq!(json["errors"][0]["rule"], "Missing");
+        assert_eq!(
+            json["warnings"][0]["message"],
+            "rule `C` is unreachable from the start rule"
+        );
+    }
+
+    #[test]
+    fn test_validate_left_recursive() {
+        let grammar = grammar("S = A 'x' | 'y'\nA = A 'a' | B?\nB = 'b'");
+        let report = grammar.validate();
+        assert_eq!(
+            report.errors,
+            [ValidationError::LeftRecursion {
+                rule: "A",
+                kind: LeftRecursion::Direct
+            }]
+        );
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_allow_left_recursion() {
+        let source = "
+            S = E | L
+            // @allow_left_recursion
+            E = E '+' 'n' | 'n' # @allow_left_recursion
+            L = L 'x' | 'y'
+        ";
+        for options in [
+            ParseOptions {
+                full_fidelity: true,
+                ..Default::default()
+            },
+            ParseOptions {
+                preserve_trivia: true,
+                ..Default::default()
+            },
+        ] {
+            let mut parser = Parser::new_with_options(source, options);
+            parser.parse();
+            let grammar = GrammarBuilder::new(source, parser.tree()).build();
+            assert_eq!(grammar.allowed_left_recursion, IndexSet::from(["E"]));
+            assert_eq!(
+                grammar.validate().errors,
+                [ValidationError::LeftRecursion {
+                    rule: "L",
+                    kind: LeftRecursion::Direct
+                }]
+            );
+        }
+
+        // Comments are dropped by default
+        assert!(grammar(source).allowed_left_recursion.is_empty());
+    }
+
+    #[test]
+    fn test_validate_exit_code() {
+        let clean = grammar("S = 'a' B\nB = 'b'");
+        assert_eq!(clean.validate().exit_code(true), 0);
+        assert_eq!(clean.validate().to_json(), r#"{"errors":[],"warnings":[]}"#);
+
+        let unreachable = grammar("S = 'a'\nB = 'b'");
+        assert_eq!(unreachable.validate().exit_code(false), 2);
+        assert_eq!(unreachable.validate().exit_code(true), 1);
+    }
+
+    #[test]
+    fn test_validate_ll1_conflicts() {
+        let grammar = grammar("S = 'a' 'b' | 'a' 'c'");
+
+        let report = grammar.validate();
+        assert!(report.is_ok());
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].rule(), "S");
+        assert_eq!(
+            report.warnings[0].to_string(),
+            "In rule 'S', alternatives 0 and 1 both predict token 'a': branch 0 starts with 'a' \
+             'b' and branch 1 starts with 'a' 'c'."
+        );
+        assert_eq!(
+            report.to_json(),
+            r#"{"errors":[],"warnings":[{"rule":"S","message":"In rule 'S', alternatives 0 and 1 both predict token 'a': branch 0 starts with 'a' 'b' and branch 1 starts with 'a' 'c'."}]}"#
+        );
+    }
+
+    #[test]
+    fn test_one_or_more() {
+        let grammar = grammar(
+            "
+            S = 'a'+ ('b' C)+
+            C = 'c'?
+            D = C+
+            ",
+        );
+
+        assert_eq!(
+            grammar.rules["S"],
+            Expr::Sequence(vec![
+                Expr::OneOrMore(Box::new(Expr::Literal("a"))),
+                Expr::OneOrMore(Box::new(Expr::Sequence(vec![
+                    Expr::Literal("b"),
+                    Expr::Rule("C"),
+                ]))),
+            ])
+        );
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["a"]));
+        assert_eq!(grammar.nullable_set(), IndexSet::from(["C", "D"]));
+    }
+
+    #[test]
+    fn test_one_or_more_sets() {
+        let grammar = grammar(
+            "
+            S = Item+ ';'
+            Item = 'a' | 'b' Opt
+            Opt = 'c'?
+            L = Opt+ 'd'
+            ",
+        );
+
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["a", "b"]));
+        assert_eq!(grammar.first_set("L"), IndexSet::from(["c", "d"]));
+
+        assert_eq!(grammar.follow_set("Item"), IndexSet::from(["a", "b", ";"]));
+        assert_eq!(grammar.follow_set_strict("Item"), IndexSet::from([";"]));
+        assert_eq!(
+            grammar.follow_set("Opt"),
+            IndexSet::from(["a", "b", ";", "c", "d"])
+        );
+    }
+
+    #[test]
+    fn test_to_bnf_string() {
+        let source = "
+            S = A ('b' | C)* 'd'+
+            A = 'a'? | ('x' 'y') 'z'
+            C = 'c'
+            ";
+        let grammar = grammar(source);
+
+        let bnf = grammar.to_bnf_string();
+        assert_eq!(
+            bnf,
+            "<S> ::= <A> ( 'b' | <C> )* ( 'd' )+\n\
+             <A> ::= ( 'a' | ε ) | ( 'x' 'y' ) 'z'\n\
+             <C> ::= 'c'\n"
+        );
+
+        // Map the BNF back into ungram notation to parse it again
+        let ungram = bnf
+            .replace("::=", "=")
+            .replace(" | ε )", " )?")
+            .replace(['<', '>'], "");
+        assert!(grammar.rules_eq(&self::grammar(&ungram)));
+        assert!(!grammar.rules_eq(&self::grammar("S = 'a'")));
+    }
+
+    #[test]
+    fn test_to_ebnf_string() {
+        let cases = [
+            ("S = 'a'\n", "literal"),
+            ("S = A B\n", "sequence"),
+            ("S = A | 'b' C\n", "choice"),
+            ("S = 'a'? B?\n", "optional"),
+            ("S = 'a'* B*\n", "repeat"),
+            ("S = 'a'+ B+\n", "one or more"),
+            ("S = ('a' | B)? ('c' D)*\n", "grouped postfix"),
+            ("S = ('a' B) | (C)\n", "group"),
+            ("S = (('a' | B)* C)+ '\\''\n", "nested"),
+        ];
+
+        for (source, construct) in cases {
+            let grammar = grammar(source);
+            let ebnf = grammar.to_ebnf_string();
+            assert_eq!(ebnf, source, "{construct}");
+            assert_eq!(self::grammar(&ebnf).rules, grammar.rules, "{construct}");
+        }
+    }
+
+    #[test]
+    fn test_expr_display() {
+        let body = "'a' (B | C)* D? | 'e'+ ('f' G)";
+        let source = format!("S = {body}");
+        let grammar = grammar(&source);
+
+        let expr = &grammar.rules["S"];
+        assert_eq!(expr.to_string(), body);
+        let kinds = |source: &str| {
+            Lexer::<1>::new(source)
+                .map(|token| token.kind)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(kinds(&expr.to_string()), kinds(body));
+
+        assert_eq!(Expr::Literal("a").to_string(), "'a'");
+        assert_eq!(
+            Expr::Optional(Box::new(Expr::Sequence(vec![
+                Expr::Rule("A"),
+                Expr::Rule("B")
+            ])))
+            .to_string(),
+            "(A B)?"
+        );
+    }
+
+    #[test]
+    fn test_expr_terminals() {
+        let grammar = grammar("S = 'a' (B | 'c')* 'a' | (B? 'd')+ C");
+        let expr = &grammar.rules["S"];
+        assert_eq!(expr.terminals().collect::<Vec<_>>(), ["a", "c", "d"]);
+        assert_eq!(expr.non_terminals().collect::<Vec<_>>(), ["B", "C"]);
+        assert_eq!(Expr::Rule("A").terminals().count(), 0);
+    }
+
+    #[test]
+    fn test_expr_depth() {
+        assert_eq!(Expr::Literal("a").depth(), 0);
+        assert_eq!(Expr::Literal("a").size(), 1);
+        assert_eq!(Expr::Repeat(Box::new(Expr::Rule("A"))).depth(), 1);
+        assert_eq!(Expr::Sequence(vec![]).depth(), 1);
+
+        // Choice [Sequence ['a', Repeat (Sequence [B, Optional C])], Sequence [D]]
+        let grammar = grammar("S = 'a' (B C?)* | D");
+        let expr = &grammar.rules["S"];
+        assert_eq!(expr.depth(), 5);
+        assert_eq!(expr.size(), 10);
+    }
+
+    #[test]
+    fn test_expr_contains() {
+        let rule = Expr::Rule("A");
+        let literal = Expr::Literal("a");
+        assert!(rule.contains_rule("A") && !rule.contains_rule("B"));
+        assert!(!rule.contains_literal("A"));
+        assert!(literal.contains_literal("a") && !literal.contains_literal("b"));
+        assert!(!literal.contains_rule("a"));
+
+        let sequence = Expr::Sequence(vec![Expr::Literal("b"), rule.clone()]);
+        let choice = Expr::Choice(vec![Expr::Rule("B"), literal.clone()]);
+        assert!(sequence.contains_rule("A") && sequence.contains_literal("b"));
+        assert!(!sequence.contains_rule("B") && !sequence.contains_literal("a"));
+        assert!(choice.contains_rule("B") && choice.contains_literal("a"));
+        assert!(!choice.contains_rule("A") && !choice.contains_literal("b"));
+
+        for wrap in [Expr::Optional, Expr::Repeat, Expr::OneOrMore] {
+            let expr = wrap(Box::new(choice.clone()));
+            assert!(expr.contains_rule("B") && expr.contains_literal("a"));
+            assert!(!expr.contains_rule("A") && !expr.contains_literal("b"));
+        }
+    }
+
+    #[test]
+    fn test_sort_alternatives() {
+        let mut grammar = grammar("S = C | 'b' (Z | 'y' | X)* | A\nT = 't'");
+        grammar.sort_alternatives();
+        assert_eq!(
+            grammar.to_ebnf_string(),
+            "S = 'b' ('y' | X | Z)* | A | C\nT = 't'\n"
+        );
+    }
+
+    #[test]
+    fn test_to_dot_string() {
+        let grammar = grammar(
+            "
+            S = A ('b' | C)*
+            A = 'a'?
+            C = '\"'
+            D = 'd'
+            ",
+        );
+
+        assert_eq!(
+            grammar.to_dot_string_from("S"),
+            r#"digraph grammar {
+  "S" [label="S", shape=box, style=bold, color=navy];
+  n0 [label="seq", shape=box];
+  n0 -> "A" [label="0"];
+  n1 [label="*", shape=ellipse];
+  n2 [label="|", shape=diamond];
+  n3 [label="'b'", shape=plaintext, fontcolor=darkgreen];
+  n2 -> n3;
+  n2 -> "C";
+  n1 -> n2;
+  n0 -> n1 [label="1"];
+  "S" -> n0;
+  "A" [label="A", shape=box, style=bold, color=navy];
+  n4 [label="?", shape=ellipse];
+  n5 [label="'a'", shape=plaintext, fontcolor=darkgreen];
+  n4 -> n5;
+  "A" -> n4;
+  "C" [label="C", shape=box, style=bold, color=navy];
+  n6 [label="'\"'", shape=plaintext, fontcolor=darkgreen];
+  "C" -> n6;
+}
+"#
+        );
+        assert!(grammar.to_dot_string().contains(r#""D" -> n"#));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let grammar = grammar(
+            r#"
+            S = A ('b' | C)* 'd'+
+            A = '"'?
+            C = '\\'
+            "#,
+        );
+
+        let json = grammar.to_json();
+        assert_eq!(
+            json,
+            r#"{"S":{"type":"Sequence","items":[{"type":"Rule","name":"A"},{"type":"Repeat","expr":{"type":"Choice","branches":[{"type":"Sequence","items":[{"type":"Literal","value":"b"}]},{"type":"Sequence","items":[{"type":"Rule","name":"C"}]}]}},{"type":"OneOrMore","expr":{"type":"Literal","value":"d"}}]},"A":{"type":"Sequence","items":[{"type":"Optional","expr":{"type":"Literal","value":"\""}}]},"C":{"type":"Sequence","items":[{"type":"Literal","value":"\\\\"}]}}"#
+        );
+
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(value["A"]["items"][0]["expr"]["value"], "\"");
+        assert_eq!(value["C"]["items"][0]["value"], "\\\\");
+    }
+
+    #[test]
+    fn test_into_owned() {
+        let source = String::from("S = 'a' (B | 'c')*\nB = 'b'?");
+        let owned = grammar(&source).clone().into_owned();
+        let expected = format!("{:#?}", grammar(&source));
+        drop(source);
+
+        assert_eq!(format!("{owned:#?}"), expected);
+        assert!(owned.rules_eq(&owned.clone()));
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = "S = A B\nA = 'a'";
+        let extra = "B = 'b'\nC = 'c'";
+        let conflicting = "B = 'x'\nA = 'y'";
+
+        let merged = grammar(base).merge(grammar(extra)).unwrap();
+        assert!(merged.rules_eq(&grammar("S = A B\nA = 'a'\nB = 'b'\nC = 'c'")));
+        assert_eq!(
+            merged.rules.keys().copied().collect::<Vec<_>>(),
+            ["S", "A", "B", "C"]
+        );
+
+        assert_eq!(
+            grammar(base).merge(grammar(conflicting)).unwrap_err(),
+            MergeError::DuplicateRule { name: "A" }
+        );
+
+        let merged = grammar(base).merge_override(grammar(conflicting));
+        assert!(merged.rules_eq(&grammar("S = A B\nA = 'y'\nB = 'x'")));
+        assert_eq!(
+            merged.rules.keys().copied().collect::<Vec<_>>(),
+            ["S", "A", "B"]
+        );
+    }
+
+    #[test]
+    fn test_rule_mutation() {
+        let mut grammar = grammar("S = 'a' S?\nT = 't'");
+        grammar.add_rule("B", Expr::Literal("b")).unwrap();
+        assert_eq!(
+            grammar.add_rule("T", Expr::Literal("x")),
+            Err(DuplicateRuleError { name: "T" })
+        );
+        grammar.rules["S"] = Expr::Sequence(vec![Expr::Rule("B"), Expr::Rule("S")]);
+
+        assert_eq!(grammar.rename_rule("B", "Bee"), Ok(()));
+        assert_eq!(grammar.rename_rule("S", "Start"), Ok(()));
+        assert_eq!(grammar.rename_rule("B", "C"), Err(RenameError::UnknownRule));
+        assert_eq!(
+            grammar.rename_rule("T", "Bee"),
+            Err(RenameError::AlreadyExists)
+        );
+        assert!(grammar.rules_eq(&self::grammar("Start = Bee Start\nT = 't'\nBee = 'b'")));
+        assert_eq!(
+            grammar.rules.keys().copied().collect::<Vec<_>>(),
+            ["Start", "T", "Bee"]
+        );
+
+        assert_eq!(
+            grammar.remove_rule("T"),
+            Some(Expr::Sequence(vec![Expr::Literal("t")]))
+        );
+        assert_eq!(grammar.remove_rule("T"), None);
+        assert_eq!(
+            grammar.rules.keys().copied().collect::<Vec<_>>(),
+            ["Start", "Bee"]
+        );
+    }
+
+    #[test]
+    fn test_set_cache() {
+        let mut grammar = grammar("S = A 'x'\nA = 'a'");
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["a"]));
+        assert_eq!(grammar.follow_set("A"), IndexSet::from(["x"]));
+
+        grammar.remove_rule("A");
+        grammar
+            .add_rule("A", Expr::Sequence(vec![Expr::Literal("b")]))
+            .unwrap();
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["b"]));
+
+        grammar.rename_rule("A", "B").unwrap();
+        assert_eq!(grammar.follow_set("B"), IndexSet::from(["x"]));
+        assert!(grammar.follow_set("A").is_empty());
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["b"]));
+
+        // Direct changes to the rules need the cache to be cleared by hand
+        grammar.rules["B"] = Expr::Sequence(vec![Expr::Literal("c")]);
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["b"]));
+        grammar.clear_cache();
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["c"]));
+    }
+
+    #[test]
+    fn test_substitute_rule() {
+        let mut grammar = grammar("Number = Digit Digit*\nDigit = '0' | '1'");
+        let digit = Expr::Choice(vec![Expr::Literal("0"), Expr::Literal("1")]);
+        grammar.substitute_rule("Digit", &digit);
+
+        assert_eq!(
+            grammar.rules["Number"],
+            Expr::Sequence(vec![digit.clone(), Expr::Repeat(Box::new(digit))])
+        );
+        assert!(grammar.rules.contains_key("Digit"));
+
+        // Recursive rules are unrolled one level
+        let mut grammar = self::grammar("List = 'x' List?");
+        let body = grammar.rules["List"].clone();
+        grammar.substitute_rule("List", &body);
+        assert!(grammar.rules_eq(&self::grammar("List = 'x' ('x' List?)?")));
+    }
+
+    #[test]
+    fn test_start_symbol() {
+        let mut grammar = grammar("A = B\nB = C\nC = 'c'\nD = 'd'");
+        assert_eq!(grammar.start_symbol(), Some("A"));
+        assert_eq!(grammar.set_start_symbol("E"), Err(UnknownRuleError));
+
+        grammar.set_start_symbol("C").unwrap();
+        assert_eq!(grammar.start_symbol(), Some("C"));
+        assert_eq!(
+            grammar.rules.keys().copied().collect::<Vec<_>>(),
+            ["C", "A", "B", "D"]
+        );
+        let (name, expr) = grammar.first_rule().unwrap();
+        assert_eq!(name, "C");
+        assert!(expr.contains_literal("c"));
+
+        assert!(Grammar::new(IndexMap::new()).start_symbol().is_none());
+    }
+
+    #[test]
+    fn test_build_skips_errors() {
+        let grammar = grammar("A = 'a'\n= 'b'\nC = ('c' $ | D)\nE =\nF = 'f'");
+
+        assert_eq!(
+            grammar.rules.keys().copied().collect::<Vec<_>>(),
+            ["A", "C", "E", "F"]
+        );
+        assert_eq!(
+            grammar.rules["C"],
+            Expr::Sequence(vec![Expr::Sequence(vec![Expr::Literal("c")])])
+        );
+        assert_eq!(grammar.rules["E"], Expr::Sequence(vec![]));
+    }
+
+    #[test]
+    fn test_build_with_diagnostics() {
+        let source = "A = 'a'\nB = 'b'";
+        let tree = Tree {
+            kind: Kind::Grammar,
+            children: vec![
+                Child::Tree(Tree {
+                    kind: Kind::Rule,
+                    children: vec![
+                        Child::Token(Token::new(Span::new(0, 1), token::Kind::Ident)),
+                        Child::Token(Token::new(Span::new(4, 7), token::Kind::Literal)),
+                    ],
+                }),
+                Child::Token(Token::new(Span::new(8, 9), token::Kind::Ident)),
+                Child::Tree(Tree {
+                    kind: Kind::Rule,
+                    children: vec![
+                        Child::Token(Token::new(Span::new(8, 9), token::Kind::Ident)),
+                        Child::Tree(Tree {
+                            kind: Kind::Optional,
+                            children: vec![],
+                        }),
+                    ],
+                }),
+            ],
+        };
+
+        let (grammar, diagnostics) = GrammarBuilder::new(source, tree).build_with_diagnostics();
+        assert_eq!(grammar.rules.keys().copied().collect::<Vec<_>>(), ["A"]);
+        assert_eq!(
+            diagnostics,
+            [
+                Diagnostic::error(Span::new(8, 9), "unexpected token identifier"),
+                Diagnostic::error(Span::new(0, 0), "empty Optional"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_owned() {
+        fn read() -> Grammar<'static> {
+            let source = String::from("S = A 'x'\nA = 'a' | 'b'");
+            GrammarBuilder::build_owned(source)
         }
+
+        let grammar = read();
+        assert!(grammar.rules_eq(&self::grammar("S = A 'x'\nA = 'a' | 'b'")));
+        assert_eq!(grammar.first_set("S"), IndexSet::from(["a", "b"]));
+    }
+
+    #[test]
+    fn test_build_checked() {
+        let build = |source| {
+            let mut parser = Parser::new(source);
+            parser.parse();
+            GrammarBuilder::new(source, parser.tree()).build_checked()
+        };
+
+        let grammar = build("A = 'a'\nB = A 'b'").unwrap();
+        assert_eq!(
+            grammar.rules.keys().copied().collect::<Vec<_>>(),
+            ["A", "B"]
+        );
+
+        assert_eq!(
+            build("A = 'a'\nB = 'b'\nA = 'c'").err(),
+            Some(vec![BuildError::DuplicateRule { name: "A" }])
+        );
+        assert_eq!(
+            build("B 'b'\nC = 'c'").err(),
+            Some(vec![BuildError::SyntaxError { name: "B" }])
+        );
     }
 }
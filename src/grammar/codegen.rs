@@ -0,0 +1,335 @@
+//! Parser skeletons generated from a grammar, meant as a starting point to build on
+
+use indexmap::IndexSet;
+
+use super::{Expr, Grammar};
+
+const RECURSIVE_DESCENT_HEADER: &str = "\
+// Recursive descent parser generated by ungram, a starting point to build on.
+// Branches are chosen by the PREDICT sets of the grammar
+
+/// Input of the parser, implemented by the harness
+pub trait Tokens {
+    /// Text of the next terminal, `\"$\"` at the end of the input
+    fn peek(&self) -> &str;
+    /// Consumes the next terminal, which should be `expected`
+    fn expect(&mut self, expected: &str);
+    /// Called when the next terminal starts none of the `expected` alternatives
+    fn error(&mut self, expected: &[&str]);
+}
+";
+
+const COMBINATOR_HEADER: &str = "\
+// Parser combinators generated by ungram, a starting point to build on
+
+/// Combinators implemented by the harness
+pub trait Combinators: Sized {
+    fn token(text: &'static str) -> Self;
+    /// Defers to the parser of another rule, so rules may be recursive
+    fn rule(parser: fn() -> Self) -> Self;
+    fn seq(parsers: Vec<Self>) -> Self;
+    fn any_of(parsers: Vec<Self>) -> Self;
+    fn optional(parser: Self) -> Self;
+    fn many(parser: Self) -> Self;
+    fn many1(parser: Self) -> Self;
+}
+";
+
+/// Writes the body of one `parse_*` function at a time
+struct RecursiveDescent<'g, 'src> {
+    grammar: &'g Grammar<'src>,
+    nullable: IndexSet<&'src str>,
+    /// Rule being generated
+    rule: &'src str,
+    out: String,
+}
+
+impl<'src> Grammar<'src> {
+    /// Rust source with a `parse_<rule>` function per rule, rule names in snake case. The
+    /// functions read the input through a `Tokens` trait the caller implements.
+    ///
+    /// Like [`Grammar::predict_set`] it panics when a referenced rule is not defined, and the
+    /// functions of left recursive rules never return
+    pub fn to_recursive_descent_string(&self) -> String {
+        let mut generator = RecursiveDescent {
+            grammar: self,
+            nullable: self.nullable_set(),
+            rule: "",
+            out: String::from(RECURSIVE_DESCENT_HEADER),
+        };
+        for (name, expr) in &self.rules {
+            generator.rule = name;
+            generator.out.push_str(&format!(
+                "\npub fn parse_{}(p: &mut impl Tokens) {{\n",
+                snake_case(name)
+            ));
+            match expr {
+                Expr::Choice(branches) => {
+                    let arms = (0..branches.len())
+                        .map(|i| self.predict_set_branch(name, i))
+                        .collect();
+                    generator.write_choice(branches, arms, None, 1);
+                }
+                expr => generator.write_expr(expr, 1),
+            }
+            generator.out.push_str("}\n");
+        }
+        generator.out
+    }
+
+    /// Rust source with a `parse_<rule>` function per rule building the parser out of the
+    /// combinators of a `Combinators` trait the caller implements
+    pub fn to_parser_combinator_string(&self) -> String {
+        let mut out = String::from(COMBINATOR_HEADER);
+        for (name, expr) in &self.rules {
+            out.push_str(&format!(
+                "\npub fn parse_{}<P: Combinators>() -> P {{\n    {}\n}}\n",
+                snake_case(name),
+                combinator(expr)
+            ));
+        }
+        out
+    }
+}
+
+impl<'src> RecursiveDescent<'_, 'src> {
+    fn line(&mut self, indent: usize, text: &str) {
+        self.out.push_str(&"    ".repeat(indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Terminals that may start `expr`
+    fn first(&self, expr: &Expr<'src>) -> IndexSet<&'src str> {
+        let mut set = self
+            .grammar
+            .first_set_impl(expr, &mut IndexSet::from([self.rule]));
+        set.swap_remove("ε");
+        set
+    }
+
+    fn write_expr(&mut self, expr: &Expr<'src>, indent: usize) {
+        match expr {
+            Expr::Literal(lit) => self.line(indent, &format!("p.expect({lit:?});")),
+            Expr::Rule(rule) => self.line(indent, &format!("parse_{}(p);", snake_case(rule))),
+            Expr::Sequence(exprs) => {
+                for expr in exprs {
+                    self.write_expr(expr, indent);
+                }
+            }
+            Expr::Choice(branches) => {
+                let arms = branches.iter().map(|branch| self.first(branch)).collect();
+                // Nested choices know nothing about what follows, an empty alternative is
+                // taken whenever no other one matches
+                let fallback = branches
+                    .iter()
+                    .position(|branch| branch.derives_empty(&self.nullable));
+                self.write_choice(branches, arms, fallback, indent);
+            }
+            Expr::Optional(expr) => {
+                let condition = matches_any(&self.first(expr));
+                self.line(indent, &format!("if {condition} {{"));
+                self.write_expr(expr, indent + 1);
+                self.line(indent, "}");
+            }
+            Expr::Repeat(expr) => {
+                let condition = matches_any(&self.first(expr));
+                self.line(indent, &format!("while {condition} {{"));
+                self.write_expr(expr, indent + 1);
+                self.line(indent, "}");
+            }
+            Expr::OneOrMore(expr) => {
+                let condition = matches_any(&self.first(expr));
+                self.line(indent, "loop {");
+                self.write_expr(expr, indent + 1);
+                self.line(indent + 1, &format!("if !{condition} {{"));
+                self.line(indent + 2, "break;");
+                self.line(indent + 1, "}");
+                self.line(indent, "}");
+            }
+        }
+    }
+
+    /// Writes a `match` on the next terminal with an arm per branch. Terminals predicting more
+    /// than one branch go to the first one, the others are left as comments
+    fn write_choice(
+        &mut self,
+        branches: &[Expr<'src>],
+        arms: Vec<IndexSet<&'src str>>,
+        fallback: Option<usize>,
+        indent: usize,
+    ) {
+        let expected = arms.iter().flatten().copied().collect::<IndexSet<_>>();
+        let mut taken = IndexSet::new();
+
+        self.line(indent, "match p.peek() {");
+        for (i, (branch, arm)) in branches.iter().zip(arms).enumerate() {
+            if Some(i) == fallback {
+                continue;
+            }
+            let (conflicts, terminals) = arm
+                .into_iter()
+                .partition::<Vec<_>, _>(|terminal| taken.contains(terminal));
+            if !conflicts.is_empty() {
+                self.line(
+                    indent + 1,
+                    &format!("// LL(1) conflict, {conflicts:?} also predict: {branch}"),
+                );
+            }
+            if terminals.is_empty() {
+                continue;
+            }
+            taken.extend(terminals.iter().copied());
+
+            let pattern = terminals
+                .iter()
+                .map(|terminal| format!("{terminal:?}"))
+                .collect::<Vec<_>>()
+                .join(" | ");
+            self.line(indent + 1, &format!("{pattern} => {{"));
+            self.write_expr(branch, indent + 2);
+            self.line(indent + 1, "}");
+        }
+        match fallback {
+            Some(fallback) => {
+                self.line(indent + 1, "_ => {");
+                self.write_expr(&branches[fallback], indent + 2);
+                self.line(indent + 1, "}");
+            }
+            None => {
+                let expected = expected
+                    .iter()
+                    .map(|terminal| format!("{terminal:?}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                self.line(indent + 1, &format!("_ => p.error(&[{expected}]),"));
+            }
+        }
+        self.line(indent, "}");
+    }
+}
+
+/// `matches!` on the next terminal, `false` when nothing may start the expression
+fn matches_any(terminals: &IndexSet<&str>) -> String {
+    if terminals.is_empty() {
+        return "false".to_string();
+    }
+    let pattern = terminals
+        .iter()
+        .map(|terminal| format!("{terminal:?}"))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    format!("matches!(p.peek(), {pattern})")
+}
+
+fn combinator(expr: &Expr) -> String {
+    let list = |exprs: &[Expr]| exprs.iter().map(combinator).collect::<Vec<_>>().join(", ");
+    match expr {
+        Expr::Literal(lit) => format!("P::token({lit:?})"),
+        Expr::Rule(rule) => format!("P::rule(parse_{}::<P>)", snake_case(rule)),
+        Expr::Sequence(exprs) if exprs.len() == 1 => combinator(&exprs[0]),
+        Expr::Sequence(exprs) => format!("P::seq(vec![{}])", list(exprs)),
+        Expr::Choice(branches) => format!("P::any_of(vec![{}])", list(branches)),
+        Expr::Optional(expr) => format!("P::optional({})", combinator(expr)),
+        Expr::Repeat(expr) => format!("P::many({})", combinator(expr)),
+        Expr::OneOrMore(expr) => format!("P::many1({})", combinator(expr)),
+    }
+}
+
+/// `ParamList` becomes `param_list` and `HTTPHeader` becomes `http_header`
+pub(super) fn snake_case(name: &str) -> String {
+    let chars = name.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let previous = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_lowercase());
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use expect_test::expect;
+
+    use crate::grammar::test::grammar;
+
+    use super::{snake_case, COMBINATOR_HEADER, RECURSIVE_DESCENT_HEADER};
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("ParamList"), "param_list");
+        assert_eq!(snake_case("HTTPHeader"), "http_header");
+        assert_eq!(snake_case("S"), "s");
+        assert_eq!(snake_case("Expr2Tail"), "expr2_tail");
+        assert_eq!(snake_case("A_tail"), "a_tail");
+    }
+
+    #[test]
+    fn test_to_recursive_descent_string() {
+        let grammar = grammar("S = Item+ '#'\nItem = 'a' ('b' | 'c'?) | 'd'* 'e'");
+        let code = grammar.to_recursive_descent_string();
+        expect![[r##"
+
+            pub fn parse_s(p: &mut impl Tokens) {
+                loop {
+                    parse_item(p);
+                    if !matches!(p.peek(), "a" | "d" | "e") {
+                        break;
+                    }
+                }
+                p.expect("#");
+            }
+
+            pub fn parse_item(p: &mut impl Tokens) {
+                match p.peek() {
+                    "a" => {
+                        p.expect("a");
+                        match p.peek() {
+                            "b" => {
+                                p.expect("b");
+                            }
+                            _ => {
+                                if matches!(p.peek(), "c") {
+                                    p.expect("c");
+                                }
+                            }
+                        }
+                    }
+                    "d" | "e" => {
+                        while matches!(p.peek(), "d") {
+                            p.expect("d");
+                        }
+                        p.expect("e");
+                    }
+                    _ => p.error(&["a", "d", "e"]),
+                }
+            }
+        "##]]
+        .assert_eq(&code[RECURSIVE_DESCENT_HEADER.len()..]);
+    }
+
+    #[test]
+    fn test_to_parser_combinator_string() {
+        let grammar = grammar("S = Item+ '#'\nItem = 'a' ('b' | 'c'?) | 'd'* 'e'");
+        let code = grammar.to_parser_combinator_string();
+        expect![[r##"
+
+            pub fn parse_s<P: Combinators>() -> P {
+                P::seq(vec![P::many1(P::rule(parse_item::<P>)), P::token("#")])
+            }
+
+            pub fn parse_item<P: Combinators>() -> P {
+                P::any_of(vec![P::seq(vec![P::token("a"), P::any_of(vec![P::token("b"), P::optional(P::token("c"))])]), P::seq(vec![P::many(P::token("d")), P::token("e")])])
+            }
+        "##]].assert_eq(&code[COMBINATOR_HEADER.len()..]);
+    }
+}
@@ -0,0 +1,521 @@
+//! Conversions of the grammar to the input formats of other parser generators
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{codegen::snake_case, Expr, Grammar};
+
+/// Words with a meaning in ANTLR4 grammars that cannot name a rule
+const ANTLR_KEYWORDS: &[&str] = &[
+    "catch", "channels", "finally", "fragment", "grammar", "import", "lexer", "locals", "mode",
+    "options", "parser", "returns", "throws", "tokens",
+];
+
+/// Which kind of symbol is written in uppercase in Yacc grammars, the other one is lowercase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum YaccCase {
+    #[default]
+    UppercaseTerminals,
+    UppercaseRules,
+}
+
+/// Why a grammar cannot be written faithfully as a Yacc grammar
+#[derive(Debug, PartialEq, Eq)]
+pub enum YaccWarning {
+    /// Different rules or terminals map to the same Yacc symbol
+    NameClash { symbol: String },
+    /// Yacc tokens cannot be empty
+    EmptyLiteral { rule: String },
+    /// `error` is predefined by Yacc
+    ReservedName { name: String },
+}
+
+impl std::fmt::Display for YaccWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            YaccWarning::NameClash { symbol } => {
+                write!(f, "more than one rule or terminal is written as `{symbol}`")
+            }
+            YaccWarning::EmptyLiteral { rule } => write!(f, "rule `{rule}` has an empty literal"),
+            YaccWarning::ReservedName { name } => {
+                write!(f, "`{name}` is written as the reserved `error` token")
+            }
+        }
+    }
+}
+
+impl<'src> Grammar<'src> {
+    /// ANTLR4 combined grammar named `grammar_name`, which should match the `.g4` file name.
+    /// Rule names start in lowercase as ANTLR4 requires for parser rules, and whitespace is
+    /// skipped between tokens
+    pub fn to_antlr4_string(&self, grammar_name: &str) -> String {
+        let mut out = format!("grammar {grammar_name};\n");
+        for (name, expr) in &self.rules {
+            out.push_str(&format!("\n{}\n", antlr_rule_name(name)));
+            for (i, branch) in expr.branches().iter().enumerate() {
+                let separator = if i == 0 { ':' } else { '|' };
+                out.push_str(&format!("    {separator} "));
+                write_antlr_body(branch, &mut out);
+                out.push('\n');
+            }
+            out.push_str("    ;\n");
+        }
+        out.push_str("\nWS\n    : [ \\t\\r\\n]+ -> skip\n    ;\n");
+        out
+    }
+
+    /// Tree-sitter `grammar.js` defining a language called `name`, rule names are in snake
+    /// case and the first rule is the root. Tree-sitter rejects rules other than the root that
+    /// match the empty string, those have to be reworked by hand
+    pub fn to_tree_sitter_string(&self, name: &str) -> String {
+        let mut out = format!("module.exports = grammar({{\n  name: '{name}',\n\n  rules: {{\n");
+        for (rule, expr) in &self.rules {
+            out.push_str(&format!(
+                "    {}: $ => {},\n",
+                snake_case(rule),
+                tree_sitter_expr(expr)
+            ));
+        }
+        out.push_str("  }\n});\n");
+        out
+    }
+
+    /// Yacc grammar with uppercase terminals, see [`Grammar::to_yacc_string_with_case`]
+    pub fn to_yacc_string(&self) -> Result<String, Vec<YaccWarning>> {
+        self.to_yacc_string_with_case(YaccCase::default())
+    }
+
+    /// Yacc grammar taking the first rule as the start symbol. Optionals and choices become
+    /// plain alternatives, ε an empty one, and repetitions new right recursive rules
+    /// `<name>_rep`. Rule names are in snake case.
+    ///
+    /// Single character literals are written as character literals, literals that are
+    /// identifiers as tokens named after them and any other one as a `TOKEN_<n>` token with
+    /// a Bison string alias
+    pub fn to_yacc_string_with_case(&self, case: YaccCase) -> Result<String, Vec<YaccWarning>> {
+        let rules = self.flattened_rules();
+        let mut warnings = Vec::new();
+        let mut symbols = IndexMap::<String, Expr>::new();
+        let mut tokens = IndexSet::new();
+        let mut aliases = IndexMap::new();
+        let mut symbol = |expr: &Expr<'static>, rule: &str, warnings: &mut Vec<YaccWarning>| {
+            let (name, uppercase) = match expr {
+                Expr::Rule(name) => (snake_case(name), case == YaccCase::UppercaseRules),
+                Expr::Literal("") => {
+                    warnings.push(YaccWarning::EmptyLiteral {
+                        rule: rule.to_string(),
+                    });
+                    return String::new();
+                }
+                Expr::Literal(lit) if is_char_literal(lit) => return format!("'{lit}'"),
+                Expr::Literal(lit) if is_identifier(lit) => {
+                    (lit.to_string(), case == YaccCase::UppercaseTerminals)
+                }
+                Expr::Literal(lit) => {
+                    let count = aliases.len();
+                    let alias = format!("\"{}\"", lit.replace('"', "\\\""));
+                    let name = aliases.entry(alias.clone()).or_insert_with(|| {
+                        let name = format!("TOKEN_{}", count + 1);
+                        match case {
+                            YaccCase::UppercaseTerminals => name,
+                            YaccCase::UppercaseRules => name.to_lowercase(),
+                        }
+                    });
+                    tokens.insert(format!("{name} {alias}"));
+                    return alias;
+                }
+                _ => unreachable!("alternatives are made of literals and rules"),
+            };
+            let name = if uppercase {
+                name.to_uppercase()
+            } else {
+                name.to_lowercase()
+            };
+
+            if let Expr::Literal(_) = expr {
+                tokens.insert(name.clone());
+            }
+            if name == "error" {
+                let warning = YaccWarning::ReservedName {
+                    name: expr.to_string(),
+                };
+                if !warnings.contains(&warning) {
+                    warnings.push(warning);
+                }
+            }
+            match symbols.get(&name) {
+                Some(previous) if previous != expr => {
+                    let warning = YaccWarning::NameClash {
+                        symbol: name.clone(),
+                    };
+                    if !warnings.contains(&warning) {
+                        warnings.push(warning);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    symbols.insert(name.clone(), expr.clone());
+                }
+            }
+            name
+        };
+
+        let start = rules
+            .keys()
+            .next()
+            .map(|start| symbol(&Expr::Rule(start), start, &mut warnings));
+        let mut body = String::new();
+        for (name, alternatives) in &rules {
+            body.push_str(&format!(
+                "\n{}\n",
+                symbol(&Expr::Rule(name), name, &mut warnings)
+            ));
+            for (i, alternative) in alternatives.iter().enumerate() {
+                let separator = if i == 0 { ':' } else { '|' };
+                let alternative = if alternative.is_empty() {
+                    "/* empty */".to_string()
+                } else {
+                    alternative
+                        .iter()
+                        .map(|expr| symbol(expr, name, &mut warnings))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                };
+                body.push_str(&format!("    {separator} {alternative}\n"));
+            }
+            body.push_str("    ;\n");
+        }
+
+        if !warnings.is_empty() {
+            return Err(warnings);
+        }
+
+        let mut out = String::new();
+        for token in tokens {
+            out.push_str(&format!("%token {token}\n"));
+        }
+        if let Some(start) = start {
+            out.push_str(&format!("%start {start}\n"));
+        }
+        out.push_str("%%\n");
+        out.push_str(&body);
+        out.push_str("\n%%\n");
+        Ok(out)
+    }
+}
+
+fn tree_sitter_expr(expr: &Expr) -> String {
+    let list = |exprs: &[Expr]| {
+        exprs
+            .iter()
+            .map(tree_sitter_expr)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    match expr {
+        Expr::Literal(lit) => format!("'{lit}'"),
+        Expr::Rule(rule) => format!("$.{}", snake_case(rule)),
+        Expr::Sequence(exprs) if exprs.len() == 1 => tree_sitter_expr(&exprs[0]),
+        Expr::Sequence(exprs) => format!("seq({})", list(exprs)),
+        Expr::Choice(branches) => format!("choice({})", list(branches)),
+        Expr::Optional(expr) => format!("optional({})", tree_sitter_expr(expr)),
+        Expr::Repeat(expr) => format!("repeat({})", tree_sitter_expr(expr)),
+        Expr::OneOrMore(expr) => format!("repeat1({})", tree_sitter_expr(expr)),
+    }
+}
+
+/// A single character, written as a Yacc character literal
+fn is_char_literal(lit: &str) -> bool {
+    let mut chars = lit.chars();
+    chars.next().is_some_and(|c| c != '\\') && chars.next().is_none()
+}
+
+fn is_identifier(lit: &str) -> bool {
+    lit.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && lit.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `ParamList` becomes `paramList`, names that ANTLR4 would reject get an underscore
+fn antlr_rule_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+    let name = first.to_lowercase().chain(chars).collect::<String>();
+    if !first.is_alphabetic() {
+        format!("r_{name}")
+    } else if ANTLR_KEYWORDS.contains(&name.as_str()) {
+        format!("{name}_")
+    } else {
+        name
+    }
+}
+
+fn write_antlr_body(expr: &Expr, out: &mut String) {
+    match expr {
+        Expr::Sequence(exprs) => {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                write_antlr_term(expr, out);
+            }
+        }
+        Expr::Choice(branches) => {
+            for (i, branch) in branches.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(" | ");
+                }
+                write_antlr_body(branch, out);
+            }
+        }
+        expr => write_antlr_term(expr, out),
+    }
+}
+
+/// Writes a single element of a sequence, grouping it when needed
+fn write_antlr_term(expr: &Expr, out: &mut String) {
+    let (inner, suffix) = match expr {
+        Expr::Literal(lit) => return out.push_str(&format!("'{lit}'")),
+        Expr::Rule(rule) => return out.push_str(&antlr_rule_name(rule)),
+        Expr::Sequence(exprs) if exprs.len() == 1 => return write_antlr_term(&exprs[0], out),
+        Expr::Sequence(_) | Expr::Choice(_) => {
+            out.push('(');
+            write_antlr_body(expr, out);
+            return out.push(')');
+        }
+        Expr::Optional(inner) => (inner, '?'),
+        Expr::Repeat(inner) => (inner, '*'),
+        Expr::OneOrMore(inner) => (inner, '+'),
+    };
+
+    match inner.as_ref() {
+        inner @ (Expr::Literal(_) | Expr::Rule(_)) => write_antlr_term(inner, out),
+        inner => {
+            out.push('(');
+            write_antlr_body(inner, out);
+            out.push(')');
+        }
+    }
+    out.push(suffix);
+}
+
+#[cfg(test)]
+mod test {
+    use expect_test::expect;
+
+    use crate::grammar::test::grammar;
+
+    use super::{antlr_rule_name, YaccCase, YaccWarning};
+
+    const EXAMPLE: &str = "
+        S = File '#'
+        File = Fn*
+        Fn = 'fn' 'name' ParamList ('->' 'type')? Block
+        ParamList = '(' Param* ')'
+        Param = 'name' ':' 'type' ','?
+        Block = '{' ('statement' ';')+ '}' | ';'
+    ";
+
+    #[test]
+    fn test_antlr_rule_name() {
+        assert_eq!(antlr_rule_name("ParamList"), "paramList");
+        assert_eq!(antlr_rule_name("Grammar"), "grammar_");
+        assert_eq!(antlr_rule_name("1st"), "r_1st");
+    }
+
+    #[test]
+    fn test_to_antlr4_string() {
+        let g4 = grammar(EXAMPLE).to_antlr4_string("Example");
+        assert_eq!(
+            g4,
+            "grammar Example;
+
+s
+    : file '#'
+    ;
+
+file
+    : fn*
+    ;
+
+fn
+    : 'fn' 'name' paramList ('->' 'type')? block
+    ;
+
+paramList
+    : '(' param* ')'
+    ;
+
+param
+    : 'name' ':' 'type' ','?
+    ;
+
+block
+    : '{' ('statement' ';')+ '}'
+    | ';'
+    ;
+
+WS
+    : [ \\t\\r\\n]+ -> skip
+    ;
+"
+        );
+    }
+
+    #[test]
+    fn test_to_tree_sitter_string() {
+        let js = grammar(EXAMPLE).to_tree_sitter_string("example");
+        expect![[r#"
+            module.exports = grammar({
+              name: 'example',
+
+              rules: {
+                s: $ => seq($.file, '#'),
+                file: $ => repeat($.fn),
+                fn: $ => seq('fn', 'name', $.param_list, optional(seq('->', 'type')), $.block),
+                param_list: $ => seq('(', repeat($.param), ')'),
+                param: $ => seq('name', ':', 'type', optional(',')),
+                block: $ => choice(seq('{', repeat1(seq('statement', ';')), '}'), ';'),
+              }
+            });
+        "#]]
+        .assert_eq(&js);
+    }
+
+    #[test]
+    fn test_to_yacc_string() {
+        let yacc = grammar(EXAMPLE).to_yacc_string().unwrap();
+        expect![[r#"
+            %token FN
+            %token NAME
+            %token TOKEN_1 "->"
+            %token TYPE
+            %token STATEMENT
+            %start s
+            %%
+
+            s
+                : file '#'
+                ;
+
+            file
+                : file_rep
+                | /* empty */
+                ;
+
+            fn
+                : FN NAME param_list "->" TYPE block
+                | FN NAME param_list block
+                ;
+
+            param_list
+                : '(' param_list_rep ')'
+                | '(' ')'
+                ;
+
+            param
+                : NAME ':' TYPE ','
+                | NAME ':' TYPE
+                ;
+
+            block
+                : '{' block_rep '}'
+                | ';'
+                ;
+
+            file_rep
+                : fn file_rep
+                | fn
+                ;
+
+            param_list_rep
+                : param param_list_rep
+                | param
+                ;
+
+            block_rep
+                : STATEMENT ';' block_rep
+                | STATEMENT ';'
+                ;
+
+            %%
+        "#]]
+        .assert_eq(&yacc);
+
+        let yacc = grammar("Expr = Term ('+' Term)*\nTerm = 'num'")
+            .to_yacc_string_with_case(YaccCase::UppercaseRules)
+            .unwrap();
+        expect![[r#"
+            %token num
+            %start EXPR
+            %%
+
+            EXPR
+                : TERM EXPR_REP
+                | TERM
+                ;
+
+            TERM
+                : num
+                ;
+
+            EXPR_REP
+                : '+' TERM EXPR_REP
+                | '+' TERM
+                ;
+
+            %%
+        "#]]
+        .assert_eq(&yacc);
+    }
+
+    #[test]
+    fn test_yacc_warnings() {
+        let warnings = grammar("S = 'if' | 'IF' | Error\nError = ''")
+            .to_yacc_string_with_case(YaccCase::UppercaseTerminals)
+            .unwrap_err();
+        assert_eq!(
+            warnings,
+            [
+                YaccWarning::NameClash {
+                    symbol: "IF".to_string()
+                },
+                YaccWarning::ReservedName {
+                    name: "Error".to_string()
+                },
+                YaccWarning::EmptyLiteral {
+                    rule: "Error".to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "more than one rule or terminal is written as `IF`"
+        );
+    }
+
+    /// Needs the `antlr4` command, e.g from `pip install antlr4-tools`
+    #[cfg(feature = "test-antlr")]
+    #[test]
+    fn test_antlr4_accepts_grammar() {
+        let dir = std::env::temp_dir().join(format!("ungram-antlr-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Example.g4");
+        std::fs::write(&path, grammar(EXAMPLE).to_antlr4_string("Example")).unwrap();
+
+        let output = std::process::Command::new("antlr4")
+            .arg("-o")
+            .arg(dir.join("out"))
+            .arg(&path)
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success());
+        assert!(
+            output.stdout.is_empty() && output.stderr.is_empty(),
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
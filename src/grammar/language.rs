@@ -0,0 +1,309 @@
+//! Strings of the language described by a grammar
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{Expr, Grammar};
+
+/// Number of parse trees of each string, counting stops at 2
+type TreeCounts = IndexMap<Vec<&'static str>, usize>;
+
+impl<'src> Grammar<'src> {
+    /// A string derived from `start` by two different parse trees of height up to `max_depth`,
+    /// proving the grammar ambiguous. Its terminals are separated by spaces.
+    ///
+    /// The parse trees of every rule are counted bottom up one level of height at a time, like
+    /// a CYK table over the strings themselves, so the work grows exponentially with
+    /// `max_depth`. `None` only means no ambiguity shows up within `max_depth`, or that
+    /// `start` is not defined
+    pub fn ambiguity_witness(&self, start: &str, max_depth: usize) -> Option<String> {
+        let rules = self.flattened_rules();
+        let (&start, _) = rules.get_key_value(start)?;
+
+        let mut trees = rules
+            .keys()
+            .map(|&name| (name, TreeCounts::new()))
+            .collect::<IndexMap<_, _>>();
+        for _ in 0..max_depth {
+            let next = rules
+                .iter()
+                .map(|(&name, alternatives)| {
+                    let mut counts = TreeCounts::new();
+                    for alternative in alternatives {
+                        for (string, count) in concatenations(alternative, &trees) {
+                            add_trees(&mut counts, string, count);
+                        }
+                    }
+                    (name, counts)
+                })
+                .collect::<IndexMap<_, _>>();
+
+            if let Some((string, _)) = next[start].iter().find(|(_, count)| **count > 1) {
+                return Some(string.join(" "));
+            }
+            // Taller trees add nothing, the language is finite and every string was counted
+            if next == trees {
+                break;
+            }
+            trees = next;
+        }
+        None
+    }
+
+    /// Whether `start` derives no string of terminals, as when it is unproductive or undefined
+    pub fn language_empty(&self, start: &str) -> bool {
+        !self.rules.contains_key(start) || self.unproductive_rules().contains(start)
+    }
+
+    /// Whether `start` derives finitely many strings. Once ε productions are removed every
+    /// rule on a cycle of rule references pumps the strings longer, unless the cycle is made
+    /// only of alternatives holding a single rule or goes through rules deriving nothing
+    pub fn language_finite(&self, start: &str) -> bool {
+        let grammar = self.remove_epsilon();
+        let rules = grammar.flattened_rules();
+        let unproductive = grammar.unproductive_rules();
+
+        // References of the alternatives deriving some string, split by whether the alternative
+        // is a unit production `A = B`
+        let mut edges = IndexMap::<&str, IndexSet<&str>>::new();
+        let mut pumping = IndexSet::new();
+        for (&name, alternatives) in &rules {
+            let references = edges.entry(name).or_default();
+            for alternative in alternatives {
+                let rules = alternative
+                    .iter()
+                    .filter_map(|expr| match expr {
+                        Expr::Rule(rule) => Some(*rule),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if rules.iter().any(|rule| unproductive.contains(rule)) {
+                    continue;
+                }
+                references.extend(rules.iter().copied());
+                if alternative.len() > 1 {
+                    pumping.extend(rules.into_iter().map(|rule| (name, rule)));
+                }
+            }
+        }
+
+        let reachable = grammar.reachable_rules(start);
+        edges.retain(|name, _| reachable.contains(name));
+        for references in edges.values_mut() {
+            references.retain(|rule| reachable.contains(rule));
+        }
+
+        !grammar.cycles(&edges).iter().any(|component| {
+            pumping
+                .iter()
+                .any(|(from, to)| component.contains(from) && component.contains(to))
+        })
+    }
+
+    /// Shortest string derived from `start` with its terminals separated by spaces, `None` when
+    /// `start` derives no string at all or is not defined
+    pub fn shortest_string(&self, start: &str) -> Option<String> {
+        self.shortest_strings(start, 1).pop()
+    }
+
+    /// The `n` shortest distinct strings derived from `start` from the shortest up, fewer when
+    /// the language is smaller.
+    ///
+    /// Sentential forms are expanded leftmost first in order of the shortest string they may
+    /// still derive, so complete strings come out by length
+    pub fn shortest_strings(&self, start: &str, n: usize) -> Vec<String> {
+        let mut strings = IndexSet::new();
+        if n == 0 || self.unproductive_rules().contains(start) || !self.rules.contains_key(start) {
+            return Vec::new();
+        }
+        if self.nullable_set().contains(start) {
+            strings.insert(String::new());
+        }
+
+        // Without ε rules every symbol adds to the length, so there are finitely many forms of
+        // each length
+        let rules = self.remove_epsilon().flattened_rules();
+        let mut lengths = IndexMap::<&str, usize>::new();
+        loop {
+            let before = lengths.len();
+            for (name, alternatives) in &rules {
+                if let Some(len) = alternatives
+                    .iter()
+                    .filter_map(|alternative| min_length(alternative, &lengths))
+                    .min()
+                {
+                    lengths.insert(name, len);
+                }
+            }
+            if lengths.len() == before {
+                break;
+            }
+        }
+
+        // Forms found so far, the queue holds their indices ordered by length and then by age
+        let mut forms = IndexSet::new();
+        let mut queue = BinaryHeap::new();
+        if let Some((&start, &len)) = lengths.get_key_value(start) {
+            forms.insert(vec![Expr::Rule(start)]);
+            queue.push((Reverse(len), Reverse(0)));
+        }
+        while strings.len() < n
+            && let Some((_, Reverse(index))) = queue.pop()
+        {
+            let form = &forms[index];
+            let Some(i) = form.iter().position(|expr| matches!(expr, Expr::Rule(_))) else {
+                let terminals = form
+                    .iter()
+                    .map(|expr| match expr {
+                        Expr::Literal(lit) => *lit,
+                        _ => unreachable!(),
+                    })
+                    .collect::<Vec<_>>();
+                strings.insert(terminals.join(" "));
+                continue;
+            };
+            let Expr::Rule(rule) = form[i] else {
+                unreachable!()
+            };
+
+            let (prefix, suffix) = (form[..i].to_vec(), form[i + 1..].to_vec());
+            for alternative in &rules[rule] {
+                let mut expanded = prefix.clone();
+                expanded.extend_from_slice(alternative);
+                expanded.extend_from_slice(&suffix);
+                if let Some(len) = min_length(&expanded, &lengths)
+                    && let (index, true) = forms.insert_full(expanded)
+                {
+                    queue.push((Reverse(len), Reverse(index)));
+                }
+            }
+        }
+
+        strings.into_iter().take(n).collect()
+    }
+}
+
+/// Length of the shortest string `exprs` derives, `None` when some rule derives none
+fn min_length(exprs: &[Expr], lengths: &IndexMap<&str, usize>) -> Option<usize> {
+    exprs
+        .iter()
+        .map(|expr| match expr {
+            Expr::Literal(_) => Some(1),
+            Expr::Rule(rule) => lengths.get(rule).copied(),
+            _ => unreachable!("flattened rules hold only literals and rule references"),
+        })
+        .sum()
+}
+
+/// Strings of the sequence `alternative` with the number of ways to build each one out of
+/// the trees of its rules
+fn concatenations(
+    alternative: &[Expr<'static>],
+    trees: &IndexMap<&'static str, TreeCounts>,
+) -> TreeCounts {
+    let mut strings = TreeCounts::from([(Vec::new(), 1)]);
+    for expr in alternative {
+        let parts = match expr {
+            Expr::Literal(lit) => TreeCounts::from([(vec![*lit], 1)]),
+            Expr::Rule(rule) => trees.get(rule).cloned().unwrap_or_default(),
+            _ => unreachable!("flattened rules hold only literals and rule references"),
+        };
+
+        let mut next = TreeCounts::new();
+        for (prefix, prefix_count) in &strings {
+            for (part, part_count) in &parts {
+                let mut string = prefix.clone();
+                string.extend_from_slice(part);
+                add_trees(&mut next, string, prefix_count * part_count);
+            }
+        }
+        strings = next;
+    }
+    strings
+}
+
+fn add_trees(counts: &mut TreeCounts, string: Vec<&'static str>, count: usize) {
+    let total = counts.entry(string).or_default();
+    *total = (*total + count).min(2);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::test::grammar;
+
+    #[test]
+    fn test_ambiguity_witness() {
+        let grammar = grammar("E = E '+' E | 'n'");
+        assert_eq!(grammar.ambiguity_witness("E", 2), None);
+        assert_eq!(
+            grammar.ambiguity_witness("E", 3).as_deref(),
+            Some("n + n + n")
+        );
+
+        let dangling = self::grammar("S = 'if' S | 'if' S 'else' S | 'x'");
+        assert_eq!(
+            dangling.ambiguity_witness("S", 5).as_deref(),
+            Some("if if x else x")
+        );
+
+        // Both optionals may derive the `a`
+        let optionals = self::grammar("S = A A 'b'\nA = 'a'?");
+        assert_eq!(optionals.ambiguity_witness("S", 4).as_deref(), Some("a b"));
+
+        let unambiguous = self::grammar("E = T ('+' T)*\nT = 'n' | '(' E ')'");
+        assert_eq!(unambiguous.ambiguity_witness("E", 6), None);
+        assert_eq!(unambiguous.ambiguity_witness("Missing", 6), None);
+    }
+
+    #[test]
+    fn test_language_empty_finite() {
+        let grammar = grammar(
+            "
+            S = 'a' A | 'b' | C
+            A = 'c' | B
+            B = A | 'e'
+            C = 'c' C
+            D = 'x' D | 'y'
+            E = F E | 'x'
+            F = 'f'?
+            ",
+        );
+        assert!(!grammar.language_empty("S"));
+        assert!(grammar.language_empty("C"));
+        assert!(grammar.language_empty("Missing"));
+
+        // `A` and `B` only derive each other through unit productions and `C` derives nothing
+        assert!(grammar.language_finite("S"));
+        assert!(grammar.language_finite("C"));
+        assert!(!grammar.language_finite("D"));
+        assert!(!grammar.language_finite("E"));
+
+        assert!(!self::grammar("S = 'a'* 'b'").language_finite("S"));
+        assert!(self::grammar("S = A?\nA = 'a'").language_finite("S"));
+    }
+
+    #[test]
+    fn test_shortest_strings() {
+        let grammar = grammar("S = 'a' S 'b' | A\nA = 'c'+ | 'd' 'e'?");
+        assert_eq!(grammar.shortest_string("S").as_deref(), Some("d"));
+        assert_eq!(
+            grammar.shortest_strings("S", 6),
+            ["d", "c", "d e", "c c", "c c c", "a d b"]
+        );
+        assert_eq!(grammar.shortest_strings("A", 0), Vec::<String>::new());
+
+        let nullable = self::grammar("S = A B\nA = 'a'?\nB = 'b' | A | S S");
+        assert_eq!(nullable.shortest_string("S").as_deref(), Some(""));
+        assert_eq!(nullable.shortest_strings("S", 4), ["", "b", "a", "a b"]);
+
+        // Finite language, with a rule deriving itself
+
+        let finite = self::grammar("S = 'x' | S | 'y' 'z'?");
+        assert_eq!(finite.shortest_strings("S", 10), ["x", "y", "y z"]);
+
+        let empty = self::grammar("S = 'a' S | B\nB = 'b' B");
+        assert_eq!(empty.shortest_string("S"), None);
+        assert_eq!(empty.shortest_string("Missing"), None);
+    }
+}
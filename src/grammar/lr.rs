@@ -0,0 +1,214 @@
+//! LR(0) items and the canonical collection of item sets, the base of SLR and LALR tables
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{Expr, Grammar};
+
+/// A production with a dot, `E = E • '+' T`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Item<'src> {
+    pub rule: &'src str,
+    /// Symbols of the production before the dot
+    pub position: usize,
+    /// Indices leading from the rule body to the production, the top level alternative like
+    /// the productions of [`super::table::ParseTable`]
+    pub expr_path: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemSet<'src> {
+    pub items: IndexSet<Item<'src>>,
+    /// Index in the collection of the set reached after each literal or rule reference
+    pub transitions: IndexMap<Expr<'src>, usize>,
+}
+
+impl<'src> Grammar<'src> {
+    /// Canonical collection of LR(0) item sets, the first one is the closure of the productions
+    /// of `start`. Adding an augmented start rule `S' = S` is left to the table built on top.
+    ///
+    /// Only works on grammars whose rules are a choice of sequences of literals and rule
+    /// references, optionals, repetitions and nested groups are expected to be flattened
+    /// beforehand, i.e by [`Grammar::remove_epsilon`]. Panics otherwise or when a referenced
+    /// rule is not defined
+    pub fn lr0_items(&self, start: &str) -> Vec<ItemSet<'src>> {
+        for (name, expr) in &self.rules {
+            for i in 0..expr.branches().len() {
+                let item = Item {
+                    rule: name,
+                    position: 0,
+                    expr_path: vec![i],
+                };
+                assert!(
+                    item.production(self)
+                        .iter()
+                        .all(|expr| matches!(expr, Expr::Literal(_) | Expr::Rule(_))),
+                    "rule `{name}` is not flattened"
+                );
+            }
+        }
+
+        let (&start, _) = self
+            .rules
+            .get_key_value(start)
+            .unwrap_or_else(|| panic!("rule not found {start:?}"));
+        let mut first = ItemSet {
+            items: self.initial_items(start).collect(),
+            transitions: IndexMap::new(),
+        };
+        first.closure(self);
+
+        let mut sets = vec![first];
+        let mut i = 0;
+        while i < sets.len() {
+            let symbols = sets[i]
+                .items
+                .iter()
+                .filter_map(|item| item.next_symbol(self))
+                .cloned()
+                .collect::<IndexSet<_>>();
+            for symbol in symbols {
+                let next = sets[i].goto(self, &symbol);
+                let index = match sets.iter().position(|set| set.items == next.items) {
+                    Some(index) => index,
+                    None => {
+                        sets.push(next);
+                        sets.len() - 1
+                    }
+                };
+                sets[i].transitions.insert(symbol, index);
+            }
+            i += 1;
+        }
+        sets
+    }
+
+    /// Items of every production of `rule` with the dot at the start
+    fn initial_items(&self, rule: &'src str) -> impl Iterator<Item = Item<'src>> {
+        let alternatives = self
+            .rules
+            .get(rule)
+            .unwrap_or_else(|| panic!("rule not found {rule:?}"))
+            .branches()
+            .len();
+        (0..alternatives).map(move |i| Item {
+            rule,
+            position: 0,
+            expr_path: vec![i],
+        })
+    }
+}
+
+impl<'src> Item<'src> {
+    /// Symbols of the production of the item
+    pub fn production<'g>(&self, grammar: &'g Grammar<'src>) -> &'g [Expr<'src>] {
+        let expr = &grammar.rules[self.rule].branches()[self.expr_path[0]];
+        match expr {
+            Expr::Sequence(exprs) => exprs,
+            expr => std::slice::from_ref(expr),
+        }
+    }
+
+    /// Symbol right after the dot, `None` when the production is complete
+    pub fn next_symbol<'g>(&self, grammar: &'g Grammar<'src>) -> Option<&'g Expr<'src>> {
+        self.production(grammar).get(self.position)
+    }
+
+    /// `E = E • '+' T`
+    pub fn display(&self, grammar: &Grammar<'src>) -> String {
+        let mut out = format!("{} =", self.rule);
+        let production = self.production(grammar);
+        for (i, expr) in production.iter().enumerate() {
+            if i == self.position {
+                out.push_str(" •");
+            }
+            out.push_str(&format!(" {expr}"));
+        }
+        if self.position == production.len() {
+            out.push_str(" •");
+        }
+        out
+    }
+}
+
+impl<'src> ItemSet<'src> {
+    /// Adds the initial items of every rule right after the dot of an item, until no more
+    /// items are added
+    pub fn closure(&mut self, grammar: &Grammar<'src>) {
+        let mut i = 0;
+        while let Some(item) = self.items.get_index(i) {
+            if let Some(Expr::Rule(rule)) = item.next_symbol(grammar) {
+                let items = grammar.initial_items(rule).collect::<Vec<_>>();
+                self.items.extend(items);
+            }
+            i += 1;
+        }
+    }
+
+    /// Closure of the items with `symbol` after the dot, the dot moved past it
+    pub fn goto(&self, grammar: &Grammar<'src>, symbol: &Expr<'src>) -> ItemSet<'src> {
+        let mut set = ItemSet {
+            items: self
+                .items
+                .iter()
+                .filter(|item| item.next_symbol(grammar) == Some(symbol))
+                .map(|item| Item {
+                    position: item.position + 1,
+                    ..item.clone()
+                })
+                .collect(),
+            transitions: IndexMap::new(),
+        };
+        set.closure(grammar);
+        set
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::{test::grammar, Expr};
+
+    #[test]
+    fn test_lr0_items() {
+        let grammar = grammar("S = E '$'\nE = E '+' T | T\nT = 'n' | '(' E ')'");
+        let sets = grammar.lr0_items("S");
+        let items = |i: usize| {
+            sets[i]
+                .items
+                .iter()
+                .map(|item| item.display(&grammar))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            items(0),
+            [
+                "S = • E '$'",
+                "E = • E '+' T",
+                "E = • T",
+                "T = • 'n'",
+                "T = • '(' E ')'",
+            ]
+        );
+        let after_e = sets[0].transitions[&Expr::Rule("E")];
+        assert_eq!(items(after_e), ["S = E • '$'", "E = E • '+' T"]);
+
+        // `(` leads to the same set from every set it appears in
+        let open = sets[0].transitions[&Expr::Literal("(")];
+        assert_eq!(sets[open].transitions[&Expr::Literal("(")], open);
+        assert_eq!(items(open)[0], "T = '(' • E ')'");
+
+        assert_eq!(sets.len(), 10);
+        let complete = sets
+            .iter()
+            .flat_map(|set| &set.items)
+            .filter(|item| item.next_symbol(&grammar).is_none())
+            .count();
+        assert_eq!(complete, 5);
+    }
+
+    #[test]
+    #[should_panic = "rule `S` is not flattened"]
+    fn test_lr0_items_not_flattened() {
+        grammar("S = 'a'* 'b'").lr0_items("S");
+    }
+}
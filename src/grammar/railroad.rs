@@ -0,0 +1,299 @@
+//! Railroad diagrams of the rules as a self-contained HTML page with inline SVG
+
+use super::{Expr, Grammar};
+
+/// Height of a terminal or non-terminal box
+const BOX_HEIGHT: usize = 24;
+/// Width taken by each character of a box label
+const CHAR_WIDTH: usize = 8;
+/// Horizontal room for the curves entering and leaving branches and loops
+const ARC: usize = 16;
+/// Line between consecutive elements of a sequence
+const GAP: usize = 10;
+/// Vertical space between stacked branches and below loops
+const SPACING: usize = 8;
+/// Space around every diagram
+const MARGIN: usize = 10;
+
+const STYLE: &str = "\
+body { font-family: sans-serif; }
+svg { display: block; margin-bottom: 24px; }
+svg.defs { position: absolute; }
+path { fill: none; stroke: #333; stroke-width: 1.5; }
+rect { stroke: #333; stroke-width: 1.5; }
+rect.terminal { fill: #e6f4e6; }
+rect.non-terminal { fill: #e6ecf8; }
+text { font-family: monospace; font-size: 13px; }
+a text { fill: navy; }";
+
+/// Diagram of an expression, the track enters at the left and leaves at the right `up` pixels
+/// below the top
+enum Track<'a> {
+    Terminal(&'a str),
+    NonTerminal(&'a str),
+    Sequence(Vec<Track<'a>>),
+    /// Branches stacked vertically, the track goes through the first one
+    Choice(Vec<Track<'a>>),
+    /// Its inner track followed by a path back to its start
+    Loop(Box<Track<'a>>),
+    /// A plain line, the bypass of optionals
+    Skip,
+}
+
+#[derive(Clone, Copy)]
+struct Size {
+    width: usize,
+    /// Height above the track
+    up: usize,
+    /// Height below the track
+    down: usize,
+}
+
+impl<'src> Grammar<'src> {
+    /// HTML page with a railroad diagram for every rule. Terminals are drawn as rounded boxes
+    /// and non-terminals as rectangles linking to the diagram of their rule
+    pub fn to_railroad_html(&self) -> String {
+        let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!(
+            "<title>Grammar</title>\n<style>\n{STYLE}\n</style>\n</head>\n<body>\n"
+        ));
+        // Shared by the loops of every diagram
+        out.push_str(
+            "<svg class=\"defs\" width=\"0\" height=\"0\"><defs><marker id=\"arrow\" markerWidth=\"6\" \
+             markerHeight=\"6\" refX=\"6\" refY=\"3\" orient=\"auto\">\
+             <path d=\"M 0 0 L 6 3 L 0 6\"/></marker></defs></svg>\n",
+        );
+        for (name, expr) in &self.rules {
+            let name = html_escape(name);
+            out.push_str(&format!("<h2 id=\"rule-{name}\">{name}</h2>\n"));
+            write_diagram(&mut out, &Track::new(expr));
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+impl<'a> Track<'a> {
+    fn new(expr: &Expr<'a>) -> Self {
+        match expr {
+            Expr::Literal(lit) => Track::Terminal(lit),
+            Expr::Rule(rule) => Track::NonTerminal(rule),
+            Expr::Sequence(exprs) if exprs.len() == 1 => Track::new(&exprs[0]),
+            Expr::Sequence(exprs) => Track::Sequence(exprs.iter().map(Track::new).collect()),
+            Expr::Choice(branches) => Track::Choice(branches.iter().map(Track::new).collect()),
+            Expr::Optional(expr) => Track::Choice(vec![Track::Skip, Track::new(expr)]),
+            Expr::Repeat(expr) => {
+                Track::Choice(vec![Track::Skip, Track::Loop(Box::new(Track::new(expr)))])
+            }
+            Expr::OneOrMore(expr) => Track::Loop(Box::new(Track::new(expr))),
+        }
+    }
+
+    fn size(&self) -> Size {
+        match self {
+            Track::Terminal(label) | Track::NonTerminal(label) => Size {
+                width: box_width(label),
+                up: BOX_HEIGHT / 2,
+                down: BOX_HEIGHT / 2,
+            },
+            Track::Sequence(tracks) => {
+                let sizes = tracks.iter().map(Track::size).collect::<Vec<_>>();
+                Size {
+                    width: sizes.iter().map(|size| size.width).sum::<usize>()
+                        + GAP * sizes.len().saturating_sub(1),
+                    up: sizes.iter().map(|size| size.up).max().unwrap_or(0),
+                    down: sizes.iter().map(|size| size.down).max().unwrap_or(0),
+                }
+            }
+            Track::Choice(branches) => {
+                let sizes = branches.iter().map(Track::size).collect::<Vec<_>>();
+                let first = sizes.first().copied().unwrap_or(Size {
+                    width: 0,
+                    up: 0,
+                    down: 0,
+                });
+                Size {
+                    width: sizes.iter().map(|size| size.width).max().unwrap_or(0) + 2 * ARC,
+                    up: first.up,
+                    down: first.down
+                        + sizes
+                            .iter()
+                            .skip(1)
+                            .map(|size| SPACING + size.up + size.down)
+                            .sum::<usize>(),
+                }
+            }
+            Track::Loop(track) => {
+                let size = track.size();
+                Size {
+                    width: size.width + 2 * ARC,
+                    up: size.up,
+                    down: size.down + SPACING,
+                }
+            }
+            Track::Skip => Size {
+                width: 0,
+                up: 0,
+                down: 0,
+            },
+        }
+    }
+
+    /// Draws the track entering at `(x, y)`
+    fn write(&self, out: &mut String, x: usize, y: usize) {
+        match self {
+            Track::Terminal(label) => write_box(out, x, y, label, "terminal", None),
+            Track::NonTerminal(label) => write_box(out, x, y, label, "non-terminal", Some(label)),
+            Track::Sequence(tracks) => {
+                let mut x = x;
+                for (i, track) in tracks.iter().enumerate() {
+                    if i > 0 {
+                        write_line(out, x, y, x + GAP);
+                        x += GAP;
+                    }
+                    track.write(out, x, y);
+                    x += track.size().width;
+                }
+            }
+            Track::Choice(branches) => {
+                let size = self.size();
+                let inner = size.width - 2 * ARC;
+                let mut branch_y = y;
+                for (i, branch) in branches.iter().enumerate() {
+                    let branch_size = branch.size();
+                    if i > 0 {
+                        branch_y += SPACING + branch_size.up;
+                    }
+                    let end = x + ARC + branch_size.width;
+                    if i == 0 {
+                        write_line(out, x, y, x + ARC);
+                    } else {
+                        write_curve(out, x, y, x + ARC, branch_y);
+                    }
+                    branch.write(out, x + ARC, branch_y);
+                    write_line(out, end, branch_y, x + ARC + inner);
+                    if i == 0 {
+                        write_line(out, x + ARC + inner, y, x + size.width);
+                    } else {
+                        write_curve(out, x + size.width, y, x + ARC + inner, branch_y);
+                    }
+                    branch_y += branch_size.down;
+                }
+            }
+            Track::Loop(track) => {
+                let size = track.size();
+                let start = x + ARC;
+                let end = start + size.width;
+                let back = y + size.down + SPACING;
+                write_line(out, x, y, start);
+                track.write(out, start, y);
+                write_line(out, end, y, end + ARC);
+                out.push_str(&format!(
+                    "<path d=\"M {end} {y} C {} {y}, {} {back}, {end} {back} L {start} {back} \
+                     C {x} {back}, {x} {y}, {start} {y}\" marker-end=\"url(#arrow)\"/>\n",
+                    end + ARC,
+                    end + ARC,
+                ));
+            }
+            Track::Skip => {}
+        }
+    }
+}
+
+/// Writes the svg of a whole rule, with the marks of its start and end
+fn write_diagram(out: &mut String, track: &Track) {
+    let size = track.size();
+    let width = size.width + 2 * MARGIN + 2 * GAP;
+    let height = size.up + size.down + 2 * MARGIN;
+    let y = MARGIN + size.up;
+    let end = MARGIN + GAP + size.width;
+
+    out.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\">\n"
+    ));
+    out.push_str(&format!(
+        "<path d=\"M {MARGIN} {} v {BOX_HEIGHT} M {end} {y} h {GAP} m 0 {} v {BOX_HEIGHT}\"/>\n",
+        y - BOX_HEIGHT / 2,
+        BOX_HEIGHT / 2,
+    ));
+    write_line(out, MARGIN, y, MARGIN + GAP);
+    track.write(out, MARGIN + GAP, y);
+    out.push_str("</svg>\n");
+}
+
+fn box_width(label: &str) -> usize {
+    label.chars().count() * CHAR_WIDTH + 2 * GAP
+}
+
+/// Writes a box with `label` centered on the track, linking to `link` when given
+fn write_box(out: &mut String, x: usize, y: usize, label: &str, class: &str, link: Option<&str>) {
+    let width = box_width(label);
+    let top = y - BOX_HEIGHT / 2;
+    let radius = if class == "terminal" {
+        BOX_HEIGHT / 2
+    } else {
+        0
+    };
+    let text = format!(
+        "<text x=\"{}\" y=\"{y}\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text>",
+        x + width / 2,
+        html_escape(label),
+    );
+    let text = match link {
+        Some(link) => format!("<a href=\"#rule-{}\">{text}</a>", html_escape(link)),
+        None => text,
+    };
+    out.push_str(&format!(
+        "<rect class=\"{class}\" x=\"{x}\" y=\"{top}\" width=\"{width}\" height=\"{BOX_HEIGHT}\" \
+         rx=\"{radius}\"/>{text}\n"
+    ));
+}
+
+fn write_line(out: &mut String, from: usize, y: usize, to: usize) {
+    if from != to {
+        out.push_str(&format!("<path d=\"M {from} {y} H {to}\"/>\n"));
+    }
+}
+
+/// S-shaped curve between the track and a branch below it
+fn write_curve(out: &mut String, x1: usize, y1: usize, x2: usize, y2: usize) {
+    let middle = x1.min(x2) + ARC / 2;
+    out.push_str(&format!(
+        "<path d=\"M {x1} {y1} C {middle} {y1}, {middle} {y2}, {x2} {y2}\"/>\n"
+    ));
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::test::grammar;
+
+    #[test]
+    fn test_to_railroad_html() {
+        let grammar = grammar("S = 'a' B? | C*\nB = '<b>'+\nC = 'c' | 'd'");
+        let html = grammar.to_railroad_html();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+        assert_eq!(html.matches("<svg xmlns").count(), 3);
+        assert_eq!(html.matches("</svg>").count(), 4);
+        assert!(html.contains("<h2 id=\"rule-B\">B</h2>"));
+
+        // Terminals are rounded, non-terminals link to their rule
+        assert_eq!(html.matches("class=\"terminal\"").count(), 4);
+        assert_eq!(html.matches("rx=\"12\"").count(), 4);
+        assert_eq!(html.matches("class=\"non-terminal\"").count(), 2);
+        assert!(html.contains("<a href=\"#rule-C\">"));
+        assert!(html.contains(">&lt;b&gt;</text>"));
+
+        // The loops of `C*` and `'<b>'+` point back to their start
+        assert_eq!(html.matches("marker-end=\"url(#arrow)\"").count(), 2);
+    }
+}
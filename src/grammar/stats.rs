@@ -0,0 +1,128 @@
+//! Size and complexity metrics of a grammar
+
+use indexmap::IndexSet;
+
+use super::{Expr, Grammar};
+
+#[derive(Debug, PartialEq)]
+pub struct GrammarStats {
+    pub rules: usize,
+    /// Distinct literals
+    pub terminals: usize,
+    /// Distinct non-terminals, defined or only referenced
+    pub non_terminals: usize,
+    pub average_depth: f64,
+    pub max_depth: usize,
+    pub nullable_rules: usize,
+    pub left_recursive_rules: usize,
+    /// `None` when some rule is undefined and the LL(1) check cannot run
+    pub is_ll1: Option<bool>,
+}
+
+impl<'src> Grammar<'src> {
+    pub fn stats(&self) -> GrammarStats {
+        let mut terminals = IndexSet::new();
+        let mut non_terminals = self.rules.keys().copied().collect::<IndexSet<_>>();
+        for expr in self.rules.values() {
+            terminals.extend(expr.terminals());
+            non_terminals.extend(expr.non_terminals());
+        }
+
+        let depths = self.rules.values().map(Expr::depth).collect::<Vec<_>>();
+        let average_depth = match depths.len() {
+            0 => 0.0,
+            len => depths.iter().sum::<usize>() as f64 / len as f64,
+        };
+
+        let is_ll1 = self.undefined_rules().is_empty().then(|| self.is_ll1());
+
+        GrammarStats {
+            rules: self.rules.len(),
+            terminals: terminals.len(),
+            non_terminals: non_terminals.len(),
+            average_depth,
+            max_depth: depths.into_iter().max().unwrap_or(0),
+            nullable_rules: self.nullable_set().len(),
+            left_recursive_rules: self.left_recursive_rules().len(),
+            is_ll1,
+        }
+    }
+}
+
+impl GrammarStats {
+    pub fn to_json(&self) -> String {
+        let is_ll1 = match self.is_ll1 {
+            Some(is_ll1) => is_ll1.to_string(),
+            None => "null".to_string(),
+        };
+        format!(
+            concat!(
+                r#"{{"rules":{},"terminals":{},"non_terminals":{},"average_depth":{},"#,
+                r#""max_depth":{},"nullable_rules":{},"left_recursive_rules":{},"is_ll1":{}}}"#
+            ),
+            self.rules,
+            self.terminals,
+            self.non_terminals,
+            self.average_depth,
+            self.max_depth,
+            self.nullable_rules,
+            self.left_recursive_rules,
+            is_ll1
+        )
+    }
+}
+
+impl std::fmt::Display for GrammarStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let is_ll1 = match self.is_ll1 {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "unknown, some rules are undefined",
+        };
+        writeln!(f, "rules: {}", self.rules)?;
+        writeln!(f, "terminals: {}", self.terminals)?;
+        writeln!(f, "non-terminals: {}", self.non_terminals)?;
+        writeln!(f, "average depth: {:.2}", self.average_depth)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        writeln!(f, "nullable rules: {}", self.nullable_rules)?;
+        writeln!(f, "left recursive rules: {}", self.left_recursive_rules)?;
+        writeln!(f, "LL(1): {is_ll1}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::test::grammar;
+
+    use super::GrammarStats;
+
+    #[test]
+    fn test_stats() {
+        let grammar = grammar("S = A 'x' | 'y'\nA = A 'a' | B?\nB = 'b' | 'x'");
+        let stats = grammar.stats();
+        assert_eq!(
+            stats,
+            GrammarStats {
+                rules: 3,
+                terminals: 4,
+                non_terminals: 3,
+                average_depth: 7.0 / 3.0,
+                max_depth: 3,
+                nullable_rules: 1,
+                left_recursive_rules: 1,
+                is_ll1: Some(false),
+            }
+        );
+
+        let json = serde_json::from_str::<serde_json::Value>(&stats.to_json()).unwrap();
+        assert_eq!(json["average_depth"], 7.0 / 3.0);
+        assert_eq!(json["is_ll1"], false);
+
+        let stats = self::grammar("S = 'a' Missing").stats();
+        assert_eq!(stats.non_terminals, 2);
+        assert_eq!(stats.is_ll1, None);
+        assert!(stats
+            .to_string()
+            .ends_with("LL(1): unknown, some rules are undefined\n"));
+    }
+}
@@ -0,0 +1,303 @@
+//! LL(1) parse table built from the PREDICT sets
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{json_string, Expr, Grammar};
+
+/// Column of the end of the input
+pub const END: &str = super::END_OF_INPUT;
+
+pub struct ParseTable<'src> {
+    /// Every literal in order of appearance followed by [`END`]
+    pub terminals: IndexSet<&'src str>,
+    /// Productions predicted for each rule and terminal, a production is the index of a top
+    /// level alternative of the rule
+    pub rows: IndexMap<&'src str, IndexMap<&'src str, Vec<usize>>>,
+}
+
+impl<'src> Grammar<'src> {
+    /// LL(1) parse table taking the first rule as the start symbol, cells with more than one
+    /// production are conflicts.
+    ///
+    /// Like [`Grammar::predict_set`] it panics when a referenced rule is not defined. Left
+    /// recursive grammars are never LL(1), their table is built but misses the recursive
+    /// predictions, check [`Grammar::left_recursive_rules`] first
+    pub fn ll1_table(&self) -> ParseTable<'src> {
+        let nullable = self.nullable_set();
+        let at_end = self.end_of_input_rules(&nullable);
+
+        let mut terminals = IndexSet::new();
+        for expr in self.rules.values() {
+            terminals.extend(expr.terminals());
+        }
+        terminals.insert(END);
+
+        let mut rows = IndexMap::new();
+        for (name, expr) in &self.rules {
+            let mut row = IndexMap::<_, Vec<_>>::new();
+            for (i, branch) in expr.branches().iter().enumerate() {
+                let mut predict = self.predict_set_branch(name, i);
+                if at_end.contains(name) && branch.derives_empty(&nullable) {
+                    predict.insert(END);
+                }
+                for terminal in predict {
+                    row.entry(terminal).or_default().push(i);
+                }
+            }
+            rows.insert(*name, row);
+        }
+
+        ParseTable { terminals, rows }
+    }
+
+    /// Writes the LL(1) parse table as an ASCII grid, a row per rule and a column per terminal
+    /// sorted with [`END`] last. Cells hold the predicted alternative or `—`, conflicting
+    /// cells list every predicted alternative after a `!` separated by `/`
+    pub fn print_table(&self, out: &mut dyn std::io::Write) -> std::io::Result<()> {
+        let table = self.ll1_table();
+        let mut terminals = table
+            .terminals
+            .iter()
+            .copied()
+            .filter(|terminal| *terminal != END)
+            .collect::<Vec<_>>();
+        terminals.sort_unstable();
+        terminals.push(END);
+
+        let mut lines = vec![std::iter::once(String::new())
+            .chain(terminals.iter().map(|terminal| terminal.to_string()))
+            .collect::<Vec<_>>()];
+        for (rule, row) in &table.rows {
+            let branches = self.rules[rule].branches();
+            let cells = terminals.iter().map(|terminal| {
+                let productions = row.get(terminal).map(Vec::as_slice).unwrap_or_default();
+                let text = productions
+                    .iter()
+                    .map(|&i| branches[i].to_string())
+                    .collect::<Vec<_>>()
+                    .join(" / ");
+                match productions.len() {
+                    0 => "—".to_string(),
+                    1 => text,
+                    _ => format!("! {text}"),
+                }
+            });
+            lines.push(std::iter::once(rule.to_string()).chain(cells).collect());
+        }
+
+        let widths = (0..terminals.len() + 1)
+            .map(|column| {
+                lines
+                    .iter()
+                    .map(|line| line[column].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        let border = widths
+            .iter()
+            .map(|width| "-".repeat(width + 2))
+            .collect::<Vec<_>>()
+            .join("+");
+
+        writeln!(out, "+{border}+")?;
+        for (i, line) in lines.iter().enumerate() {
+            for (cell, width) in line.iter().zip(&widths) {
+                let padding = width - cell.chars().count();
+                write!(out, "| {cell}{} ", " ".repeat(padding))?;
+            }
+            writeln!(out, "|")?;
+            if i == 0 {
+                writeln!(out, "+{border}+")?;
+            }
+        }
+        writeln!(out, "+{border}+")
+    }
+
+    /// Rules that may derive the last tokens of the input, i.e the start symbol and every rule
+    /// they may end with
+    fn end_of_input_rules(&self, nullable: &IndexSet<&str>) -> IndexSet<&'src str> {
+        let mut at_end = IndexSet::from_iter(self.start_symbol());
+        let mut i = 0;
+        while let Some(&name) = at_end.get_index(i) {
+            if let Some(expr) = self.rules.get(name) {
+                expr.trailing_rules(nullable, &mut at_end);
+            }
+            i += 1;
+        }
+        at_end
+    }
+}
+
+impl<'src> Expr<'src> {
+    /// Rules that may be derived last by the expression
+    fn trailing_rules(&self, nullable: &IndexSet<&str>, set: &mut IndexSet<&'src str>) {
+        match self {
+            Expr::Literal(_) => {}
+            Expr::Rule(rule) => {
+                set.insert(rule);
+            }
+            Expr::Sequence(exprs) => {
+                for expr in exprs.iter().rev() {
+                    expr.trailing_rules(nullable, set);
+                    if !expr.derives_empty(nullable) {
+                        break;
+                    }
+                }
+            }
+            Expr::Choice(exprs) => exprs.iter().for_each(|x| x.trailing_rules(nullable, set)),
+            Expr::Optional(x) | Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                x.trailing_rules(nullable, set)
+            }
+        }
+    }
+}
+
+impl ParseTable<'_> {
+    pub fn has_conflicts(&self) -> bool {
+        self.rows
+            .values()
+            .flat_map(IndexMap::values)
+            .any(|productions| productions.len() > 1)
+    }
+
+    /// `—` for no production, the production index or `[CONFLICT: 0, 1]`
+    fn cell(&self, rule: &str, terminal: &str) -> String {
+        match self.rows[rule].get(terminal).map(Vec::as_slice) {
+            None | Some([]) => "—".to_string(),
+            Some([production]) => production.to_string(),
+            Some(productions) => {
+                let productions = productions
+                    .iter()
+                    .map(|i| i.to_string())
+                    .collect::<Vec<_>>();
+                format!("[CONFLICT: {}]", productions.join(", "))
+            }
+        }
+    }
+
+    /// Header with the terminals, then a line per rule
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("rule");
+        for terminal in &self.terminals {
+            out.push(',');
+            out.push_str(&csv_field(terminal));
+        }
+        out.push('\n');
+
+        for rule in self.rows.keys() {
+            out.push_str(&csv_field(rule));
+            for terminal in &self.terminals {
+                out.push(',');
+                out.push_str(&csv_field(&self.cell(rule, terminal)));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// `{"terminals":[..],"rows":{"Rule":{"terminal":[productions]}}}`, empty cells are left out
+    pub fn to_json(&self) -> String {
+        let terminals = self
+            .terminals
+            .iter()
+            .map(|terminal| json_string(terminal))
+            .collect::<Vec<_>>();
+        let rows = self
+            .rows
+            .iter()
+            .map(|(rule, row)| {
+                let cells = row
+                    .iter()
+                    .map(|(terminal, productions)| {
+                        format!("{}:{productions:?}", json_string(terminal))
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}:{{{}}}", json_string(rule), cells.join(","))
+            })
+            .collect::<Vec<_>>();
+        format!(
+            r#"{{"terminals":[{}],"rows":{{{}}}}}"#,
+            terminals.join(","),
+            rows.join(",")
+        )
+    }
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::grammar::test::grammar;
+
+    #[test]
+    fn test_ll1_table() {
+        let grammar = grammar("S = 'a' B | 'c'\nB = 'b' | C\nC = 'd'?");
+        let table = grammar.ll1_table();
+        assert!(!table.has_conflicts());
+        assert_eq!(
+            table.to_csv(),
+            "rule,a,c,b,d,$\nS,0,1,—,—,—\nB,—,—,0,1,1\nC,—,—,—,0,0\n"
+        );
+
+        let json = serde_json::from_str::<serde_json::Value>(&table.to_json()).unwrap();
+        assert_eq!(json["terminals"][4], "$");
+        assert_eq!(json["rows"]["B"]["$"][0], 1);
+        assert!(json["rows"]["S"]["b"].is_null());
+    }
+
+    #[test]
+    fn test_print_table() {
+        let grammar = grammar("S = 'b' B | 'a'\nB = 'b' | C\nC = 'd'?");
+        let mut out = Vec::new();
+        grammar.print_table(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
++---+-----+-------+------+------+
+|   | a   | b     | d    | $    |
++---+-----+-------+------+------+
+| S | 'a' | 'b' B | —    | —    |
+| B | —   | 'b'   | C    | C    |
+| C | —   | —     | 'd'? | 'd'? |
++---+-----+-------+------+------+
+"
+        );
+
+        let grammar = self::grammar("S = 'a' | 'a' 'b' | ()");
+        let mut out = Vec::new();
+        grammar.print_table(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().nth(3), Some("| S | ! 'a' / 'a' 'b' | — | () |"));
+    }
+
+    #[test]
+    fn test_ll1_table_conflicts() {
+        let grammar = grammar("S = 'a' | 'a' 'b' | T\nT = 'c'?");
+        let table = grammar.ll1_table();
+        assert!(table.has_conflicts());
+        let mut out = Vec::new();
+        grammar.print_table(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
++---+-----------------+---+------+------+
+|   | a               | b | c    | $    |
++---+-----------------+---+------+------+
+| S | ! 'a' / 'a' 'b' | — | T    | T    |
+| T | —               | — | 'c'? | 'c'? |
++---+-----------------+---+------+------+
+"
+        );
+        assert_eq!(
+            table.to_csv().lines().nth(1),
+            Some("S,\"[CONFLICT: 0, 1]\",—,2,2")
+        );
+    }
+}
@@ -0,0 +1,1185 @@
+//! Transformations producing new grammars from an existing one
+
+use indexmap::{IndexMap, IndexSet};
+
+use super::{intern, Expr, Grammar, InlineError};
+
+/// A rule body as a list of alternatives, each one a sequence of expressions
+pub(super) type Alternatives = Vec<Vec<Expr<'static>>>;
+
+impl<'src> Grammar<'src> {
+    /// Replaces every reference to `name` with its body and removes the rule
+    pub fn inline_rule(&self, name: &str) -> Result<Grammar<'src>, InlineError> {
+        let body = self.rules.get(name).ok_or(InlineError::UnknownRule)?;
+        if body.contains_rule(name) {
+            return Err(InlineError::Recursive);
+        }
+
+        // A single element body needs no grouping
+        let body = body.ungrouped();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|(rule, _)| **rule != name)
+            .map(|(rule, expr)| {
+                let mut expr = expr.clone();
+                expr.replace_rule(name, body);
+                (*rule, expr)
+            })
+            .collect();
+
+        Ok(Grammar::new(rules))
+    }
+
+    /// Removes direct and indirect left recursion following Paull's algorithm.
+    ///
+    /// Rules are processed in declaration order, for each left recursive rule `A = A α | β` a
+    /// new rule `A_tail = (α A_tail)?` is introduced and `A` is rewritten as `A = β A_tail`.
+    /// Groups and optionals at the start of an alternative are distributed first so the
+    /// recursion becomes visible. When it is hidden behind leading nullable rules or
+    /// repetitions, those are expanded as well, i.e `A = B A 'x'` with `B = 'b'?` becomes
+    /// `A = 'b' A 'x' | A 'x'` before the recursion is removed. New tails are nullable too
+    pub fn eliminate_left_recursion(&self) -> Grammar<'static> {
+        let recursive = self.left_recursive_rules();
+        let mut nullable = self.nullable_set();
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|(name, expr)| (intern(name), alternatives(expr)))
+            .collect::<IndexMap<_, _>>();
+
+        let names = rules
+            .keys()
+            .copied()
+            .filter(|name| recursive.contains_key(name))
+            .collect::<Vec<_>>();
+
+        for (i, &name) in names.iter().enumerate() {
+            let mut current = std::mem::take(&mut rules[name])
+                .into_iter()
+                .flat_map(expand_leading)
+                .collect::<Alternatives>();
+
+            // Substituted rules may start with nullable tails hiding more recursion, so both
+            // steps repeat until nothing changes
+            let targets = &names[..=i];
+            loop {
+                let before = current.clone();
+
+                // Recursion into `A` or an earlier rule behind a nullable prefix
+                current = current
+                    .into_iter()
+                    .flat_map(|alternative| {
+                        let mut corners = IndexSet::new();
+                        Expr::Sequence(alternative.clone()).left_corners(&nullable, &mut corners);
+                        let visible = matches!(
+                            alternative.first(),
+                            Some(Expr::Rule(rule)) if targets.contains(rule)
+                        );
+                        if visible || !corners.iter().any(|rule| targets.contains(rule)) {
+                            return vec![alternative];
+                        }
+                        expand_nullable_leading(alternative, &rules, &nullable, &[name])
+                    })
+                    .collect();
+
+                // Replace `A_i = A_j γ` with `A_i = δ γ` for every earlier `A_j = δ`
+                for &earlier in &names[..i] {
+                    current = current
+                        .into_iter()
+                        .flat_map(|alternative| {
+                            if alternative.first() != Some(&Expr::Rule(earlier)) {
+                                return vec![alternative];
+                            }
+
+                            rules[earlier]
+                                .iter()
+                                .map(|delta| {
+                                    let mut expanded = delta.clone();
+                                    expanded.extend_from_slice(&alternative[1..]);
+                                    expanded
+                                })
+                                .collect()
+                        })
+                        .collect();
+                }
+
+                if current == before {
+                    break;
+                }
+            }
+
+            let (left, other): (Alternatives, Alternatives) = current
+                .into_iter()
+                .partition(|alternative| alternative.first() == Some(&Expr::Rule(name)));
+            if left.is_empty() {
+                rules[name] = other;
+                continue;
+            }
+
+            let tail = fresh_name(&rules, &format!("{name}_tail"));
+            nullable.insert(tail);
+            rules[name] = other
+                .into_iter()
+                .map(|mut beta| {
+                    beta.push(Expr::Rule(tail));
+                    beta
+                })
+                .collect();
+
+            // `A = A` alternatives derive nothing new and are dropped
+            let alphas = left
+                .into_iter()
+                .filter(|alpha| alpha.len() > 1)
+                .map(|alpha| {
+                    let mut alpha = alpha[1..].to_vec();
+                    alpha.push(Expr::Rule(tail));
+                    alpha
+                })
+                .collect::<Alternatives>();
+            rules.insert(
+                tail,
+                vec![vec![Expr::Optional(Box::new(from_alternatives(alphas)))]],
+            );
+        }
+
+        Grammar::new(
+            rules
+                .into_iter()
+                .map(|(name, alternatives)| (name, from_alternatives(alternatives)))
+                .collect(),
+        )
+    }
+
+    /// Equivalent grammar without ε productions, its language is the original one minus the
+    /// empty string.
+    ///
+    /// Optionals and choices are expanded into plain alternatives and every repetition `X*`
+    /// becomes a new right recursive rule `<Name>_rep = X <Name>_rep | X`, then each
+    /// alternative gets a copy without every combination of its nullable rules. Rules left
+    /// without alternatives only derived ε and are removed, no rule of the result is nullable
+    pub fn remove_epsilon(&self) -> Grammar<'static> {
+        let mut rules = self.flattened_rules();
+
+        let mut nullable = IndexSet::new();
+        loop {
+            let before = nullable.len();
+            for (name, alternatives) in &rules {
+                let derives_empty = alternatives.iter().any(|alternative| {
+                    alternative
+                        .iter()
+                        .all(|expr| matches!(expr, Expr::Rule(rule) if nullable.contains(rule)))
+                });
+                if derives_empty {
+                    nullable.insert(*name);
+                }
+            }
+
+            if nullable.len() == before {
+                break;
+            }
+        }
+
+        for alternatives in rules.values_mut() {
+            let mut variants = Alternatives::new();
+            for alternative in alternatives.iter() {
+                let mut expanded: Alternatives = vec![Vec::new()];
+                for expr in alternative {
+                    let without = expanded.clone();
+                    expanded.iter_mut().for_each(|x| x.push(expr.clone()));
+                    if matches!(expr, Expr::Rule(rule) if nullable.contains(rule)) {
+                        expanded.extend(without);
+                    }
+                }
+
+                for variant in expanded {
+                    if !variant.is_empty() && !variants.contains(&variant) {
+                        variants.push(variant);
+                    }
+                }
+            }
+            *alternatives = variants;
+        }
+
+        // Drop the rules that only derived ε along with the alternatives referencing them
+        loop {
+            let empty = rules
+                .iter()
+                .filter_map(|(name, alternatives)| alternatives.is_empty().then_some(*name))
+                .collect::<IndexSet<_>>();
+            if empty.is_empty() {
+                break;
+            }
+
+            rules.retain(|name, _| !empty.contains(name));
+            for alternatives in rules.values_mut() {
+                alternatives.retain(|alternative| {
+                    !alternative
+                        .iter()
+                        .any(|expr| matches!(expr, Expr::Rule(rule) if empty.contains(rule)))
+                });
+            }
+        }
+
+        Grammar::new(
+            rules
+                .into_iter()
+                .map(|(name, alternatives)| (name, from_alternatives(alternatives)))
+                .collect(),
+        )
+    }
+
+    /// Chomsky Normal Form of the grammar, every alternative of the result is either two rule
+    /// references or a single literal.
+    ///
+    /// The steps are applied as START (new start rule `<start>_0`), DEL through
+    /// [`Grammar::remove_epsilon`] which also expands optionals and repetitions, UNIT, TERM
+    /// (literals inside longer alternatives move into `T` rules) and BIN (long alternatives are
+    /// chained through `<Name>_bin` rules). Rules unreachable from the new start are dropped
+    /// and, as with DEL, the empty string is not part of the resulting language
+    pub fn to_cnf(&self, start: &str) -> Grammar<'static> {
+        let mut rules = self
+            .remove_epsilon()
+            .rules
+            .iter()
+            .map(|(name, expr)| (*name, alternatives(expr)))
+            .collect::<IndexMap<_, _>>();
+
+        let new_start = fresh_name(&rules, &format!("{start}_0"));
+        let start = intern(start);
+        if rules.contains_key(start) {
+            rules.insert(new_start, vec![vec![Expr::Rule(start)]]);
+            rules.move_index(rules.len() - 1, 0);
+        }
+
+        eliminate_units(&mut rules);
+
+        // Literals inside longer alternatives are replaced by a rule deriving just them
+        let mut terminals = IndexMap::new();
+        let names = rules.keys().copied().collect::<Vec<_>>();
+        for name in names {
+            let mut alternatives = std::mem::take(&mut rules[name]);
+            for alternative in alternatives.iter_mut().filter(|x| x.len() > 1) {
+                for expr in alternative.iter_mut() {
+                    let Expr::Literal(lit) = *expr else {
+                        continue;
+                    };
+                    let terminal = match terminals.get(lit) {
+                        Some(&terminal) => terminal,
+                        None => {
+                            let terminal = fresh_name(&rules, "T");
+                            rules.insert(terminal, vec![vec![Expr::Literal(lit)]]);
+                            terminals.insert(lit, terminal);
+                            terminal
+                        }
+                    };
+                    *expr = Expr::Rule(terminal);
+                }
+            }
+
+            // `A = X1 X2 X3` becomes `A = X1 A_bin` and `A_bin = X2 X3`
+            for alternative in alternatives.iter_mut().filter(|x| x.len() > 2) {
+                let mut exprs = std::mem::take(alternative);
+                let mut tail = exprs.split_off(exprs.len() - 2);
+                while let Some(expr) = exprs.pop() {
+                    let chained = fresh_name(&rules, &format!("{name}_bin"));
+                    rules.insert(chained, vec![tail]);
+                    tail = vec![expr, Expr::Rule(chained)];
+                }
+                *alternative = tail;
+            }
+
+            rules[name] = alternatives;
+        }
+
+        let mut reachable = IndexSet::from([new_start]);
+        let mut stack = vec![new_start];
+        while let Some(name) = stack.pop() {
+            for expr in rules.get(name).into_iter().flatten().flatten() {
+                if let Expr::Rule(rule) = expr
+                    && reachable.insert(rule)
+                {
+                    stack.push(rule);
+                }
+            }
+        }
+        rules.retain(|name, _| reachable.contains(name));
+
+        Grammar::new(
+            rules
+                .into_iter()
+                .map(|(name, alternatives)| {
+                    let mut branches = alternatives
+                        .into_iter()
+                        .map(|mut alternative| match alternative.len() {
+                            1 => alternative.pop().unwrap(),
+                            _ => Expr::Sequence(alternative),
+                        })
+                        .collect::<Vec<_>>();
+                    let expr = match branches.len() {
+                        1 => branches.pop().unwrap(),
+                        _ => Expr::Choice(branches),
+                    };
+                    (name, expr)
+                })
+                .collect(),
+        )
+    }
+
+    /// Replaces every alternative made of a single rule reference, `A = B`, with the
+    /// alternatives of every rule `A` reaches through such chains that are not unit
+    /// productions themselves. Rules without unit productions are left as they are
+    pub fn eliminate_unit_productions(&self) -> Grammar<'static> {
+        let original = self
+            .rules
+            .iter()
+            .map(|(name, expr)| (intern(name), alternatives(expr)))
+            .collect::<IndexMap<_, _>>();
+        let mut rules = original.clone();
+        eliminate_units(&mut rules);
+
+        Grammar::new(
+            rules
+                .into_iter()
+                .map(
+                    |(name, alternatives)| match alternatives == original[name] {
+                        true => (name, self.rules[name].to_static()),
+                        false => (name, from_alternatives(alternatives)),
+                    },
+                )
+                .collect(),
+        )
+    }
+
+    /// Factors out the longest prefix shared by alternatives of the same rule, the differing
+    /// suffixes are moved into helper rules named `<Name>_rest_N`.
+    ///
+    /// Helper rules are factored as well, so applying it twice yields the same grammar
+    pub fn left_factor(&self) -> Grammar<'static> {
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|(name, expr)| (intern(name), alternatives(expr)))
+            .collect::<IndexMap<_, _>>();
+
+        let mut pending = rules.keys().map(|&name| (name, name)).collect::<Vec<_>>();
+        let mut i = 0;
+        while let Some(&(name, origin)) = pending.get(i) {
+            let helpers = factor_alternatives(&mut rules, name, origin);
+            pending.extend(helpers.into_iter().map(|helper| (helper, origin)));
+            i += 1;
+        }
+
+        Grammar::new(
+            rules
+                .into_iter()
+                .map(|(name, alternatives)| (name, from_factored(alternatives)))
+                .collect(),
+        )
+    }
+
+    /// Like [`Grammar::left_factor`] for the alternatives of `name` alone, the other rules are
+    /// left untouched. Helper rules `<Name>_rest_N` are added at the end, the grammar is
+    /// returned unchanged when no alternatives share a prefix or `name` is not defined
+    pub fn factor_common_prefix_in_rule(&self, name: &str) -> Grammar<'src> {
+        let Some(expr) = self.rules.get(name) else {
+            return self.clone();
+        };
+
+        // Every rule is listed so helpers get names free in the whole grammar
+        let mut rules = self
+            .rules
+            .keys()
+            .map(|&rule| (intern(rule), Alternatives::new()))
+            .collect::<IndexMap<_, _>>();
+        let name = intern(name);
+        let original = alternatives(expr);
+        rules[name] = original.clone();
+
+        let mut pending = vec![name];
+        let mut i = 0;
+        while let Some(&rule) = pending.get(i) {
+            pending.extend(factor_alternatives(&mut rules, rule, name));
+            i += 1;
+        }
+        if rules[name] == original {
+            return self.clone();
+        }
+
+        let mut grammar = self.clone();
+        for rule in pending {
+            let expr = from_factored(std::mem::take(&mut rules[rule]));
+            grammar.rules.insert(rule, expr);
+        }
+        grammar.clear_cache();
+        grammar
+    }
+
+    /// Every rule as alternatives made only of literals and rule references, repetitions
+    /// become new right recursive rules `<Name>_rep = X <Name>_rep | X`
+    pub(super) fn flattened_rules(&self) -> IndexMap<&'static str, Alternatives> {
+        let mut rules = self
+            .rules
+            .keys()
+            .map(|&name| (intern(name), Alternatives::new()))
+            .collect::<IndexMap<_, _>>();
+        for (name, expr) in &self.rules {
+            rules[*name] = flatten(expr, name, &mut rules);
+        }
+        rules
+    }
+
+    /// Rewrites every `X?` as `X | 'ε'`, nested ones included
+    pub fn expand_optionals(&self) -> Grammar<'src> {
+        Grammar::new(
+            self.rules
+                .iter()
+                .map(|(name, expr)| (*name, expand_optionals(expr)))
+                .collect(),
+        )
+    }
+
+    /// Replaces every `X*` with a reference to a new right recursive rule
+    /// `X_list = X X_list | 'ε'`. Lists of a rule reference are named after it, other ones
+    /// after the rule containing them, and equal repetitions share the same rule
+    pub fn expand_repeats(&self) -> Grammar<'src> {
+        let mut rules = self.rules.clone();
+        let mut lists = Vec::new();
+        for i in 0..self.rules.len() {
+            let (&name, expr) = self.rules.get_index(i).unwrap();
+            rules[i] = expand_repeats(expr, name, &mut rules, &mut lists);
+        }
+        Grammar::new(rules)
+    }
+}
+
+fn expand_optionals<'src>(expr: &Expr<'src>) -> Expr<'src> {
+    match expr {
+        Expr::Literal(_) | Expr::Rule(_) => expr.clone(),
+        Expr::Sequence(exprs) => Expr::Sequence(exprs.iter().map(expand_optionals).collect()),
+        Expr::Choice(exprs) => Expr::Choice(exprs.iter().map(expand_optionals).collect()),
+        Expr::Optional(x) => Expr::Choice(vec![expand_optionals(x), Expr::Literal("ε")]),
+        Expr::Repeat(x) => Expr::Repeat(Box::new(expand_optionals(x))),
+        Expr::OneOrMore(x) => Expr::OneOrMore(Box::new(expand_optionals(x))),
+    }
+}
+
+/// Copy of `expr` with its repetitions replaced by list rules added to `rules`, `lists` maps
+/// each repeated expression to its rule
+fn expand_repeats<'src>(
+    expr: &Expr<'src>,
+    origin: &str,
+    rules: &mut IndexMap<&'src str, Expr<'src>>,
+    lists: &mut Vec<(Expr<'src>, &'src str)>,
+) -> Expr<'src> {
+    let mut expand = |x: &Expr<'src>| expand_repeats(x, origin, rules, lists);
+    match expr {
+        Expr::Literal(_) | Expr::Rule(_) => expr.clone(),
+        Expr::Sequence(exprs) => Expr::Sequence(exprs.iter().map(expand).collect()),
+        Expr::Choice(exprs) => Expr::Choice(exprs.iter().map(expand).collect()),
+        Expr::Optional(x) => Expr::Optional(Box::new(expand(x))),
+        Expr::OneOrMore(x) => Expr::OneOrMore(Box::new(expand(x))),
+        Expr::Repeat(x) => {
+            let item = expand(x);
+            if let Some((_, name)) = lists.iter().find(|(repeated, _)| *repeated == item) {
+                return Expr::Rule(name);
+            }
+
+            let base = match item {
+                Expr::Rule(rule) => rule,
+                _ => origin,
+            };
+            let name = fresh_name(rules, &format!("{base}_list"));
+            let mut sequence = match item.clone() {
+                Expr::Sequence(exprs) => exprs,
+                item => vec![item],
+            };
+            sequence.push(Expr::Rule(name));
+            rules.insert(
+                name,
+                Expr::Choice(vec![Expr::Sequence(sequence), Expr::Literal("ε")]),
+            );
+            lists.push((item, name));
+            Expr::Rule(name)
+        }
+    }
+}
+
+/// Expands `expr` into alternatives made only of literals and rule references, repetitions
+/// become new right recursive rules in `rules` named after `origin`
+fn flatten(
+    expr: &Expr,
+    origin: &str,
+    rules: &mut IndexMap<&'static str, Alternatives>,
+) -> Alternatives {
+    match expr {
+        Expr::Literal(_) | Expr::Rule(_) => vec![vec![expr.to_static()]],
+        Expr::Sequence(exprs) => {
+            let mut alternatives = vec![Vec::new()];
+            for expr in exprs {
+                let parts = flatten(expr, origin, rules);
+                alternatives = alternatives
+                    .iter()
+                    .flat_map(|prefix| {
+                        parts.iter().map(move |part| {
+                            let mut alternative = prefix.clone();
+                            alternative.extend_from_slice(part);
+                            alternative
+                        })
+                    })
+                    .collect();
+            }
+            alternatives
+        }
+        Expr::Choice(branches) => branches
+            .iter()
+            .flat_map(|branch| flatten(branch, origin, rules))
+            .collect(),
+        Expr::Optional(x) => {
+            let mut alternatives = flatten(x, origin, rules);
+            alternatives.push(Vec::new());
+            alternatives
+        }
+        Expr::Repeat(x) | Expr::OneOrMore(x) => {
+            let name = fresh_name(rules, &format!("{origin}_rep"));
+            rules.insert(name, Alternatives::new());
+
+            let body = flatten(x, origin, rules);
+            let mut alternatives = body
+                .iter()
+                .map(|alternative| {
+                    let mut alternative = alternative.clone();
+                    alternative.push(Expr::Rule(name));
+                    alternative
+                })
+                .collect::<Alternatives>();
+            alternatives.extend(body);
+            rules[name] = alternatives;
+
+            match expr {
+                Expr::Repeat(_) => vec![vec![Expr::Rule(name)], Vec::new()],
+                _ => vec![vec![Expr::Rule(name)]],
+            }
+        }
+    }
+}
+
+/// Replaces every `A = B` alternative with the alternatives of `B` that are not a single rule
+/// reference themselves, following chains of them
+fn eliminate_units(rules: &mut IndexMap<&'static str, Alternatives>) {
+    let is_unit = |alternative: &[Expr<'static>]| match alternative {
+        [Expr::Rule(rule)] => rules.contains_key(rule),
+        _ => false,
+    };
+
+    let mut result = IndexMap::new();
+    for &name in rules.keys() {
+        let mut closure = IndexSet::from([name]);
+        let mut i = 0;
+        while let Some(&rule) = closure.get_index(i) {
+            for alternative in &rules[rule] {
+                if let [Expr::Rule(unit)] = alternative.as_slice()
+                    && is_unit(alternative)
+                {
+                    closure.insert(unit);
+                }
+            }
+            i += 1;
+        }
+
+        let mut alternatives = Alternatives::new();
+        for rule in closure {
+            for alternative in &rules[rule] {
+                if !is_unit(alternative) && !alternatives.contains(alternative) {
+                    alternatives.push(alternative.clone());
+                }
+            }
+        }
+        result.insert(name, alternatives);
+    }
+
+    *rules = result;
+}
+
+/// Factors every group of alternatives of `name` starting with the same expression, returns
+/// the helper rules introduced
+fn factor_alternatives(
+    rules: &mut IndexMap<&'static str, Alternatives>,
+    name: &str,
+    origin: &str,
+) -> Vec<&'static str> {
+    let mut helpers = Vec::new();
+    loop {
+        let alternatives = &rules[name];
+        let Some(first) = alternatives
+            .iter()
+            .enumerate()
+            .position(|(i, alternative)| {
+                alternative.first().is_some_and(|head| {
+                    alternatives[i + 1..]
+                        .iter()
+                        .any(|other| other.first() == Some(head))
+                })
+            })
+        else {
+            break;
+        };
+
+        let group = (first..alternatives.len())
+            .filter(|&i| alternatives[i].first() == alternatives[first].first())
+            .collect::<Vec<_>>();
+        let prefix_len = (1..=alternatives[first].len())
+            .take_while(|&len| {
+                group.iter().all(|&i| {
+                    alternatives[i].len() >= len
+                        && alternatives[i][..len] == alternatives[first][..len]
+                })
+            })
+            .last()
+            .unwrap_or(1);
+
+        let mut alternatives = std::mem::take(&mut rules[name]);
+        let mut suffixes = Alternatives::new();
+        for &i in &group {
+            let suffix = alternatives[i][prefix_len..].to_vec();
+            if !suffixes.contains(&suffix) {
+                suffixes.push(suffix);
+            }
+        }
+
+        let mut factored = alternatives[first][..prefix_len].to_vec();
+        // Identical alternatives collapse into one without needing a helper
+        if suffixes.iter().any(|suffix| !suffix.is_empty()) {
+            let helper = (1..)
+                .map(|n| format!("{origin}_rest_{n}"))
+                .find(|helper| !rules.contains_key(helper.as_str()))
+                .map(|helper| intern(&helper))
+                .unwrap();
+            rules.insert(helper, suffixes);
+            factored.push(Expr::Rule(helper));
+            helpers.push(helper);
+        }
+
+        for &i in group[1..].iter().rev() {
+            alternatives.remove(i);
+        }
+        alternatives[first] = factored;
+        rules[name] = alternatives;
+    }
+
+    helpers
+}
+
+/// Like [`from_alternatives`] but empty alternatives turn the rest into an optional
+fn from_factored(mut alternatives: Alternatives) -> Expr<'static> {
+    let len = alternatives.len();
+    alternatives.retain(|alternative| !alternative.is_empty());
+    if alternatives.is_empty() {
+        return Expr::Sequence(Vec::new());
+    }
+
+    if alternatives.len() < len {
+        from_alternatives(vec![vec![Expr::Optional(Box::new(from_alternatives(
+            alternatives,
+        )))]])
+    } else {
+        from_alternatives(alternatives)
+    }
+}
+
+fn alternatives(expr: &Expr) -> Alternatives {
+    expr.branches()
+        .iter()
+        .map(|branch| match branch {
+            Expr::Sequence(exprs) => exprs.iter().map(Expr::to_static).collect(),
+            branch => vec![branch.to_static()],
+        })
+        .collect()
+}
+
+fn from_alternatives(alternatives: Alternatives) -> Expr<'static> {
+    let mut branches = alternatives
+        .into_iter()
+        .map(Expr::Sequence)
+        .collect::<Vec<_>>();
+    if branches.len() == 1 {
+        branches.pop().unwrap()
+    } else {
+        Expr::Choice(branches)
+    }
+}
+
+/// Splits an alternative starting with a group, a choice or an optional into the
+/// alternatives it stands for, i.e `('a' | B) C` becomes `'a' C` and `B C`
+fn expand_leading(alternative: Vec<Expr<'static>>) -> Alternatives {
+    let Some((first, rest)) = alternative.split_first() else {
+        return vec![alternative];
+    };
+
+    let heads = match first {
+        Expr::Sequence(exprs) => vec![exprs.clone()],
+        Expr::Choice(branches) => branches.iter().map(|x| vec![x.clone()]).collect(),
+        Expr::Optional(x) => vec![vec![x.as_ref().clone()], vec![]],
+        _ => return vec![alternative],
+    };
+
+    heads
+        .into_iter()
+        .flat_map(|mut head| {
+            head.extend_from_slice(rest);
+            expand_leading(head)
+        })
+        .collect()
+}
+
+/// Replaces a leading nullable rule with its alternatives and a leading `X*` with `X X*` and
+/// nothing, until the alternative starts with something that cannot derive ε. Rules in
+/// `expanded` are not expanded again so recursive ones end
+fn expand_nullable_leading(
+    alternative: Vec<Expr<'static>>,
+    rules: &IndexMap<&'static str, Alternatives>,
+    nullable: &IndexSet<&str>,
+    expanded: &[&str],
+) -> Alternatives {
+    let Some((first, rest)) = alternative.split_first() else {
+        return vec![alternative];
+    };
+
+    let mut expanded = expanded.to_vec();
+    let heads = match first {
+        Expr::Rule(rule)
+            if nullable.contains(rule) && !expanded.contains(rule) && rules.contains_key(rule) =>
+        {
+            expanded.push(rule);
+            rules[rule].clone()
+        }
+        Expr::Repeat(x) => vec![vec![x.as_ref().clone(), first.clone()], vec![]],
+        _ => return vec![alternative],
+    };
+
+    heads
+        .into_iter()
+        .flat_map(|mut head| {
+            head.extend_from_slice(rest);
+            expand_leading(head)
+        })
+        .flat_map(|alternative| expand_nullable_leading(alternative, rules, nullable, &expanded))
+        .collect()
+}
+
+/// `base` or `base` followed by a number if a rule already uses the name
+fn fresh_name<T>(rules: &IndexMap<&str, T>, base: &str) -> &'static str {
+    let mut name = base.to_owned();
+    let mut i = 1;
+    while rules.contains_key(name.as_str()) {
+        name = format!("{base}{i}");
+        i += 1;
+    }
+    intern(&name)
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::IndexSet;
+
+    use crate::grammar::test::grammar;
+    use crate::grammar::{Expr, Grammar, InlineError};
+
+    #[test]
+    fn test_eliminate_left_recursion() {
+        let grammar = grammar(
+            "
+            Expr = Expr '+' Term | Term
+            Term = Term '*' Factor | Factor
+            Factor = '(' Expr ')' | 'n'
+            ",
+        );
+
+        let result = grammar.eliminate_left_recursion();
+        assert!(result.left_recursive_rules().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+Expr = Term Expr_tail
+Term = Factor Term_tail
+Factor = '(' Expr ')' | 'n'
+Expr_tail = ('+' Term Expr_tail)?
+Term_tail = ('*' Factor Term_tail)?
+"
+        );
+    }
+
+    #[test]
+    fn test_eliminate_hidden_left_recursion() {
+        let grammar = grammar(
+            "
+            A = B A 'x' | 'y'
+            B = 'b'?
+            ",
+        );
+        assert!(!grammar.left_recursive_rules().is_empty());
+
+        let result = grammar.eliminate_left_recursion();
+        assert!(result.left_recursive_rules().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+A = 'b' A 'x' A_tail | 'y' A_tail
+B = 'b'?
+A_tail = ('x' A_tail)?
+"
+        );
+
+        let grammar = self::grammar("S = 'a'* C S 'z' | 'w'\nC = () | 'c'");
+        let result = grammar.eliminate_left_recursion();
+        assert!(result.left_recursive_rules().is_empty());
+        assert!(result.rules["S"]
+            .to_string()
+            .starts_with("'a' 'a'* C S 'z' S_tail"));
+    }
+
+    #[test]
+    fn test_eliminate_left_recursion_behind_tail() {
+        let grammar = grammar("A = A 'a' | ()\nB = A B 'x' | 'y'");
+
+        let result = grammar.eliminate_left_recursion();
+        assert!(result.left_recursive_rules().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+A = A_tail
+B = 'a' A_tail B 'x' B_tail | 'y' B_tail
+A_tail = ('a' A_tail)?
+B_tail = ('x' B_tail)?
+"
+        );
+    }
+
+    #[test]
+    fn test_eliminate_indirect_left_recursion() {
+        let grammar = grammar(
+            "
+            A = B 'a' | 'x'
+            B = (A | 'z') 'b' | 'y'
+            ",
+        );
+
+        let result = grammar.eliminate_left_recursion();
+        assert!(result.left_recursive_rules().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+A = B 'a' | 'x'
+B = 'x' 'b' B_tail | 'z' 'b' B_tail | 'y' B_tail
+B_tail = ('a' 'b' B_tail)?
+"
+        );
+    }
+
+    #[test]
+    fn test_left_factor() {
+        let grammar = grammar(
+            "
+            Stmt = 'if' Expr 'then' Stmt | 'if' Expr 'then' Stmt 'else' Stmt | 'x'
+            Expr = 'a' 'b' 'c' | 'a' 'b' 'd' | 'a' 'e' | 'f'
+            ",
+        );
+
+        let result = grammar.left_factor();
+        assert!(result.ll1_conflicts().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+Stmt = 'if' Expr 'then' Stmt Stmt_rest_1 | 'x'
+Expr = 'a' Expr_rest_1 | 'f'
+Stmt_rest_1 = ('else' Stmt)?
+Expr_rest_1 = 'b' Expr_rest_2 | 'e'
+Expr_rest_2 = 'c' | 'd'
+"
+        );
+        assert_eq!(result.left_factor().rules, result.rules);
+    }
+
+    #[test]
+    fn test_eliminate_unit_productions() {
+        let grammar = grammar(
+            "
+            Expr = Term | Expr '+' Term
+            Term = Factor | Term '*' Factor
+            Factor = 'n' | '(' Expr ')'
+            Loop = Other | 'x'
+            Other = Loop | 'y'
+            ",
+        );
+
+        let result = grammar.eliminate_unit_productions();
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+Expr = Expr '+' Term | Term '*' Factor | 'n' | '(' Expr ')'
+Term = Term '*' Factor | 'n' | '(' Expr ')'
+Factor = 'n' | '(' Expr ')'
+Loop = 'x' | 'y'
+Other = 'y' | 'x'
+"
+        );
+        assert_eq!(result.rules["Factor"], grammar.rules["Factor"]);
+        assert_eq!(result.eliminate_unit_productions().rules, result.rules);
+    }
+
+    #[test]
+    fn test_factor_common_prefix_in_rule() {
+        let grammar = grammar(
+            "
+            Stmt = 'if' Expr 'then' Stmt | 'if' Expr 'then' Stmt 'else' Stmt | 'x'
+            Expr = 'a' 'b' 'c' | 'a' 'b' 'd' | 'a' 'e' | 'f'
+            Expr_rest_1 = 'g'
+            ",
+        );
+
+        let result = grammar.factor_common_prefix_in_rule("Expr");
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+Stmt = 'if' Expr 'then' Stmt | 'if' Expr 'then' Stmt 'else' Stmt | 'x'
+Expr = 'a' Expr_rest_2 | 'f'
+Expr_rest_1 = 'g'
+Expr_rest_2 = 'b' Expr_rest_3 | 'e'
+Expr_rest_3 = 'c' | 'd'
+"
+        );
+
+        let result = grammar.factor_common_prefix_in_rule("Stmt");
+        assert_eq!(
+            result.rules["Stmt"].to_string(),
+            "'if' Expr 'then' Stmt Stmt_rest_1 | 'x'"
+        );
+        assert_eq!(result.rules["Expr"], grammar.rules["Expr"]);
+
+        for unchanged in ["Expr_rest_1", "Missing"] {
+            let result = grammar.factor_common_prefix_in_rule(unchanged);
+            assert_eq!(result.rules, grammar.rules);
+        }
+    }
+
+    #[test]
+    fn test_inline_rule() {
+        let grammar = grammar(
+            "
+            S = A B* | A?
+            A = 'a' | 'b' 'c'
+            B = 'd'
+            ",
+        );
+
+        let inlined = grammar.inline_rule("A").unwrap();
+        assert_eq!(
+            inlined.to_ebnf_string(),
+            "\
+S = ('a' | 'b' 'c') B* | ('a' | 'b' 'c')?
+B = 'd'
+"
+        );
+
+        let inlined = inlined.inline_rule("B").unwrap();
+        assert_eq!(
+            inlined.to_ebnf_string(),
+            "S = ('a' | 'b' 'c') 'd'* | ('a' | 'b' 'c')?\n"
+        );
+    }
+
+    #[test]
+    fn test_inline_rule_errors() {
+        let grammar = grammar(
+            "
+            S = A
+            A = 'a' A?
+            ",
+        );
+
+        assert_eq!(grammar.inline_rule("A").err(), Some(InlineError::Recursive));
+        assert_eq!(
+            grammar.inline_rule("B").err(),
+            Some(InlineError::UnknownRule)
+        );
+    }
+
+    #[test]
+    fn test_expand_optionals() {
+        let grammar = grammar("S = 'a'? (B ','?)*\nB = 'b'");
+        let result = grammar.expand_optionals();
+        assert_eq!(
+            result.to_ebnf_string(),
+            "S = ('a' | 'ε') (B (',' | 'ε'))*\nB = 'b'\n"
+        );
+        assert_eq!(grammar.rules["S"].to_string(), "'a'? (B ','?)*");
+    }
+
+    #[test]
+    fn test_expand_repeats() {
+        let grammar = grammar(
+            "
+            S = A* ('x' A*)*
+            A = 'a' | ('b' 'c')*
+            ",
+        );
+
+        let result = grammar.expand_repeats();
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+S = A_list S_list
+A = 'a' | A_list1
+A_list = A A_list | 'ε'
+S_list = 'x' A_list S_list | 'ε'
+A_list1 = 'b' 'c' A_list1 | 'ε'
+"
+        );
+        assert_eq!(grammar.rules.len(), 2);
+    }
+
+    #[test]
+    fn test_remove_epsilon() {
+        let grammar = grammar(
+            "
+            S = A B 'c'
+            A = 'a'?
+            B = ('b' | 'd')*
+            E = A?
+            F = 'f' E
+            ",
+        );
+
+        let result = grammar.remove_epsilon();
+        assert!(result.nullable_set().is_empty());
+        assert_eq!(
+            result.to_ebnf_string(),
+            "\
+S = A B 'c' | B 'c' | A 'c' | 'c'
+A = 'a'
+B = B_rep
+E = A
+F = 'f' E | 'f'
+B_rep = 'b' B_rep | 'd' B_rep | 'b' | 'd'
+"
+        );
+    }
+
+    /// Every position where a match of `expr` starting at `pos` may end
+    fn matches(grammar: &Grammar, expr: &Expr, input: &[&str], pos: usize) -> IndexSet<usize> {
+        match expr {
+            Expr::Literal(lit) => {
+                IndexSet::from_iter((input.get(pos) == Some(lit)).then_some(pos + 1))
+            }
+            Expr::Rule(rule) => matches(grammar, &grammar.rules[rule], input, pos),
+            Expr::Sequence(exprs) => exprs.iter().fold(IndexSet::from([pos]), |ends, expr| {
+                ends.into_iter()
+                    .flat_map(|end| matches(grammar, expr, input, end))
+                    .collect()
+            }),
+            Expr::Choice(branches) => branches
+                .iter()
+                .flat_map(|branch| matches(grammar, branch, input, pos))
+                .collect(),
+            Expr::Optional(x) => {
+                let mut ends = matches(grammar, x, input, pos);
+                ends.insert(pos);
+                ends
+            }
+            Expr::Repeat(x) | Expr::OneOrMore(x) => {
+                let mut ends = matches(grammar, x, input, pos);
+                let mut i = 0;
+                while let Some(&end) = ends.get_index(i) {
+                    if end != pos {
+                        ends.extend(matches(grammar, x, input, end));
+                    }
+                    i += 1;
+                }
+                if matches!(expr, Expr::Repeat(_)) {
+                    ends.insert(pos);
+                }
+                ends
+            }
+        }
+    }
+
+    /// CYK recognizer, the grammar must be in CNF
+    fn cyk(grammar: &Grammar, start: &str, input: &[&str]) -> bool {
+        let n = input.len();
+        // table[len - 1][i] holds the rules deriving input[i..i + len]
+        let mut table = vec![vec![IndexSet::new(); n]; n];
+        for (name, expr) in &grammar.rules {
+            for alternative in expr.branches() {
+                match alternative {
+                    Expr::Literal(lit) => {
+                        for (i, token) in input.iter().enumerate() {
+                            if token == lit {
+                                table[0][i].insert(*name);
+                            }
+                        }
+                    }
+                    Expr::Sequence(pair) => {
+                        assert!(matches!(pair.as_slice(), [Expr::Rule(_), Expr::Rule(_)]))
+                    }
+                    _ => panic!("not in CNF {alternative:?}"),
+                }
+            }
+        }
+
+        for len in 2..=n {
+            for i in 0..=n - len {
+                for split in 1..len {
+                    for (name, expr) in &grammar.rules {
+                        for alternative in expr.branches() {
+                            if let Expr::Sequence(pair) = alternative
+                                && let [Expr::Rule(a), Expr::Rule(b)] = pair.as_slice()
+                                && table[split - 1][i].contains(a)
+                                && table[len - split - 1][i + split].contains(b)
+                            {
+                                table[len - 1][i].insert(*name);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        table[n - 1][0].contains(start)
+    }
+
+    #[test]
+    fn test_to_cnf() {
+        let grammar = grammar(
+            "
+            S = '(' S ')' S | 'a' B* | Unit
+            B = 'b' | 'c' 'd'?
+            Unit = 'u'+
+            ",
+        );
+
+        let cnf = grammar.to_cnf("S");
+        let start = *cnf.rules.keys().next().unwrap();
+        assert_eq!(start, "S_0");
+
+        let alphabet = ["(", ")", "a", "b", "c", "d", "u"];
+        let mut inputs = vec![Vec::new()];
+        for _ in 0..5 {
+            inputs = inputs
+                .iter()
+                .flat_map(|input| {
+                    alphabet.iter().map(move |token| {
+                        let mut input = input.clone();
+                        input.push(*token);
+                        input
+                    })
+                })
+                .collect();
+
+            for input in &inputs {
+                let expected =
+                    matches(&grammar, &grammar.rules["S"], input, 0).contains(&input.len());
+                assert_eq!(cyk(&cnf, start, input), expected, "{input:?}");
+            }
+        }
+    }
+}
@@ -1,21 +1,89 @@
+use std::collections::VecDeque;
+
 use logos::{Logos, SpannedIter};
 
-use crate::{ring::Ring, token};
+use crate::{
+    diagnostic::Diagnostic,
+    ring::Ring,
+    span::{LineIndex, Location, Span},
+    token,
+};
+
+/// What the lexer does with text no token matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LexerMode {
+    /// Emit a `Kind::Error` token
+    #[default]
+    Silent,
+    /// Panic with the offending character and its location
+    Strict,
+    /// Emit a `Kind::Error` token and record a [`LexError`]
+    Collect,
+}
+
+/// Text no token matches, collected in [`LexerMode::Collect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexError {
+    pub span: Span,
+    /// First character of the span
+    pub char: char,
+}
+
+impl LexError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(self.span, format!("unexpected character {:?}", self.char))
+    }
+}
 
 pub struct Lexer<'src, const LOOKUP: usize> {
     inner: SpannedIter<'src, token::Kind>,
-    buffer_span: Ring<crate::span::Span, LOOKUP>,
+    buffer_span: Ring<Span, LOOKUP>,
     buffer_kind: Ring<token::Kind, LOOKUP>,
-    last_span: crate::span::Span,
+    last_span: Span,
+    /// Trivia lexed so far and not yet taken, `None` when trivia is dropped
+    trivia: Option<VecDeque<token::Token>>,
+    /// Built the first time a location is asked for
+    line_index: Option<LineIndex>,
+    mode: LexerMode,
+    errors: Vec<LexError>,
+    /// End of the last token or trivia taken, where [`Lexer::seek`] resumes from
+    consumed: usize,
 }
 
 impl<'src, const LOOKUP: usize> Lexer<'src, LOOKUP> {
     pub fn new(source: &'src str) -> Self {
+        Self::new_with_mode(source, LexerMode::Silent)
+    }
+
+    pub fn new_with_mode(source: &'src str, mode: LexerMode) -> Self {
+        Self::new_impl(source, None, mode)
+    }
+
+    /// Lexer that keeps whitespace and comments aside, they are taken with
+    /// [`Lexer::next_trivia`] while lookahead only sees significant tokens
+    pub fn with_trivia(source: &'src str) -> Self {
+        Self::with_trivia_and_mode(source, LexerMode::Silent)
+    }
+
+    pub fn with_trivia_and_mode(source: &'src str, mode: LexerMode) -> Self {
+        Self::new_impl(source, Some(VecDeque::new()), mode)
+    }
+
+    fn new_impl(
+        source: &'src str,
+        trivia: Option<VecDeque<token::Token>>,
+        mode: LexerMode,
+    ) -> Self {
         let mut s = Self {
             inner: token::Kind::lexer(source).spanned(),
             buffer_span: Ring::new(),
             buffer_kind: Ring::new(),
-            last_span: crate::span::Span::from(0..0),
+            last_span: Span::from(0..0),
+            trivia,
+            line_index: None,
+            mode,
+            errors: Vec::new(),
+            consumed: 0,
         };
 
         for _ in 0..LOOKUP {
@@ -29,10 +97,59 @@ impl<'src, const LOOKUP: usize> Lexer<'src, LOOKUP> {
         self.inner.source()
     }
 
+    /// Unmatched text lexed so far, always empty unless in [`LexerMode::Collect`]
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    /// Byte offset up to which tokens and trivia were taken
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Lexes again from the byte `offset`, which must be a token boundary like an earlier
+    /// [`Lexer::consumed`]. Lookahead, pending trivia and errors past it are discarded
+    pub fn seek(&mut self, offset: usize) {
+        let mut inner = token::Kind::lexer(self.source());
+        inner.bump(offset);
+        self.inner = inner.spanned();
+        self.last_span = Span::from(offset..offset);
+        self.consumed = offset;
+        if let Some(trivia) = &mut self.trivia {
+            trivia.retain(|token| token.span.start < offset);
+        }
+        self.errors.retain(|error| error.span.start < offset);
+
+        for _ in 0..LOOKUP {
+            self.advance();
+        }
+    }
+
+    /// Line and column of the byte `offset` of the source
+    pub fn location(&mut self, offset: usize) -> Location {
+        let source = self.inner.source();
+        self.line_index
+            .get_or_insert_with(|| LineIndex::new(source))
+            .location_of(offset)
+    }
+
     pub fn peek_array(&self) -> [token::Kind; LOOKUP] {
         self.buffer_kind.data()
     }
 
+    /// Kind of the `n`th lookahead token, `None` past the lookahead buffer
+    pub fn peek_n(&self, n: usize) -> Option<token::Kind> {
+        self.buffer_kind.get(n).copied()
+    }
+
+    /// The `n`th lookahead token, `None` past the lookahead buffer
+    pub fn peek_token_n(&self, n: usize) -> Option<token::Token> {
+        Some(token::Token::new(
+            *self.buffer_span.get(n)?,
+            *self.buffer_kind.get(n)?,
+        ))
+    }
+
     pub fn peek_token(&self) -> token::Token {
         token::Token::new(self.buffer_span[0], self.buffer_kind[0])
     }
@@ -41,6 +158,41 @@ impl<'src, const LOOKUP: usize> Lexer<'src, LOOKUP> {
         self.buffer_kind[0]
     }
 
+    /// Byte offset where the next unconsumed token starts, the length of the source once
+    /// every token was consumed
+    pub fn position(&self) -> usize {
+        if self.is_at_end() {
+            self.source().len()
+        } else {
+            self.buffer_span[0].start
+        }
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.peek_kind() == token::Kind::Eof
+    }
+
+    /// Next trivia token before the current lookahead token, every remaining one at the end
+    pub fn peek_trivia(&self) -> Option<&token::Token> {
+        let before = if self.is_at_end() {
+            usize::MAX
+        } else {
+            self.buffer_span[0].start
+        };
+        self.trivia
+            .as_ref()?
+            .front()
+            .filter(|token| token.span.start < before)
+    }
+
+    /// Takes the token returned by [`Lexer::peek_trivia`]
+    pub fn next_trivia(&mut self) -> Option<token::Token> {
+        self.peek_trivia()?;
+        let token = self.trivia.as_mut()?.pop_front()?;
+        self.consumed = token.span.end;
+        Some(token)
+    }
+
     pub fn advance(&mut self) {
         let (token, span) = self.next_token_impl();
         self.buffer_span.push(span);
@@ -49,36 +201,130 @@ impl<'src, const LOOKUP: usize> Lexer<'src, LOOKUP> {
 
     pub fn next_token(&mut self) -> token::Token {
         let curr = token::Token::new(self.buffer_span[0], self.buffer_kind[0]);
+        if !self.is_at_end() {
+            self.consumed = curr.span.end;
+        }
         self.advance();
         curr
     }
 
-    fn next_token_impl(&mut self) -> (token::Kind, crate::span::Span) {
-        self.inner
-            .next()
-            .map(|(token, span)| {
-                (token.unwrap_or(token::Kind::Error), {
-                    let span = crate::span::Span::from(span);
-                    self.last_span = span;
-                    span
-                })
-            })
-            .unwrap_or((token::Kind::Eof, self.last_span))
+    fn next_token_impl(&mut self) -> (token::Kind, Span) {
+        let source = self.inner.source();
+        for (token, span) in self.inner.by_ref() {
+            let span = Span::from(span);
+            let kind = match token {
+                Ok(kind) => kind,
+                Err(()) => {
+                    let char = source[span.start..].chars().next().unwrap_or_default();
+                    match self.mode {
+                        LexerMode::Silent => {}
+                        LexerMode::Strict => panic!(
+                            "unexpected character {char:?} at {:?}",
+                            span.location(source)
+                        ),
+                        LexerMode::Collect => self.errors.push(LexError { span, char }),
+                    }
+                    token::Kind::Error
+                }
+            };
+            if kind.is_trivia() {
+                if let Some(trivia) = &mut self.trivia {
+                    trivia.push_back(token::Token::new(span, kind));
+                }
+                continue;
+            }
+
+            self.last_span = span;
+            return (kind, span);
+        }
+        (token::Kind::Eof, self.last_span)
     }
 }
 
-impl Iterator for Lexer<'_, 1> {
+impl<const LOOKUP: usize> Iterator for Lexer<'_, LOOKUP> {
     type Item = token::Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        (self.peek_kind() != token::Kind::Eof).then(|| self.next_token())
+        (!self.is_at_end()).then(|| self.next_token())
     }
 }
 
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
+
     use crate::token::Paren;
 
+    #[test]
+    fn test_peek_n() {
+        let mut lexer = super::Lexer::<2>::new("A = 'a'");
+        assert_eq!(lexer.peek_n(0), Some(super::token::Kind::Ident));
+        assert_eq!(lexer.peek_n(1), Some(super::token::Kind::Equal));
+        assert_eq!(lexer.peek_n(2), None);
+
+        lexer.advance();
+        let token = lexer.peek_token_n(1).unwrap();
+        assert_eq!(token.kind, super::token::Kind::Literal);
+        assert_eq!(token.span.range(), 4..7);
+        assert_eq!(lexer.peek_token_n(0), Some(lexer.peek_token()));
+        assert_eq!(lexer.peek_token_n(2), None);
+    }
+
+    #[test]
+    fn test_iter_lookup() {
+        let source = "A = 'a' (B | C)* /* c */ D?\nB = 'b'+";
+        let tokens = super::Lexer::<1>::new(source).collect::<Vec<_>>();
+        assert_eq!(tokens.len(), 15);
+        assert_eq!(super::Lexer::<2>::new(source).collect::<Vec<_>>(), tokens);
+        assert_eq!(super::Lexer::<4>::new(source).collect::<Vec<_>>(), tokens);
+    }
+
+    #[test]
+    fn test_position() {
+        let mut lexer = super::Lexer::<1>::new("A  =\n 'a'");
+        let mut positions = vec![lexer.position()];
+        while !lexer.is_at_end() {
+            lexer.advance();
+            positions.push(lexer.position());
+        }
+        assert_eq!(positions, [0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn test_trivia() {
+        use super::token::Kind::*;
+
+        let mut lexer = super::Lexer::<2>::with_trivia("A /* c */ =\n'a' # end\n");
+        let mut tokens = Vec::new();
+        loop {
+            while let Some(trivia) = lexer.next_trivia() {
+                tokens.push(trivia.kind);
+            }
+            if lexer.is_at_end() {
+                break;
+            }
+            tokens.push(lexer.next_token().kind);
+        }
+        assert_eq!(
+            tokens,
+            [
+                Ident,
+                Ignored,
+                BlockComment,
+                Ignored,
+                Equal,
+                Ignored,
+                Literal,
+                Ignored,
+                Comment,
+                Ignored
+            ]
+        );
+
+        let mut lexer = super::Lexer::<2>::new("A /* c */ = 'a'");
+        assert_eq!(lexer.next_trivia(), None);
+    }
+
     #[test]
     fn test_ring() {
         let source = "(|)a";
@@ -118,4 +364,158 @@ mod test {
             [super::token::Kind::Eof, super::token::Kind::Eof]
         );
     }
+
+    fn kinds(source: &str) -> Vec<super::token::Kind> {
+        super::Lexer::<1>::new(source)
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_block_comment() {
+        use super::token::Kind::*;
+
+        assert_eq!(kinds("A /* comment */ = 'a'"), [Ident, Equal, Literal]);
+        assert_eq!(kinds("A /** ** */ = 'a'"), [Ident, Equal, Literal]);
+        assert_eq!(
+            kinds("A = /* multi\n * line\n */ 'a'"),
+            [Ident, Equal, Literal]
+        );
+    }
+
+    #[test]
+    fn test_block_comment_nested() {
+        use super::token::Kind::*;
+
+        // The comment ends at the first `*/`, the trailing `*/` is an error
+        assert_eq!(
+            kinds("/* outer /* inner */ outer */ A"),
+            [Ident, Star, Error, Ident]
+        );
+    }
+
+    #[test]
+    fn test_block_comment_unterminated() {
+        use super::token::Kind::*;
+
+        let mut lexer = super::Lexer::<1>::new("A = /* never closed\n 'a'");
+        assert_eq!(lexer.next().map(|token| token.kind), Some(Ident));
+        assert_eq!(lexer.next().map(|token| token.kind), Some(Equal));
+        let error = lexer.next().unwrap();
+        assert_eq!(error.kind, Error);
+        assert_eq!(error.span.range(), 4..24);
+        assert_eq!(lexer.next(), None);
+    }
+
+    #[test]
+    fn test_lexer_mode() {
+        use super::{token::Kind::*, LexError, Lexer, LexerMode};
+
+        let source = "A = $ 'a'\n/* open";
+        let silent = Lexer::<1>::new(source);
+        assert_eq!(silent.errors(), []);
+        let kinds = silent.map(|token| token.kind).collect::<Vec<_>>();
+        assert_eq!(kinds, [Ident, Equal, Error, Literal, Error]);
+
+        let mut lexer = Lexer::<1>::new_with_mode(source, LexerMode::Collect);
+        assert_eq!(lexer.by_ref().count(), 5);
+        assert_eq!(
+            lexer.errors(),
+            [
+                LexError {
+                    span: (4..5).into(),
+                    char: '$'
+                },
+                LexError {
+                    span: (10..17).into(),
+                    char: '/'
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic = "unexpected character '$' at 1:5"]
+    fn test_lexer_mode_strict() {
+        super::Lexer::<1>::new_with_mode("A = $", super::LexerMode::Strict).count();
+    }
+
+    #[test]
+    fn test_seek() {
+        use super::{Lexer, LexerMode};
+
+        let source = "A = $ 'a' # c\nB = 'b'";
+        let mut lexer = Lexer::<2>::new_with_mode(source, LexerMode::Collect);
+        let tokens = lexer.by_ref().collect::<Vec<_>>();
+        assert_eq!(lexer.consumed(), source.len());
+        lexer.seek(0);
+        assert_eq!(lexer.by_ref().collect::<Vec<_>>(), tokens);
+        assert_eq!(lexer.errors().len(), 1);
+
+        let mut lexer = Lexer::<2>::with_trivia(source);
+        lexer.next_token();
+        lexer.next_trivia();
+        lexer.next_token();
+        let offset = lexer.consumed();
+        assert_eq!(offset, 3);
+        let rest = |lexer: &mut Lexer<2>| {
+            let mut tokens = Vec::new();
+            loop {
+                tokens.extend(std::iter::from_fn(|| lexer.next_trivia()));
+                if lexer.is_at_end() {
+                    break tokens;
+                }
+                tokens.push(lexer.next_token());
+            }
+        };
+        let expected = rest(&mut lexer);
+        lexer.seek(offset);
+        assert_eq!(lexer.peek_token().span.range(), 4..5);
+        assert_eq!(rest(&mut lexer), expected);
+    }
+
+    /// Text of a single significant token
+    fn token_text() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z0-9_]+",
+            r"'([^'\\]|\\['\\bnfrt])*'",
+            prop::sample::select(&["=", ":", "*", "?", "+", "(", ")", "|"][..])
+                .prop_map(String::from),
+        ]
+    }
+
+    fn lex(source: &str) -> Vec<(super::token::Kind, &str)> {
+        super::Lexer::<1>::new(source)
+            .map(|token| (token.kind, &source[token.span.range()]))
+            .collect()
+    }
+
+    proptest! {
+        #[test]
+        fn test_relex(texts in prop::collection::vec(token_text(), 0..32)) {
+            let source = texts.join(" ");
+            let tokens = lex(&source);
+            let rebuilt = tokens.iter().map(|(_, text)| *text).collect::<Vec<_>>().join(" ");
+            prop_assert_eq!(lex(&rebuilt), tokens);
+        }
+
+        #[test]
+        fn test_spans_tile_source(source in any::<String>()) {
+            let mut lexer = super::Lexer::<1>::with_trivia(&source);
+            let mut end = 0;
+            loop {
+                while let Some(trivia) = lexer.next_trivia() {
+                    prop_assert_eq!(trivia.span.start, end);
+                    end = trivia.span.end;
+                }
+                if lexer.is_at_end() {
+                    break;
+                }
+                let token = lexer.next_token();
+                prop_assert_eq!(token.span.start, end);
+                end = token.span.end;
+            }
+            prop_assert_eq!(end, source.len());
+        }
+    }
 }
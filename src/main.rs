@@ -1,52 +1,85 @@
 #![feature(iter_map_windows)]
 #![feature(let_chains)]
 
-use std::collections::HashSet;
+use std::path::Path;
 
 use clap::Parser;
-use indexmap::IndexSet;
 
 mod args;
+mod diagnostic;
 mod grammar;
 mod lexer;
 mod parser;
 mod ring;
+mod source;
 mod span;
 mod token;
 
+/// Reads the grammar at `path`, or stdin when it is `-`
+fn read_source(path: &Path) -> source::SourceFile {
+    if path == Path::new("-") {
+        source::SourceFile::new(
+            "<stdin>",
+            std::io::read_to_string(std::io::stdin()).unwrap(),
+        )
+    } else {
+        source::SourceFile::new(
+            path.display().to_string(),
+            std::fs::read_to_string(path).unwrap(),
+        )
+    }
+}
+
 fn main() {
     let args = args::Args::parse();
 
     match args.command {
         args::Command::Lex { path } => {
-            let source = std::fs::read_to_string(&path).unwrap();
-            let lexer = lexer::Lexer::new(&source);
+            let file = read_source(&path);
+            let source = &file.content;
+            let lexer = lexer::Lexer::<1>::new(source);
             let tokens = lexer.collect::<Vec<_>>();
             println!("{tokens:?}");
         }
         args::Command::Tree { path } => {
-            let source = std::fs::read_to_string(&path).unwrap();
-            let mut parser = parser::Parser::new(&source);
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
             parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
             let tree = parser.tree();
 
             println!("{tree:#?}");
         }
         args::Command::Parse { path } => {
-            let source = std::fs::read_to_string(&path).unwrap();
-            let mut parser = parser::Parser::new(&source);
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
             parser.parse();
-            let grammar = grammar::GrammarBuilder::new(&source, parser.tree()).build();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let (grammar, diagnostics) =
+                grammar::GrammarBuilder::new(source, parser.tree()).build_with_diagnostics();
+            for diagnostic in diagnostics {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
 
             println!("{grammar:#?}");
         }
         args::Command::First { path, non_terminal } => {
-            let source = std::fs::read_to_string(&path).unwrap();
-            let mut parser = parser::Parser::new(&source);
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
             parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
             let tree = parser.tree();
 
-            let grammar = grammar::GrammarBuilder::new(&source, tree).build();
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
 
             if let Some(nt) = non_terminal {
                 let first = grammar.first_set(&nt);
@@ -63,26 +96,251 @@ fn main() {
             non_terminal,
             strict,
         } => {
-            let source = std::fs::read_to_string(&path).unwrap();
-            let mut parser = parser::Parser::new(&source);
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+
+            let follow = |nt: &str| match strict {
+                true => grammar.follow_set_strict(nt),
+                false => grammar.follow_set(nt),
+            };
+
+            if let Some(nt) = non_terminal {
+                println!("{:?}", follow(&nt));
+            } else {
+                for nt in grammar.non_terminals() {
+                    println!("{nt}: {:?}", follow(nt));
+                }
+            }
+        }
+        args::Command::Nullable {
+            path,
+            non_terminal,
+            json,
+        } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+            let nullable = grammar.nullable_set();
+
+            let non_terminals = match &non_terminal {
+                Some(nt) => vec![nt.as_str()],
+                None => grammar.non_terminals().into_iter().collect(),
+            };
+
+            if json {
+                let entries = non_terminals
+                    .iter()
+                    .map(|nt| format!("{}:{}", grammar::json_string(nt), nullable.contains(nt)))
+                    .collect::<Vec<_>>();
+                println!("{{{}}}", entries.join(","));
+            } else {
+                for nt in non_terminals {
+                    println!("{nt}: {}", nullable.contains(nt));
+                }
+            }
+        }
+        args::Command::Format {
+            path,
+            inplace,
+            sort,
+        } => {
+            if inplace && path == Path::new("-") {
+                eprintln!("error: stdin cannot be rewritten in place");
+                std::process::exit(1);
+            }
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            let diagnostics = parser.diagnostics();
+            // Rules with errors are left out of the grammar, formatting would drop them
+            if !diagnostics.is_empty() {
+                for diagnostic in diagnostics {
+                    eprint!("{}", diagnostic.render_in(&file));
+                }
+                std::process::exit(1);
+            }
+            let tree = parser.tree();
+
+            let mut grammar = grammar::GrammarBuilder::new(source, tree).build();
+            if sort {
+                grammar.sort_alternatives();
+            }
+
+            let formatted = grammar.to_ebnf_string();
+            if inplace {
+                std::fs::write(&path, formatted).unwrap();
+            } else {
+                print!("{formatted}");
+            }
+        }
+        args::Command::Table { path, json, csv } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
             parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
             let tree = parser.tree();
 
-            let grammar = grammar::GrammarBuilder::new(&source, tree).build();
-
-            for nt in grammar.non_terminals() {
-                let mut follow = HashSet::new();
-                for (name, rule) in grammar.rules.iter() {
-                    let f = grammar.follow_set_impl(
-                        &nt,
-                        name,
-                        rule,
-                        &mut IndexSet::from([*name]),
-                        strict,
-                    );
-                    follow.extend(f);
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+            let undefined = grammar.undefined_rules();
+            if !undefined.is_empty() {
+                for rule in undefined {
+                    eprintln!("error: rule `{rule}` is referenced but never defined");
+                }
+                std::process::exit(1);
+            }
+            let left_recursive = grammar.left_recursive_rules();
+            if !left_recursive.is_empty() {
+                for rule in left_recursive.keys() {
+                    eprintln!("error: rule `{rule}` is left recursive");
                 }
-                println!("{nt}: {follow:?}");
+                std::process::exit(1);
+            }
+
+            let table = grammar.ll1_table();
+            if json {
+                println!("{}", table.to_json());
+            } else if csv {
+                print!("{}", table.to_csv());
+            } else {
+                grammar.print_table(&mut std::io::stdout()).unwrap();
+            }
+
+            if table.has_conflicts() {
+                std::process::exit(1);
+            }
+        }
+        args::Command::Validate { path, strict, json } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            // Comments are kept for the `@allow_left_recursion` annotations
+            let options = parser::ParseOptions {
+                full_fidelity: true,
+                ..Default::default()
+            };
+            let mut parser = parser::Parser::new_with_options(source, options);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+            let report = grammar.validate();
+
+            if json {
+                println!("{}", report.to_json());
+            } else {
+                for error in &report.errors {
+                    println!("error: {}", file.error(error));
+                }
+                for warning in &report.warnings {
+                    println!("warning: {}", file.error(warning));
+                }
+            }
+
+            std::process::exit(report.exit_code(strict));
+        }
+        args::Command::Stats { path, json } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+            let stats = grammar.stats();
+            if json {
+                println!("{}", stats.to_json());
+            } else {
+                print!("{stats}");
+            }
+        }
+        args::Command::Dot {
+            path,
+            start,
+            output,
+        } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+
+            let dot = match start {
+                Some(start) => grammar.to_dot_string_from(&start),
+                None => grammar.to_dot_string(),
+            };
+
+            match output {
+                Some(output) => std::fs::write(output, dot).unwrap(),
+                None => print!("{dot}"),
+            }
+        }
+        args::Command::Generate {
+            path,
+            output,
+            style,
+        } => {
+            let file = read_source(&path);
+            let source = &file.content;
+            let mut parser = parser::Parser::new(source);
+            parser.parse();
+            for diagnostic in parser.diagnostics() {
+                eprint!("{}", diagnostic.render_in(&file));
+            }
+            let tree = parser.tree();
+
+            let grammar = grammar::GrammarBuilder::new(source, tree).build();
+            let undefined = grammar.undefined_rules();
+            if !undefined.is_empty() {
+                for rule in undefined {
+                    eprintln!("error: rule `{rule}` is referenced but never defined");
+                }
+                std::process::exit(1);
+            }
+            let left_recursive = grammar.left_recursive_rules();
+            if matches!(style, args::GenStyle::RecursiveDescent) && !left_recursive.is_empty() {
+                for rule in left_recursive.keys() {
+                    eprintln!("error: rule `{rule}` is left recursive");
+                }
+                std::process::exit(1);
+            }
+
+            let code = match style {
+                args::GenStyle::RecursiveDescent => grammar.to_recursive_descent_string(),
+                args::GenStyle::ParserCombinator => grammar.to_parser_combinator_string(),
+            };
+
+            match output {
+                Some(output) => std::fs::write(output, code).unwrap(),
+                None => print!("{code}"),
             }
         }
     }
@@ -1,6 +1,12 @@
 //! Recursive descent parser
 
-use crate::{lexer::Lexer, token};
+use crate::{
+    diagnostic::Diagnostic,
+    grammar::{Expr, GrammarBuilder},
+    lexer::{LexError, Lexer, LexerMode},
+    span::{Location, Span},
+    token,
+};
 
 #[derive(Debug)]
 pub struct Tree {
@@ -8,6 +14,122 @@ pub struct Tree {
     pub children: Vec<Child>,
 }
 
+impl Tree {
+    pub fn has_errors(&self) -> bool {
+        self.kind == Kind::Error
+            || self.children.iter().any(|child| match child {
+                Child::Tree(tree) => tree.has_errors(),
+                Child::Token(_) => false,
+            })
+    }
+
+    /// Every `Kind::Error` node in the tree, outermost first
+    pub fn error_nodes(&self) -> Vec<&Tree> {
+        let mut nodes = Vec::new();
+        self.error_nodes_impl(&mut nodes);
+        nodes
+    }
+
+    /// Span covering every token below the tree, `None` when there are none.
+    ///
+    /// Tokens the parser skips such as parenthesis or `*` are not part of the tree, so they
+    /// are only covered when they sit between other tokens
+    pub fn span(&self) -> Option<Span> {
+        Span::union(self.children.iter().filter_map(Child::span))
+    }
+
+    /// Source text covered by [`Tree::span`]
+    pub fn source_text<'s>(&self, source: &'s str) -> Option<&'s str> {
+        self.span().map(|span| &source[span.range()])
+    }
+
+    /// Lisp style rendering of the tree, tokens are written as their source text i.e
+    /// `(Grammar (Rule A (Branch (Sequence 'a' (ZeroOrMore B)))))`
+    pub fn to_sexp(&self, source: &str) -> String {
+        let mut out = String::new();
+        self.write_sexp(&mut out, source);
+        out
+    }
+
+    fn write_sexp(&self, out: &mut String, source: &str) {
+        out.push_str(&format!("({:?}", self.kind));
+        for child in &self.children {
+            out.push(' ');
+            match child {
+                Child::Tree(tree) => tree.write_sexp(out, source),
+                Child::Token(token) => out.push_str(&source[token.span.range()]),
+            }
+        }
+        out.push(')');
+    }
+
+    /// Every tree of the given kind including `self`, in pre-order
+    pub fn find_all(&self, kind: Kind) -> Vec<&Tree> {
+        self.trees().filter(|tree| tree.kind == kind).collect()
+    }
+
+    /// First tree of the given kind in pre-order, `self` included
+    pub fn find_first(&self, kind: Kind) -> Option<&Tree> {
+        self.trees().find(|tree| tree.kind == kind)
+    }
+
+    /// `self` and every descendant tree in pre-order, driven by an explicit stack so deep
+    /// trees cannot overflow
+    fn trees(&self) -> impl Iterator<Item = &Tree> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let tree = stack.pop()?;
+            stack.extend(tree.children.iter().rev().filter_map(|child| match child {
+                Child::Tree(tree) => Some(tree),
+                Child::Token(_) => None,
+            }));
+            Some(tree)
+        })
+    }
+
+    /// Depth-first pre-order traversal, each tree is visited before its children
+    pub fn walk(&self, visitor: &mut impl Visitor) {
+        if !visitor.visit_tree(self) {
+            return;
+        }
+        for child in &self.children {
+            match child {
+                Child::Tree(tree) => tree.walk(visitor),
+                Child::Token(token) => visitor.visit_token(token),
+            }
+        }
+    }
+
+    /// Depth-first post-order traversal, each tree is visited after its children
+    pub fn walk_post(&self, visitor: &mut impl Visitor) {
+        for child in &self.children {
+            match child {
+                Child::Tree(tree) => tree.walk_post(visitor),
+                Child::Token(token) => visitor.visit_token(token),
+            }
+        }
+        visitor.visit_tree(self);
+    }
+
+    fn error_nodes_impl<'a>(&'a self, nodes: &mut Vec<&'a Tree>) {
+        if self.kind == Kind::Error {
+            nodes.push(self);
+        }
+        for child in &self.children {
+            if let Child::Tree(tree) = child {
+                tree.error_nodes_impl(nodes);
+            }
+        }
+    }
+}
+
+/// Callbacks for [`Tree::walk`] and [`Tree::walk_post`]
+pub trait Visitor {
+    /// Called for every tree, returning `false` skips its children during a pre-order walk
+    fn visit_tree(&mut self, tree: &Tree) -> bool;
+    fn visit_token(&mut self, token: &token::Token);
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum Event {
     Open { kind: Kind },
@@ -22,17 +144,108 @@ pub enum Child {
     Token(token::Token),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Child {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Child::Tree(tree) => tree.span(),
+            Child::Token(token) => Some(token.span),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Child::Tree(Tree {
+                kind: Kind::Error,
+                ..
+            })
+        )
+    }
+
+    /// Whitespace or comment token, only present when trivia is preserved
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Child::Token(token) if token.kind.is_trivia())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Kind {
     Grammar,
     Rule,
     Sequence,
     ZeroOrMore,
+    OneOrMore,
     Optional,
     Branch,
     Error,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub expected: token::Kind,
+    pub found: token::Token,
+    pub location: Location,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Expected {}, got {} at {:?}",
+            self.expected, self.found, self.location
+        )
+    }
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::error(
+            self.found.span,
+            format!(
+                "Expected {}, got {}",
+                self.expected.description(),
+                self.found.kind.description()
+            ),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Nesting of parenthesized groups after which the group becomes a `Kind::Error` node
+    pub max_depth: Option<usize>,
+    /// Resume at the next rule after an error, otherwise the rest of the source becomes a
+    /// single `Kind::Error` node
+    pub error_recovery: bool,
+    /// Keep whitespace and comments as tokens of the tree
+    pub preserve_trivia: bool,
+    /// Attach whitespace and comments to the tokens of the tree instead, trivia up to the end
+    /// of the line is trailing trivia of the previous token and the rest leading trivia of
+    /// the next one
+    pub full_fidelity: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: None,
+            error_recovery: true,
+            preserve_trivia: false,
+            full_fidelity: false,
+        }
+    }
+}
+
+/// State of a [`Parser`] to go back to with [`Parser::restore`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    consumed: usize,
+    events: usize,
+    errors: usize,
+    other_errors: usize,
+    depth: usize,
+}
+
 struct MarkOpen {
     index: usize,
 }
@@ -44,30 +257,244 @@ struct MarkClose {
 pub struct Parser<'src> {
     lexer: Lexer<'src, 2>,
     events: Vec<Event>,
+    errors: Vec<ParseError>,
+    /// Problems other than unexpected tokens, like groups nested too deep
+    other_errors: Vec<Diagnostic>,
+    options: ParseOptions,
+    /// Parenthesized groups currently open
+    depth: usize,
+    /// Receives a line for every event, see [`Parser::new_with_trace`]
+    trace: Option<Box<dyn std::io::Write>>,
 }
 
 impl<'src> Parser<'src> {
     pub fn new(source: &'src str) -> Self {
+        Self::new_with_options(source, ParseOptions::default())
+    }
+
+    pub fn new_with_options(source: &'src str, options: ParseOptions) -> Self {
+        let mode = match options.error_recovery {
+            true => LexerMode::Collect,
+            false => LexerMode::Silent,
+        };
         Self {
-            lexer: Lexer::new(source),
+            lexer: if options.preserve_trivia || options.full_fidelity {
+                Lexer::with_trivia_and_mode(source, mode)
+            } else {
+                Lexer::new_with_mode(source, mode)
+            },
             events: Vec::new(),
+            errors: Vec::new(),
+            other_errors: Vec::new(),
+            options,
+            depth: 0,
+            trace: None,
+        }
+    }
+
+    /// Parser that writes every event it emits to `trace` as it goes, one per line like
+    /// `Advance Ident 0..1`. Opening and closing a node show the position of the next token
+    pub fn new_with_trace(source: &'src str, trace: Box<dyn std::io::Write>) -> Self {
+        Self {
+            trace: Some(trace),
+            ..Self::new(source)
+        }
+    }
+
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    /// Saves the position of the parser so parsing can be undone by [`Parser::restore`]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            consumed: self.lexer.consumed(),
+            events: self.events.len(),
+            errors: self.errors.len(),
+            other_errors: self.other_errors.len(),
+            depth: self.depth,
         }
     }
 
+    /// Drops the events and errors since `checkpoint` was taken and lexes again from there,
+    /// nodes opened since are forgotten
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.lexer.seek(checkpoint.consumed);
+        self.events.truncate(checkpoint.events);
+        self.errors.truncate(checkpoint.errors);
+        self.other_errors.truncate(checkpoint.other_errors);
+        self.depth = checkpoint.depth;
+        let position = checkpoint.consumed;
+        self.trace(|| format!("Restore {:?}", Span::new(position, position)));
+    }
+
+    /// Characters no token matched, only collected with error recovery
+    pub fn lex_errors(&self) -> &[LexError] {
+        self.lexer.errors()
+    }
+
+    /// Every problem found while parsing, in source order. Characters no token matched are
+    /// reported on their own instead of as the unexpected token
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let lex_errors = self.lexer.errors();
+        let mut diagnostics = self
+            .errors
+            .iter()
+            .filter(|error| !lex_errors.iter().any(|lex| lex.span == error.found.span))
+            .map(ParseError::diagnostic)
+            .chain(self.other_errors.iter().cloned())
+            .chain(lex_errors.iter().map(LexError::diagnostic))
+            .collect::<Vec<_>>();
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        diagnostics
+    }
+
+    /// Whether the next tokens start a new rule i.e `Name =`, the name is skipped to look at
+    /// what follows and the parser goes back to it
+    fn at_rule_start(&mut self) -> bool {
+        if self.peek() != token::Kind::Ident {
+            return false;
+        }
+
+        let checkpoint = self.checkpoint();
+        self.skip();
+        let at_rule_start = self.peek() == token::Kind::Equal;
+        self.restore(checkpoint);
+        at_rule_start
+    }
+
+    /// Records the error and wraps every token up to the start of the next rule in an
+    /// error node
+    fn error(&mut self, expected: token::Kind) {
+        let found = self.lexer.peek_token();
+        self.errors.push(ParseError {
+            expected,
+            location: self.lexer.location(found.span.start),
+            found,
+        });
+
+        let opened = self.open();
+        while !self.eof() && !(self.options.error_recovery && self.at_rule_start()) {
+            self.advance();
+        }
+        self.close(opened, Kind::Error);
+    }
+
+    /// Whether opening another parenthesized group exceeds [`ParseOptions::max_depth`]
+    fn too_deep(&self) -> bool {
+        self.options.max_depth.is_some_and(|max| self.depth >= max)
+    }
+
+    /// Records the error and wraps the group starting at the current `(`, its suffix included,
+    /// in an error node without descending into it
+    fn depth_error(&mut self) {
+        let max = self.options.max_depth.unwrap_or_default();
+        self.other_errors.push(Diagnostic::error(
+            self.lexer.peek_token().span,
+            format!("groups nested deeper than {max} levels"),
+        ));
+
+        let opened = self.open();
+        let mut open_groups = 0;
+        while !self.eof() {
+            match self.lexer.peek_kind() {
+                token::Kind::Paren(token::Paren::Open) => open_groups += 1,
+                token::Kind::Paren(token::Paren::Close) => open_groups -= 1,
+                _ => {}
+            }
+            self.advance();
+            if open_groups == 0 {
+                break;
+            }
+        }
+        if matches!(
+            self.lexer.peek_kind(),
+            token::Kind::Star | token::Kind::Question | token::Kind::Plus
+        ) {
+            self.advance();
+        }
+        self.close(opened, Kind::Error);
+    }
+
+    /// Emits the trivia preceding the next token when it is preserved, in full fidelity mode
+    /// it waits to be attached to the next token of the tree
+    fn trivia(&mut self) {
+        if self.options.full_fidelity {
+            return;
+        }
+        while let Some(token) = self.lexer.next_trivia() {
+            self.push_advance(token);
+        }
+    }
+
+    /// Emits the trivia left at the end of the source, in full fidelity mode it becomes
+    /// trailing trivia of the last token
+    fn end_trivia(&mut self) {
+        if self.options.full_fidelity
+            && let Some(token) = self.events.iter_mut().rev().find_map(|event| match event {
+                Event::Advance { token } => Some(token),
+                _ => None,
+            })
+        {
+            let rest = std::iter::from_fn(|| self.lexer.next_trivia());
+            token.trailing_trivia.extend(rest);
+            return;
+        }
+
+        while let Some(token) = self.lexer.next_trivia() {
+            self.push_advance(token);
+        }
+    }
+
+    /// Whether the trivia token spans more than one line
+    fn is_multiline(&self, trivia: &token::Token) -> bool {
+        self.lexer.source()[trivia.span.range()].contains('\n')
+    }
+
     fn eof(&mut self) -> bool {
-        self.lexer.peek_kind() == token::Kind::Eof
+        self.lexer.is_at_end()
     }
 
     fn advance(&mut self) {
-        let token = self.lexer.next_token();
-        self.events.push(Event::Advance { token });
+        if !self.options.full_fidelity {
+            self.trivia();
+            let token = self.lexer.next_token();
+            self.push_advance(token);
+            return;
+        }
+
+        let leading_trivia = std::iter::from_fn(|| self.lexer.next_trivia()).collect();
+        let mut token = self.lexer.next_token();
+        token.leading_trivia = leading_trivia;
+        while let Some(trivia) = self.lexer.peek_trivia()
+            && !self.is_multiline(trivia)
+        {
+            token.trailing_trivia.extend(self.lexer.next_trivia());
+        }
+        self.push_advance(token);
     }
 
     fn skip(&mut self) {
-        self.lexer.advance();
+        self.trivia();
+        let token = self.lexer.next_token();
+        self.trace(|| format!("Skip {:?} {:?}", token.kind, token.span));
         self.events.push(Event::Skip);
     }
 
+    fn push_advance(&mut self, token: token::Token) {
+        self.trace(|| format!("Advance {:?} {:?}", token.kind, token.span));
+        self.events.push(Event::Advance { token });
+    }
+
+    /// Writes the line to the trace output, if any. Failing to write is not a parse error
+    fn trace(&mut self, line: impl FnOnce() -> String) {
+        use std::io::Write;
+
+        if let Some(trace) = &mut self.trace {
+            let _ = writeln!(trace, "{}", line());
+        }
+    }
+
     fn skip_if(&mut self, kind: token::Kind) -> bool {
         if self.lexer.peek_kind() == kind {
             self.skip();
@@ -77,19 +504,18 @@ impl<'src> Parser<'src> {
         }
     }
 
-    fn skip_expect(&mut self, kind: token::Kind) {
-        if !self.skip_if(kind) {
-            let token = self.lexer.peek_token();
-            panic!(
-                "Skip expected {:?}, got {:?} at {:?}",
-                kind,
-                token,
-                token.span.location(self.lexer.source())
-            );
+    fn skip_expect(&mut self, kind: token::Kind) -> bool {
+        if self.skip_if(kind) {
+            true
+        } else {
+            self.error(kind);
+            false
         }
     }
 
     fn open(&mut self) -> MarkOpen {
+        let position = self.lexer.position();
+        self.trace(|| format!("Open {:?}", Span::new(position, position)));
         self.events.push(Event::Open { kind: Kind::Error });
         MarkOpen {
             index: self.events.len() - 1,
@@ -97,6 +523,8 @@ impl<'src> Parser<'src> {
     }
 
     fn close(&mut self, opened: MarkOpen, kind: Kind) -> MarkClose {
+        let position = self.lexer.position();
+        self.trace(|| format!("Close {kind:?} {:?}", Span::new(position, position)));
         self.events[opened.index] = Event::Open { kind };
         self.events.push(Event::Close);
         MarkClose {
@@ -105,6 +533,14 @@ impl<'src> Parser<'src> {
     }
 
     fn open_before(&mut self, opened: MarkClose) -> MarkOpen {
+        let position = self.events[opened.index..]
+            .iter()
+            .find_map(|event| match event {
+                Event::Advance { token } => Some(token.span.start),
+                _ => None,
+            })
+            .unwrap_or(self.lexer.position());
+        self.trace(|| format!("Open {:?}", Span::new(position, position)));
         self.events
             .insert(opened.index, Event::Open { kind: Kind::Error });
         MarkOpen {
@@ -112,17 +548,12 @@ impl<'src> Parser<'src> {
         }
     }
 
-    fn expect(&mut self, kind: token::Kind) {
+    fn expect(&mut self, kind: token::Kind) -> bool {
         if self.advance_if(kind) {
-            return;
+            true
         } else {
-            let token = self.lexer.peek_token();
-            panic!(
-                "Expected {:?}, got {:?} at {:?}",
-                kind,
-                token,
-                token.span.location(self.lexer.source())
-            );
+            self.error(kind);
+            false
         }
     }
 
@@ -139,6 +570,10 @@ impl<'src> Parser<'src> {
         self.lexer.peek_array()
     }
 
+    pub fn peek_n(&mut self, n: usize) -> Option<token::Kind> {
+        self.lexer.peek_n(n)
+    }
+
     pub fn peek(&mut self) -> token::Kind {
         self.lexer.peek_kind()
     }
@@ -147,32 +582,60 @@ impl<'src> Parser<'src> {
         grammar::file(self);
     }
 
+    /// Parses a single rule body like `'(' Param* ')'` from the current position, without
+    /// a leading `Name =`. [`ParseOptions::max_depth`] does not apply to it
+    pub fn parse_rule_body(&mut self) -> Result<Expr<'src>, ParseError> {
+        let events = self.events.len();
+        let errors = self.errors.len();
+        let max_depth = self.options.max_depth.take();
+        grammar::expr(self);
+        self.options.max_depth = max_depth;
+
+        let events = self.events.split_off(events);
+        if let Some(error) = self.errors.drain(errors..).next() {
+            return Err(error);
+        }
+
+        let tree = build_tree(events);
+        Ok(GrammarBuilder::new(self.lexer.source(), tree)
+            .build_expr()
+            .expect("rule bodies without errors always build"))
+    }
+
     pub fn tree(mut self) -> Tree {
-        let mut stack = Vec::new();
+        assert_eq!(self.events.last(), Some(&Event::Close));
+        build_tree(std::mem::take(&mut self.events))
+    }
+}
 
-        assert_eq!(self.events.pop(), Some(Event::Close));
+/// Turns balanced events into the tree of the outermost node
+fn build_tree(events: Vec<Event>) -> Tree {
+    let mut stack = Vec::new();
+    let mut root = None;
 
-        for event in self.events {
-            match event {
-                Event::Open { kind } => {
-                    stack.push(Tree {
-                        kind,
-                        children: Vec::new(),
-                    });
-                }
-                Event::Close => {
-                    let tree = stack.pop().unwrap();
-                    stack.last_mut().unwrap().children.push(Child::Tree(tree));
-                }
-                Event::Skip => {}
-                Event::Advance { token } => {
-                    stack.last_mut().unwrap().children.push(Child::Token(token));
+    for event in events {
+        match event {
+            Event::Open { kind } => {
+                stack.push(Tree {
+                    kind,
+                    children: Vec::new(),
+                });
+            }
+            Event::Close => {
+                let tree = stack.pop().unwrap();
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(Child::Tree(tree)),
+                    None => root = Some(tree),
                 }
             }
+            Event::Skip => {}
+            Event::Advance { token } => {
+                stack.last_mut().unwrap().children.push(Child::Token(token));
+            }
         }
-
-        stack.pop().unwrap()
     }
+
+    root.unwrap()
 }
 
 mod grammar {
@@ -186,19 +649,19 @@ mod grammar {
         while !p.eof() {
             rule(p);
         }
+        p.end_trivia();
 
         p.close(opened, super::Kind::Grammar);
     }
 
     fn term(p: &mut Parser) {
         match p.peek() {
+            // The rule body is missing, leave the next rule untouched
+            Ident if p.at_rule_start() => p.error(Ident),
             Ident | Literal => {
-                let star_or_question = if matches!(p.peek_array(), [_, Star]) {
-                    Some(Star)
-                } else if matches!(p.peek_array(), [_, Question]) {
-                    Some(Question)
-                } else {
-                    None
+                let star_or_question = match p.peek_n(1) {
+                    Some(suffix @ (Star | Question | Plus)) => Some(suffix),
+                    _ => None,
                 };
 
                 if let Some(star_or_question) = star_or_question {
@@ -210,6 +673,7 @@ mod grammar {
                         match star_or_question {
                             Star => super::Kind::ZeroOrMore,
                             Question => super::Kind::Optional,
+                            Plus => super::Kind::OneOrMore,
                             _ => unreachable!(),
                         },
                     );
@@ -217,9 +681,12 @@ mod grammar {
                     p.advance();
                 }
             }
+            Paren(Open) if p.too_deep() => p.depth_error(),
             Paren(Open) => {
                 p.skip();
+                p.depth += 1;
                 let close = expr(p);
+                p.depth -= 1;
                 p.skip_expect(Paren(Close));
 
                 if p.peek() == Star {
@@ -230,13 +697,18 @@ mod grammar {
                     let mark = p.open_before(close);
                     p.skip();
                     p.close(mark, super::Kind::Optional);
+                } else if p.peek() == Plus {
+                    let mark = p.open_before(close);
+                    p.skip();
+                    p.close(mark, super::Kind::OneOrMore);
                 }
             }
-            _ => panic!("Unexpected token"),
+            // A term may also start with a literal or a parenthesis
+            _ => p.error(Ident),
         }
     }
 
-    fn expr(p: &mut Parser) -> MarkClose {
+    pub fn expr(p: &mut Parser) -> MarkClose {
         let opened = p.open();
 
         let mut variant = p.open();
@@ -249,7 +721,7 @@ mod grammar {
                     p.close(variant, super::Kind::Sequence);
                     variant = p.open();
                 }
-                Ident if p.peek_array() == [Ident, Equal] => {
+                Ident if p.at_rule_start() => {
                     break;
                 }
                 Ident | Literal | Paren(Open) => {
@@ -265,11 +737,487 @@ mod grammar {
 
     fn rule(p: &mut Parser) {
         let opened = p.open();
-        p.expect(Ident);
-        p.skip_expect(Equal);
+        if !p.expect(Ident) || !p.skip_expect(Equal) {
+            p.close(opened, super::Kind::Rule);
+            return;
+        }
 
         expr(p);
 
         p.close(opened, super::Kind::Rule);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use expect_test::{expect, Expect};
+
+    use crate::diagnostic::Diagnostic;
+    use crate::grammar::{Expr, GrammarBuilder};
+    use crate::parser::{Child, Kind, ParseOptions, Parser, Tree, Visitor};
+    use crate::span::Span;
+    use crate::token;
+
+    #[test]
+    fn test_error_recovery() {
+        let source = "A = 'a'\n= 'b'\nD = )\nE =\nF = ('f'\nG = 'g'";
+        let mut parser = Parser::new(source);
+        parser.parse();
+
+        let errors = parser
+            .errors()
+            .iter()
+            .map(|error| (error.expected, error.found.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                (token::Kind::Ident, token::Kind::Equal),
+                (token::Kind::Ident, token::Kind::Paren(token::Paren::Close)),
+                (token::Kind::Ident, token::Kind::Ident),
+                (token::Kind::Paren(token::Paren::Close), token::Kind::Ident),
+            ]
+        );
+        assert_eq!(format!("{:?}", parser.errors()[0].location), "2:1");
+        assert_eq!(
+            parser.errors()[0].to_string(),
+            "Expected identifier, got = at 2:1"
+        );
+        assert_eq!(
+            parser.diagnostics()[0].render(source),
+            "error: Expected identifier, got equals sign\n --> 2:1\n  |\n2 | = 'b'\n  | ^\n"
+        );
+
+        let tree = parser.tree();
+        assert_eq!(tree.kind, Kind::Grammar);
+        assert_eq!(tree.children.len(), 6);
+        let Child::Tree(rule) = &tree.children[1] else {
+            panic!("expected tree");
+        };
+        let Child::Tree(error) = &rule.children[0] else {
+            panic!("expected tree");
+        };
+        assert_eq!(error.kind, Kind::Error);
+        assert_eq!(error.children.len(), 2);
+
+        assert!(tree.has_errors());
+        assert_eq!(tree.error_nodes().len(), 4);
+    }
+
+    #[test]
+    fn test_no_errors() {
+        let mut parser = Parser::new("A = 'a' (B | 'c')*\nB = 'b'?");
+        parser.parse();
+        assert!(parser.errors().is_empty());
+
+        let tree = parser.tree();
+        assert!(!tree.has_errors());
+        assert!(tree.error_nodes().is_empty());
+    }
+
+    /// Records the visit order, never descending into `skip`
+    struct Recorder {
+        skip: Option<Kind>,
+        visits: Vec<String>,
+    }
+
+    impl Visitor for Recorder {
+        fn visit_tree(&mut self, tree: &Tree) -> bool {
+            self.visits.push(format!("{:?}", tree.kind));
+            self.skip != Some(tree.kind)
+        }
+
+        fn visit_token(&mut self, token: &token::Token) {
+            self.visits.push(format!("{:?}", token.kind));
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut parser = Parser::new("A = 'a' B?");
+        parser.parse();
+        let tree = parser.tree();
+
+        let mut recorder = Recorder {
+            skip: None,
+            visits: Vec::new(),
+        };
+        tree.walk(&mut recorder);
+        assert_eq!(
+            recorder.visits,
+            ["Grammar", "Rule", "Ident", "Branch", "Sequence", "Literal", "Optional", "Ident"]
+        );
+
+        let mut recorder = Recorder {
+            skip: None,
+            visits: Vec::new(),
+        };
+        tree.walk_post(&mut recorder);
+        assert_eq!(
+            recorder.visits,
+            ["Ident", "Literal", "Ident", "Optional", "Sequence", "Branch", "Rule", "Grammar"]
+        );
+
+        let mut recorder = Recorder {
+            skip: Some(Kind::Sequence),
+            visits: Vec::new(),
+        };
+        tree.walk(&mut recorder);
+        assert_eq!(
+            recorder.visits,
+            ["Grammar", "Rule", "Ident", "Branch", "Sequence"]
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let mut parser = Parser::new("A = ('a' B?)* C?\nB = (('b')?)*");
+        parser.parse();
+        let tree = parser.tree();
+
+        assert_eq!(tree.find_all(Kind::Grammar).len(), 1);
+        assert_eq!(tree.find_all(Kind::Rule).len(), 2);
+        assert_eq!(tree.find_all(Kind::ZeroOrMore).len(), 2);
+        assert!(tree.find_all(Kind::OneOrMore).is_empty());
+
+        let optionals = tree.find_all(Kind::Optional);
+        assert_eq!(optionals.len(), 3);
+        // `B?` nested inside the repeat comes before `C?`
+        assert!(matches!(
+            optionals[0].children[..],
+            [Child::Token(token::Token {
+                kind: token::Kind::Ident,
+                span,
+                ..
+            })] if span.range() == (9..10)
+        ));
+
+        let repeat = tree.find_first(Kind::ZeroOrMore).unwrap();
+        assert!(std::ptr::eq(
+            repeat.find_first(Kind::Optional).unwrap(),
+            optionals[0]
+        ));
+        assert!(tree.find_first(Kind::Error).is_none());
+    }
+
+    #[test]
+    fn test_source_text() {
+        let source = "A = 'a' | B\nB = ( 'b' 'c' )*\nC =";
+        let mut parser = Parser::new(source);
+        parser.parse();
+        let tree = parser.tree();
+
+        let rules = tree.find_all(Kind::Rule);
+        assert_eq!(rules[0].source_text(source), Some("A = 'a' | B"));
+        // Skipped punctuation at the edges is not part of the tree
+        assert_eq!(rules[1].source_text(source), Some("B = ( 'b' 'c'"));
+        let repeat = tree.find_first(Kind::ZeroOrMore).unwrap();
+        assert_eq!(repeat.source_text(source), Some("'b' 'c'"));
+        assert_eq!(tree.span(), Some(Span::new(0, 30)));
+
+        let empty = rules[2].find_first(Kind::Sequence).unwrap();
+        assert_eq!(empty.span(), None);
+        assert_eq!(empty.source_text(source), None);
+    }
+
+    #[test]
+    fn test_sexp() {
+        let source = "Expr = 'a' B*\nB = ('b' | C)? D+\nC =";
+        let mut parser = Parser::new(source);
+        parser.parse();
+        let tree = parser.tree();
+
+        assert_eq!(
+            tree.to_sexp(source),
+            "(Grammar \
+             (Rule Expr (Branch (Sequence 'a' (ZeroOrMore B)))) \
+             (Rule B (Branch (Sequence (Optional (Branch (Sequence 'b') (Sequence C))) (OneOrMore D)))) \
+             (Rule C (Branch (Sequence (Error)))))"
+        );
+    }
+
+    fn parse(source: &str, options: ParseOptions) -> (Tree, Vec<Diagnostic>) {
+        let mut parser = Parser::new_with_options(source, options);
+        parser.parse();
+        let diagnostics = parser.diagnostics();
+        (parser.tree(), diagnostics)
+    }
+
+    #[test]
+    fn test_preserve_trivia() {
+        let source = "A = 'a' # c\nB = 'b'\n";
+        let options = ParseOptions {
+            preserve_trivia: true,
+            ..Default::default()
+        };
+        let (tree, _) = parse(source, options);
+        assert_eq!(
+            tree.to_sexp(source).replace('\n', "\\n"),
+            "(Grammar \
+             (Rule A   (Branch (Sequence   'a'))) \
+             (Rule   # c \\n B   (Branch (Sequence   'b'))) \\n)"
+        );
+
+        let grammar = GrammarBuilder::new(source, tree).build();
+        assert_eq!(grammar.to_ebnf_string(), "A = 'a'\nB = 'b'\n");
+    }
+
+    #[test]
+    fn test_full_fidelity() {
+        let source = "A = 'a' # c\n// doc\nB = ('b' )\n";
+        let options = ParseOptions {
+            full_fidelity: true,
+            ..Default::default()
+        };
+        let (tree, _) = parse(source, options);
+
+        struct Tokens<'a>(&'a str, Vec<(&'a str, Vec<&'a str>, Vec<&'a str>)>);
+        impl Visitor for Tokens<'_> {
+            fn visit_tree(&mut self, _: &Tree) -> bool {
+                true
+            }
+            fn visit_token(&mut self, token: &token::Token) {
+                let text = |tokens: &[token::Token]| {
+                    tokens
+                        .iter()
+                        .map(|token| &self.0[token.span.range()])
+                        .collect()
+                };
+                let entry = (
+                    &self.0[token.span.range()],
+                    text(&token.leading_trivia),
+                    text(&token.trailing_trivia),
+                );
+                self.1.push(entry);
+            }
+        }
+
+        let mut tokens = Tokens(source, Vec::new());
+        tree.walk(&mut tokens);
+        assert_eq!(
+            tokens.1,
+            [
+                ("A", vec![], vec![" "]),
+                ("'a'", vec![" "], vec![" ", "# c"]),
+                ("B", vec!["\n", "// doc", "\n"], vec![" "]),
+                ("'b'", vec![" "], vec![" ", "\n"]),
+            ]
+        );
+
+        let rules = tree.find_all(Kind::Rule);
+        assert_eq!(rules[1].source_text(source), Some("B = ('b'"));
+        let (plain, _) = parse(source, ParseOptions::default());
+        assert_eq!(
+            plain.find_all(Kind::Rule)[1].source_text(source),
+            rules[1].source_text(source)
+        );
+
+        let Child::Token(b) = &rules[1].children[0] else {
+            panic!("expected token");
+        };
+        assert_eq!(&source[b.full_span().range()], "\n// doc\nB ");
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let source = "A = (('a') 'b')* 'c'\nB = ((('b')+))";
+        let options = ParseOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let (tree, diagnostics) = parse(source, options);
+        assert_eq!(
+            diagnostics,
+            [Diagnostic::error(
+                Span::new(27, 28),
+                "groups nested deeper than 2 levels"
+            )]
+        );
+        assert_eq!(
+            tree.to_sexp(source),
+            "(Grammar \
+             (Rule A (Branch (Sequence (ZeroOrMore (Branch (Sequence (Branch (Sequence 'a')) 'b'))) 'c'))) \
+             (Rule B (Branch (Sequence (Branch (Sequence (Branch (Sequence (Error ( 'b' ) +)))))))))"
+        );
+    }
+
+    #[test]
+    fn test_no_error_recovery() {
+        let source = "A = 'a'\nB = )\nC = 'c'";
+        let options = ParseOptions {
+            error_recovery: false,
+            ..Default::default()
+        };
+        let (tree, diagnostics) = parse(source, options);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            tree.to_sexp(source),
+            "(Grammar (Rule A (Branch (Sequence 'a'))) (Rule B (Branch (Sequence (Error ) C = 'c')))))"
+        );
+
+        let (tree, _) = parse(source, ParseOptions::default());
+        assert_eq!(tree.find_all(Kind::Rule).len(), 3);
+    }
+
+    /// Trace output kept readable after it is handed to the parser
+    #[derive(Clone, Default)]
+    struct SharedBuffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace() {
+        let buffer = SharedBuffer::default();
+        let mut parser = Parser::new_with_trace("A = 'a'", Box::new(buffer.clone()));
+        parser.parse();
+        let trace = String::from_utf8(buffer.0.take()).unwrap();
+        expect![[r#"
+            Open 0..0
+            Open 0..0
+            Advance Ident 0..1
+            Skip Equal 2..3
+            Open 4..4
+            Open 4..4
+            Advance Literal 4..7
+            Close Sequence 7..7
+            Close Branch 7..7
+            Close Rule 7..7
+            Close Grammar 7..7
+        "#]]
+        .assert_eq(&trace);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let source = "A = 'a' | 'b'\nB = ";
+        let mut parser = Parser::new(source);
+        let checkpoint = parser.checkpoint();
+        parser.parse();
+        assert_eq!(parser.errors().len(), 1);
+
+        parser.restore(checkpoint);
+        assert_eq!(parser.checkpoint(), checkpoint);
+        assert!(parser.errors().is_empty());
+        parser.parse();
+        assert_eq!(parser.errors().len(), 1);
+        assert_eq!(
+            parser.tree().to_sexp(source),
+            parse(source, ParseOptions::default()).0.to_sexp(source)
+        );
+
+        let mut parser = Parser::new("'a' 'b' | 'c'");
+        parser.advance();
+        let checkpoint = parser.checkpoint();
+        let body = parser.parse_rule_body().unwrap();
+        parser.restore(checkpoint);
+        assert_eq!(parser.parse_rule_body(), Ok(body));
+
+        // Looking for the start of a rule goes back to where it started
+        let options = ParseOptions {
+            preserve_trivia: true,
+            ..Default::default()
+        };
+        let mut parser = Parser::new_with_options("# c\nA = 'a' B", options);
+        let checkpoint = parser.checkpoint();
+        assert!(parser.at_rule_start());
+        assert_eq!(parser.checkpoint(), checkpoint);
+        parser.parse();
+        assert!(parser.errors().is_empty());
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        let source = "A = 'a' $\nB = 'b'";
+        let mut parser = Parser::new(source);
+        parser.parse();
+        let errors = parser.lex_errors();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].char, errors[0].span.range()), ('$', 8..9));
+
+        let options = ParseOptions {
+            error_recovery: false,
+            ..Default::default()
+        };
+        let mut parser = Parser::new_with_options(source, options);
+        parser.parse();
+        assert!(parser.lex_errors().is_empty());
+
+        let mut parser = Parser::new(source);
+        parser.parse();
+        assert_eq!(
+            parser.diagnostics(),
+            vec![Diagnostic::error(
+                Span::new(8, 9),
+                "unexpected character '$'"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_rule_body() {
+        let mut parser = Parser::new("'(' Param* ')' | 'x'");
+        assert_eq!(
+            parser.parse_rule_body(),
+            Ok(Expr::Choice(vec![
+                Expr::Sequence(vec![
+                    Expr::Literal("("),
+                    Expr::Repeat(Box::new(Expr::Rule("Param"))),
+                    Expr::Literal(")"),
+                ]),
+                Expr::Sequence(vec![Expr::Literal("x")]),
+            ]))
+        );
+        assert_eq!(parser.peek(), token::Kind::Eof);
+
+        // Stops before the next rule and leaves it to be parsed
+        let mut parser = Parser::new("A? B\nB = 'b'");
+        assert_eq!(
+            parser.parse_rule_body(),
+            Ok(Expr::Sequence(vec![
+                Expr::Optional(Box::new(Expr::Rule("A"))),
+                Expr::Rule("B"),
+            ]))
+        );
+        parser.parse();
+        assert!(parser.errors().is_empty());
+        assert_eq!(parser.tree().find_all(Kind::Rule).len(), 1);
+
+        let mut parser = Parser::new(") 'a'");
+        let error = parser.parse_rule_body().unwrap_err();
+        assert_eq!(error.expected, token::Kind::Ident);
+        assert_eq!(error.found.kind, token::Kind::Paren(token::Paren::Close));
+        assert!(parser.errors().is_empty());
+    }
+
+    fn check_sexp(source: &str, expect: Expect) {
+        let (tree, _) = parse(source, ParseOptions::default());
+        expect.assert_eq(&tree.to_sexp(source));
+    }
+
+    #[test]
+    fn test_sexp_snapshots() {
+        check_sexp(
+            "A = 'a'",
+            expect!["(Grammar (Rule A (Branch (Sequence 'a'))))"],
+        );
+        check_sexp(
+            "A = 'a' | B | 'c'",
+            expect!["(Grammar (Rule A (Branch (Sequence 'a') (Sequence B) (Sequence 'c'))))"],
+        );
+        check_sexp("A = ('a' B?)?", expect!["(Grammar (Rule A (Branch (Sequence (Optional (Branch (Sequence 'a' (Optional B))))))))"]);
+        check_sexp("A = 'a'* (B 'c')*", expect!["(Grammar (Rule A (Branch (Sequence (ZeroOrMore 'a') (ZeroOrMore (Branch (Sequence B 'c')))))))"]);
+        check_sexp(
+            "A = 'a' B 'c' D",
+            expect!["(Grammar (Rule A (Branch (Sequence 'a' B 'c' D))))"],
+        );
+        check_sexp("A = 'a' )\nB = = 'b'\nC = 'c'", expect!["(Grammar (Rule A (Branch (Sequence 'a'))) (Rule (Error ))) (Rule B (Branch (Sequence (Error = 'b')))) (Rule C (Branch (Sequence 'c'))))"]);
+    }
+}
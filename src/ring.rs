@@ -18,9 +18,108 @@ impl<T, const SIZE: usize> Ring<T, SIZE> {
         }
     }
 
+    /// The most recently pushed element, `self[SIZE - 1]`
+    pub fn peek_back(&self) -> Option<&T> {
+        self.peek_back_n(0)
+    }
+
+    /// The `n`th element counting back from the most recently pushed one
+    pub fn peek_back_n(&self, n: usize) -> Option<&T> {
+        self.get(SIZE.checked_sub(n)?.checked_sub(1)?)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index < SIZE {
+            Some(&mut self.data[(self.head + index) % SIZE])
+        } else {
+            None
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.data.iter().cycle().skip(self.head).take(SIZE)
     }
+
+    /// Mutable iterator over the elements in logical order, `self[0]` first
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        let (wrapped, rest) = self.data.split_at_mut(self.head);
+        IterMut { rest, wrapped }
+    }
+
+    /// Sets every slot to a clone of `value`
+    pub fn fill(&mut self, value: T)
+    where
+        T: Clone,
+    {
+        self.data.fill(value);
+        self.head = 0;
+    }
+
+    pub fn reset_to_default(&mut self)
+    where
+        T: Default + Clone,
+    {
+        self.fill(T::default());
+    }
+
+    /// Consumes the ring yielding its elements in logical order, `self[0]` first
+    pub fn drain(self) -> impl Iterator<Item = T> {
+        self.into_array().into_iter()
+    }
+
+    /// The elements in logical order, `self[0]` first
+    pub fn into_array(mut self) -> [T; SIZE] {
+        self.data.rotate_left(self.head);
+        self.data
+    }
+}
+
+/// Pushes every item in order, so only the last `SIZE` are kept
+impl<T: Default + Copy, const SIZE: usize> FromIterator<T> for Ring<T, SIZE> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut ring = Ring::new();
+        for value in iter {
+            ring.push(value);
+        }
+        ring
+    }
+}
+
+impl<T, const SIZE: usize> IntoIterator for Ring<T, SIZE> {
+    type Item = T;
+    type IntoIter = std::array::IntoIter<T, SIZE>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_array().into_iter()
+    }
+}
+
+/// Rings are equal when their elements are equal in logical order, wherever `head` is
+impl<T: PartialEq, const SIZE: usize> PartialEq for Ring<T, SIZE> {
+    fn eq(&self, other: &Self) -> bool {
+        self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq, const SIZE: usize> Eq for Ring<T, SIZE> {}
+
+/// Yields the slots from `head` to the end of the buffer, then the ones before `head`
+pub struct IterMut<'a, T> {
+    rest: &'a mut [T],
+    wrapped: &'a mut [T],
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            self.rest = std::mem::take(&mut self.wrapped);
+        }
+        let (first, rest) = std::mem::take(&mut self.rest).split_first_mut()?;
+        self.rest = rest;
+        Some(first)
+    }
 }
 
 impl<T: Default + Clone + Copy, const SIZE: usize> Ring<T, SIZE> {
@@ -49,6 +148,12 @@ impl<T, const SIZE: usize> std::ops::Index<usize> for Ring<T, SIZE> {
     }
 }
 
+impl<T, const SIZE: usize> std::ops::IndexMut<usize> for Ring<T, SIZE> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::ring::Ring;
@@ -79,4 +184,95 @@ mod test {
         assert_eq!(ring[1], 3);
         assert_eq!(ring[2], 4);
     }
+
+    #[test]
+    fn test_ring_index_mut() {
+        let mut ring = Ring::<i8, 2>::new();
+        ring.push(1);
+        ring.push(2);
+        ring.push(3);
+        ring[0] = 5;
+        ring[1] += 1;
+        assert_eq!(ring[0], 5);
+        assert_eq!(ring[1], 4);
+        assert_eq!(ring.get_mut(2), None);
+        ring.push(6);
+        assert_eq!(ring[0], 4);
+        assert_eq!(ring[1], 6);
+    }
+
+    #[test]
+    fn test_ring_iter_mut() {
+        let mut ring = Ring::<i8, 3>::new();
+        for value in 1..=4 {
+            ring.push(value);
+        }
+        for (i, value) in ring.iter_mut().enumerate() {
+            *value *= 10;
+            *value += i as i8;
+        }
+        assert_eq!(ring.data(), [20, 31, 42]);
+        assert_eq!(ring.iter_mut().count(), 3);
+    }
+
+    #[test]
+    fn test_ring_eq() {
+        let mut a = Ring::<i8, 3>::new();
+        let mut b = Ring::<i8, 3>::new();
+        for value in 1..=3 {
+            a.push(value);
+        }
+        for value in 0..=3 {
+            b.push(value);
+        }
+        assert_eq!(a.head, 0);
+        assert_eq!(b.head, 1);
+        assert_eq!(a, b);
+
+        b[2] = 4;
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_ring_collect() {
+        let ring: Ring<u8, 4> = vec![1, 2, 3, 4].into_iter().collect();
+        assert_eq!(ring.into_iter().collect::<Vec<_>>(), [1, 2, 3, 4]);
+
+        let ring: Ring<u8, 3> = (1..=5).collect();
+        assert_eq!(ring.into_iter().collect::<Vec<_>>(), [3, 4, 5]);
+
+        let ring: Ring<u8, 3> = [7].into_iter().collect();
+        assert_eq!(ring.into_iter().collect::<Vec<_>>(), [0, 0, 7]);
+    }
+
+    #[test]
+    fn test_ring_drain() {
+        let ring: Ring<i8, 3> = (1..=4).collect();
+        assert_eq!(ring.clone().into_array(), [2, 3, 4]);
+        assert_eq!(ring.drain().collect::<Vec<_>>(), [2, 3, 4]);
+    }
+
+    #[test]
+    fn test_ring_fill() {
+        let mut ring: Ring<i8, 3> = (1..=4).collect();
+        ring.fill(7);
+        assert_eq!(ring.data(), [7, 7, 7]);
+        ring.push(1);
+        assert_eq!(ring.data(), [7, 7, 1]);
+        ring.reset_to_default();
+        assert_eq!(ring.data(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_ring_peek_back() {
+        let mut ring = Ring::<i8, 3>::new();
+        for value in 1..=4 {
+            ring.push(value);
+        }
+        assert_eq!(ring.peek_back(), Some(&4));
+        assert_eq!(ring.peek_back_n(1), Some(&3));
+        assert_eq!(ring.peek_back_n(2), Some(&ring[0]));
+        assert_eq!(ring.peek_back_n(3), None);
+        assert_eq!(Ring::<i8, 0>::new().peek_back(), None);
+    }
 }
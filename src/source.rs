@@ -0,0 +1,125 @@
+//! Source text of a grammar together with the name diagnostics refer to it by
+
+use crate::span::{Location, Span};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SourceFile {
+    /// Path of the file, or a placeholder like `<stdin>`
+    pub name: String,
+    pub content: String,
+}
+
+/// Files of a grammar split across several sources, identified by their index
+#[derive(Debug, Default)]
+pub struct SourceFileSet {
+    files: Vec<SourceFile>,
+}
+
+/// An error found in `file`, displayed prefixed by the file name
+pub struct FileError<'a, E> {
+    pub file: &'a SourceFile,
+    pub error: E,
+}
+
+impl SourceFile {
+    pub fn new(name: impl Into<String>, content: String) -> Self {
+        Self {
+            name: name.into(),
+            content,
+        }
+    }
+
+    pub fn span_location(&self, span: Span) -> Location {
+        span.location(&self.content)
+    }
+
+    /// Text of the 1-indexed `line` without its line ending
+    pub fn line_text(&self, line: usize) -> Option<&str> {
+        self.content.lines().nth(line.checked_sub(1)?)
+    }
+
+    /// Pairs `error` with the file so its message starts with the file name
+    pub fn error<E>(&self, error: E) -> FileError<'_, E> {
+        FileError { file: self, error }
+    }
+}
+
+impl SourceFileSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `file` and returns its index
+    pub fn add(&mut self, file: SourceFile) -> usize {
+        self.files.push(file);
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, index: usize) -> Option<&SourceFile> {
+        self.files.get(index)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&SourceFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for FileError<'_, E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.name, self.error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::grammar::ValidationError;
+
+    #[test]
+    fn test_source_file() {
+        let file = SourceFile::new("example.ungram", "A = 'a'\r\nB = 'b' C\n".to_string());
+        assert_eq!(
+            format!("{:?}", file.span_location(Span::new(17, 18))),
+            "2:9"
+        );
+        assert_eq!(file.line_text(1), Some("A = 'a'"));
+        assert_eq!(file.line_text(2), Some("B = 'b' C"));
+        assert_eq!(file.line_text(0), None);
+        assert_eq!(file.line_text(3), None);
+
+        let error = ValidationError::UndefinedRule { rule: "C" };
+        assert_eq!(
+            file.error(&error).to_string(),
+            format!("example.ungram: {error}")
+        );
+    }
+
+    #[test]
+    fn test_source_file_set() {
+        let mut files = SourceFileSet::new();
+        assert!(files.is_empty());
+        let a = files.add(SourceFile::new("a.ungram", "A = B".to_string()));
+        let b = files.add(SourceFile::new("b.ungram", "B = 'b'".to_string()));
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.get(b).unwrap().content, "B = 'b'");
+        assert_eq!(files.find("a.ungram"), files.get(a));
+        assert_eq!(files.find("c.ungram"), None);
+        assert_eq!(
+            files.iter().map(|file| &file.name[..]).collect::<Vec<_>>(),
+            ["a.ungram", "b.ungram"]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Hash)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
@@ -10,12 +10,35 @@ pub struct Location {
     pub column: usize,
 }
 
+/// Line starts of a source to turn byte offsets into locations and back in O(log n)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    /// Byte offset of every `\n`
+    newlines: Vec<usize>,
+    /// Byte offset and length of every character longer than a byte, columns count characters
+    wide_chars: Vec<(usize, usize)>,
+    len: usize,
+}
+
 impl Default for Span {
     fn default() -> Self {
         Span { start: 0, end: 0 }
     }
 }
 
+/// Ordered by `start`, spans starting at the same offset are ordered by `end`
+impl Ord for Span {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.start, self.end).cmp(&(other.start, other.end))
+    }
+}
+
+impl PartialOrd for Span {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl std::fmt::Debug for Span {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}..{}", self.start, self.end)
@@ -37,6 +60,101 @@ impl std::fmt::Debug for Location {
     }
 }
 
+impl Location {
+    /// Byte offset of the location in `source`, the reverse of [`Span::location`].
+    ///
+    /// Returns `None` when the line does not exist or the column is past the end of the line
+    pub fn to_byte_offset(self, source: &str) -> Option<usize> {
+        let mut line = 1;
+        let mut column = 1;
+        for (i, c) in source.char_indices() {
+            if line == self.line && column == self.column {
+                return Some(i);
+            }
+            if c == '\n' {
+                if line == self.line {
+                    return None;
+                }
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        (line == self.line && column == self.column).then_some(source.len())
+    }
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut newlines = Vec::new();
+        let mut wide_chars = Vec::new();
+        for (i, c) in source.char_indices() {
+            if c == '\n' {
+                newlines.push(i);
+            } else if c.len_utf8() > 1 {
+                wide_chars.push((i, c.len_utf8()));
+            }
+        }
+        Self {
+            newlines,
+            wide_chars,
+            len: source.len(),
+        }
+    }
+
+    /// Same as [`Span::location`] of a span starting at `offset`
+    pub fn location_of(&self, offset: usize) -> Location {
+        let offset = offset.min(self.len);
+        let line = self.newlines.partition_point(|&newline| newline < offset);
+        let start = self.line_start(line);
+        Location {
+            line: line + 1,
+            column: offset - start - self.extra_bytes(start, offset) + 1,
+        }
+    }
+
+    /// Same as [`Location::to_byte_offset`]
+    pub fn offset_of(&self, location: Location) -> Option<usize> {
+        let line = location.line.checked_sub(1)?;
+        if line > self.newlines.len() || location.column == 0 {
+            return None;
+        }
+        let start = self.line_start(line);
+        let end = self.newlines.get(line).copied().unwrap_or(self.len);
+
+        let mut offset = start + location.column - 1;
+        for &(wide, len) in self.wide_chars_between(start, end) {
+            if wide >= offset {
+                break;
+            }
+            offset += len - 1;
+        }
+        (offset <= end).then_some(offset)
+    }
+
+    fn line_start(&self, line: usize) -> usize {
+        match line {
+            0 => 0,
+            line => self.newlines[line - 1] + 1,
+        }
+    }
+
+    fn wide_chars_between(&self, start: usize, end: usize) -> &[(usize, usize)] {
+        let from = self.wide_chars.partition_point(|&(i, _)| i < start);
+        let to = self.wide_chars.partition_point(|&(i, _)| i < end);
+        &self.wide_chars[from..to]
+    }
+
+    /// Bytes past the first of the characters between `start` and `end`
+    fn extra_bytes(&self, start: usize, end: usize) -> usize {
+        self.wide_chars_between(start, end)
+            .iter()
+            .map(|(_, len)| len - 1)
+            .sum()
+    }
+}
+
 impl Span {
     pub fn new(start: usize, end: usize) -> Span {
         Span { start, end }
@@ -45,8 +163,8 @@ impl Span {
     pub fn location(&self, source: &str) -> Location {
         let mut line = 1;
         let mut column = 1;
-        for (i, c) in source.chars().enumerate() {
-            if i == self.start {
+        for (i, c) in source.char_indices() {
+            if i >= self.start {
                 break;
             }
             if c == '\n' {
@@ -66,4 +184,140 @@ impl Span {
     pub fn len(&self) -> usize {
         self.end - self.start
     }
+
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Smallest span containing both `self` and `other`
+    pub fn merge(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// Smallest span containing every span in `spans`, `None` if there are none
+    pub fn union(spans: impl IntoIterator<Item = Span>) -> Option<Span> {
+        spans.into_iter().reduce(Span::merge)
+    }
+
+    /// Whether the byte offset `pos` falls inside the span
+    pub fn contains(self, pos: usize) -> bool {
+        self.range().contains(&pos)
+    }
+
+    /// Whether both spans share at least one byte
+    pub fn overlaps(self, other: Span) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(Span::new(4, 6).merge(Span::new(1, 3)), Span::new(1, 6));
+        assert_eq!(Span::new(1, 8).merge(Span::new(2, 3)), Span::new(1, 8));
+        assert_eq!(
+            Span::union([Span::new(5, 6), Span::new(2, 3), Span::new(8, 9)]),
+            Some(Span::new(2, 9))
+        );
+        assert_eq!(Span::union([]), None);
+    }
+
+    #[test]
+    fn test_contains_overlaps() {
+        let span = Span::new(2, 5);
+        assert!(!span.contains(1));
+        assert!(span.contains(2));
+        assert!(span.contains(4));
+        assert!(!span.contains(5));
+
+        assert!(span.overlaps(Span::new(4, 8)));
+        assert!(span.overlaps(Span::new(0, 3)));
+        assert!(span.overlaps(Span::new(3, 4)));
+        assert!(!span.overlaps(Span::new(5, 8)));
+        assert!(!span.overlaps(Span::new(0, 2)));
+
+        assert!(Span::new(3, 3).is_empty());
+        assert!(!span.is_empty());
+    }
+
+    #[test]
+    fn test_ord() {
+        let mut spans = vec![
+            Span::new(4, 6),
+            Span::new(1, 9),
+            Span::new(4, 5),
+            Span::new(0, 2),
+            Span::new(1, 3),
+        ];
+        spans.sort();
+        assert_eq!(
+            spans,
+            [
+                Span::new(0, 2),
+                Span::new(1, 3),
+                Span::new(1, 9),
+                Span::new(4, 5),
+                Span::new(4, 6),
+            ]
+        );
+
+        for a in &spans {
+            for b in &spans {
+                assert_eq!(a == b, a.cmp(b).is_eq());
+                assert_eq!(a.partial_cmp(b), Some(a.cmp(b)));
+            }
+        }
+
+        let set = std::collections::HashSet::from([Span::new(1, 3), Span::new(1, 3)]);
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_to_byte_offset() {
+        let src = "A = 'á' B\n\nB = 'b'\n  | 'c'";
+        for start in (0..=src.len()).filter(|&i| src.is_char_boundary(i)) {
+            let span = Span::new(start, start);
+            assert_eq!(span.location(src).to_byte_offset(src), Some(span.start));
+        }
+
+        let location = |line, column| Location { line, column };
+        assert_eq!(location(2, 1).to_byte_offset(src), Some(11));
+        assert_eq!(location(2, 2).to_byte_offset(src), None);
+        assert_eq!(location(1, 11).to_byte_offset(src), None);
+        assert_eq!(location(5, 1).to_byte_offset(src), None);
+        assert_eq!(location(0, 0).to_byte_offset(src), None);
+    }
+
+    #[test]
+    fn test_line_index() {
+        for src in ["A = 'á' B\n\nB = 'b'\n  | 'ü→'", "", "\n", "x\r\n\n"] {
+            let index = LineIndex::new(src);
+            for start in (0..=src.len()).filter(|&i| src.is_char_boundary(i)) {
+                let location = Span::new(start, start).location(src);
+                assert_eq!(index.location_of(start), location, "{src:?} {start}");
+                assert_eq!(
+                    index.offset_of(location),
+                    Some(start),
+                    "{src:?} {location:?}"
+                );
+            }
+
+            for line in 0..6 {
+                for column in 0..12 {
+                    let location = Location { line, column };
+                    assert_eq!(
+                        index.offset_of(location),
+                        location.to_byte_offset(src),
+                        "{src:?} {location:?}"
+                    );
+                }
+            }
+        }
+    }
 }
@@ -1,7 +1,7 @@
 #[derive(Debug, PartialEq, Eq, Clone, Copy, logos::Logos, Default)]
 #[repr(u8)]
 pub enum Kind {
-    #[regex("[ \t\r\n]+", logos::skip)]
+    #[regex("[ \t\r\n]+")]
     Ignored,
 
     #[regex(r"[a-zA-Z0-9_]+")]
@@ -19,13 +19,20 @@ pub enum Kind {
     #[token("?")]
     Question,
 
+    #[token("+")]
+    Plus,
+
     #[regex(r"'([^'\\]|\\['\\bnfrt]|u[a-fA-F0-9]{4})*'")]
     Literal,
 
-    #[regex("#.*", logos::skip)]
-    #[regex("//.*", logos::skip)]
+    #[regex("#.*")]
+    #[regex("//.*")]
     Comment,
 
+    /// Ends at the first `*/`, nested comments are not supported
+    #[token("/*", block_comment)]
+    BlockComment,
+
     #[token("(", |_| Paren::Open)]
     #[token(")", |_| Paren::Close)]
     Paren(Paren),
@@ -39,20 +46,233 @@ pub enum Kind {
     Eof,
 }
 
+impl Kind {
+    /// Whitespace and comments, [`crate::lexer::Lexer`] only keeps them when asked to
+    pub fn is_trivia(&self) -> bool {
+        matches!(self, Kind::Ignored | Kind::Comment | Kind::BlockComment)
+    }
+
+    pub fn is_significant(&self) -> bool {
+        !self.is_trivia()
+    }
+
+    /// Punctuation combining or separating the expressions of a rule, `+` excluded
+    pub fn is_operator(&self) -> bool {
+        matches!(
+            self,
+            Kind::Star | Kind::Question | Kind::Pipe | Kind::Equal | Kind::Colon
+        )
+    }
+
+    pub fn is_bracket(&self) -> bool {
+        matches!(self, Kind::Paren(_))
+    }
+
+    /// The other half of a bracket, `None` for any other token
+    pub fn matching_bracket(&self) -> Option<Kind> {
+        match self {
+            Kind::Paren(Paren::Open) => Some(Kind::Paren(Paren::Close)),
+            Kind::Paren(Paren::Close) => Some(Kind::Paren(Paren::Open)),
+            _ => None,
+        }
+    }
+
+    /// Source spelling of tokens that can only be written one way
+    pub fn as_str(&self) -> Option<&'static str> {
+        match self {
+            Kind::Equal => Some("="),
+            Kind::Colon => Some(":"),
+            Kind::Star => Some("*"),
+            Kind::Question => Some("?"),
+            Kind::Plus => Some("+"),
+            Kind::Paren(Paren::Open) => Some("("),
+            Kind::Paren(Paren::Close) => Some(")"),
+            Kind::Pipe => Some("|"),
+            _ => None,
+        }
+    }
+
+    /// Human readable name for error messages
+    pub fn description(&self) -> &'static str {
+        match self {
+            Kind::Ignored => "whitespace",
+            Kind::Ident => "identifier",
+            Kind::Equal => "equals sign",
+            Kind::Colon => "colon",
+            Kind::Star => "star",
+            Kind::Question => "question mark",
+            Kind::Plus => "plus sign",
+            Kind::Literal => "string literal",
+            Kind::Comment => "comment",
+            Kind::BlockComment => "block comment",
+            Kind::Paren(Paren::Open) => "opening parenthesis",
+            Kind::Paren(Paren::Close) => "closing parenthesis",
+            Kind::Pipe => "pipe",
+            Kind::Error => "invalid token",
+            Kind::Eof => "end of file",
+        }
+    }
+}
+
+/// Extends the token up to the first `*/`, an unterminated comment is an error spanning the rest
+/// of the source
+fn block_comment(lex: &mut logos::Lexer<Kind>) -> logos::FilterResult<(), ()> {
+    match lex.remainder().find("*/") {
+        Some(end) => {
+            lex.bump(end + 2);
+            logos::FilterResult::Emit(())
+        }
+        None => {
+            lex.bump(lex.remainder().len());
+            logos::FilterResult::Error(())
+        }
+    }
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str().unwrap_or(self.description()))
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Paren {
     Open,
     Close,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Token {
     pub span: crate::span::Span,
     pub kind: Kind,
+    /// Whitespace and comments since the previous token, only filled in full fidelity mode
+    pub leading_trivia: Vec<Token>,
+    /// Whitespace and comments up to the end of the line, only filled in full fidelity mode
+    pub trailing_trivia: Vec<Token>,
 }
 
 impl Token {
     pub fn new(span: crate::span::Span, kind: Kind) -> Self {
-        Self { span, kind }
+        Self {
+            span,
+            kind,
+            leading_trivia: Vec::new(),
+            trailing_trivia: Vec::new(),
+        }
+    }
+
+    /// Span of the token with its leading and trailing trivia
+    pub fn full_span(&self) -> crate::span::Span {
+        let start = self
+            .leading_trivia
+            .first()
+            .map_or(self.span, |token| token.span);
+        let end = self
+            .trailing_trivia
+            .last()
+            .map_or(self.span, |token| token.span);
+        start.merge(end)
+    }
+}
+
+/// The spelling of fixed tokens, i.e `=`, other tokens by what they are like `<identifier>`
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            Kind::Literal => f.write_str("<literal>"),
+            kind => match kind.as_str() {
+                Some(text) => f.write_str(text),
+                None => write!(f, "<{}>", kind.description()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use logos::Logos;
+
+    use crate::span::Span;
+    use crate::token::{Kind, Paren, Token};
+
+    #[test]
+    fn test_trivia() {
+        for kind in [Kind::Ignored, Kind::Comment, Kind::BlockComment] {
+            assert!(kind.is_trivia());
+            assert!(!kind.is_significant());
+        }
+        for kind in [
+            Kind::Ident,
+            Kind::Literal,
+            Kind::Paren(Paren::Open),
+            Kind::Eof,
+        ] {
+            assert!(kind.is_significant());
+        }
+
+        assert_eq!(Kind::Ident.description(), "identifier");
+        assert_eq!(Kind::Literal.description(), "string literal");
+        assert_eq!(Kind::Eof.description(), "end of file");
+    }
+
+    #[test]
+    fn test_operators_brackets() {
+        let operators = [
+            Kind::Star,
+            Kind::Question,
+            Kind::Pipe,
+            Kind::Equal,
+            Kind::Colon,
+        ];
+        for kind in operators {
+            assert!(kind.is_operator() && !kind.is_bracket());
+            assert_eq!(kind.matching_bracket(), None);
+        }
+        for kind in [Kind::Plus, Kind::Ident, Kind::Literal, Kind::Eof] {
+            assert!(!kind.is_operator() && !kind.is_bracket());
+        }
+
+        let (open, close) = (Kind::Paren(Paren::Open), Kind::Paren(Paren::Close));
+        assert!(open.is_bracket() && close.is_bracket());
+        assert!(!open.is_operator());
+        assert_eq!(open.matching_bracket(), Some(close));
+        assert_eq!(close.matching_bracket(), Some(open));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Kind::Equal.to_string(), "=");
+        assert_eq!(Kind::Star.to_string(), "*");
+        assert_eq!(Kind::Ident.to_string(), "identifier");
+        assert_eq!(Kind::Literal.to_string(), "string literal");
+        assert_eq!(Kind::Eof.to_string(), "end of file");
+
+        let token = |kind| Token::new(Span::new(0, 1), kind).to_string();
+        assert_eq!(token(Kind::Paren(Paren::Open)), "(");
+        assert_eq!(token(Kind::Ident), "<identifier>");
+        assert_eq!(token(Kind::Literal), "<literal>");
+        assert_eq!(token(Kind::Eof), "<end of file>");
+    }
+
+    #[test]
+    fn test_as_str() {
+        let fixed = [
+            Kind::Equal,
+            Kind::Colon,
+            Kind::Star,
+            Kind::Question,
+            Kind::Plus,
+            Kind::Paren(Paren::Open),
+            Kind::Paren(Paren::Close),
+            Kind::Pipe,
+        ];
+        for kind in fixed {
+            let text = kind.as_str().unwrap();
+            let lexed = Kind::lexer(text).collect::<Vec<_>>();
+            assert_eq!(lexed, [Ok(kind)], "{text}");
+        }
+
+        assert_eq!(Kind::Ident.as_str(), None);
+        assert_eq!(Kind::Literal.as_str(), None);
     }
 }
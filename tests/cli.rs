@@ -0,0 +1,206 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Runs the binary with `args`, feeding `stdin` to it
+fn ungram(args: &[&str], stdin: &str) -> std::process::Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ungram"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The process may exit before reading its input
+    let _ = child.stdin.take().unwrap().write_all(stdin.as_bytes());
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_first_from_stdin() {
+    let output = ungram(&["first", "-", "S"], "S = 'a' B | B\nB = 'b'");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "{\"a\", \"b\"}\n");
+}
+
+#[test]
+fn test_follow_single_rule() {
+    let output = ungram(&["follow", "-", "B"], "S = A B 'c'\nA = 'a'\nB = 'b'");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout, "{\"c\"}\n");
+}
+
+#[test]
+fn test_format_inplace_stdin() {
+    let output = ungram(&["format", "-i", "-"], "S = 'a'");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_table_left_recursive() {
+    let output = ungram(&["table", "-"], "Expr = Expr '+' Term | Term\nTerm = 'n'");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "error: rule `Expr` is left recursive\n");
+}
+
+const EXAMPLE: &str = "\
+S = File '#'
+File = Fn*
+Fn = 'fn' 'name' ParamList ('->' 'type')? Block
+ParamList = '(' Param* ')'
+Param = 'name' ':' 'type' ','?
+Block = '{' 'statement'+ '}' | ';'
+";
+
+/// Input accepted by [`EXAMPLE`], terminals separated by spaces
+const EXAMPLE_INPUT: &str =
+    "fn name ( name : type , name : type ) -> type { statement statement } fn name ( ) ; #";
+
+const RECURSIVE_DESCENT_HARNESS: &str = r#"
+mod generated;
+
+struct Input<'a>(Vec<&'a str>);
+
+impl generated::Tokens for Input<'_> {
+    fn peek(&self) -> &str {
+        self.0.last().copied().unwrap_or("$")
+    }
+
+    fn expect(&mut self, expected: &str) {
+        assert_eq!(self.0.pop(), Some(expected));
+    }
+
+    fn error(&mut self, expected: &[&str]) {
+        panic!("expected one of {expected:?}, got {:?}", self.0.last());
+    }
+}
+
+fn main() {
+    let input = std::env::args().nth(1).unwrap();
+    let mut input = Input(input.split_whitespace().rev().collect());
+    generated::parse_s(&mut input);
+    assert!(input.0.is_empty());
+}
+"#;
+
+const COMBINATOR_HARNESS: &str = r#"
+mod generated;
+
+/// Number of terminals matched at the start of the input
+struct Parser(Box<dyn Fn(&[&str]) -> Option<usize>>);
+
+impl generated::Combinators for Parser {
+    fn token(text: &'static str) -> Self {
+        Parser(Box::new(move |input| (input.first() == Some(&text)).then_some(1)))
+    }
+
+    fn rule(parser: fn() -> Self) -> Self {
+        Parser(Box::new(move |input| (parser().0)(input)))
+    }
+
+    fn seq(parsers: Vec<Self>) -> Self {
+        Parser(Box::new(move |input| {
+            let mut used = 0;
+            for parser in &parsers {
+                used += (parser.0)(&input[used..])?;
+            }
+            Some(used)
+        }))
+    }
+
+    fn any_of(parsers: Vec<Self>) -> Self {
+        Parser(Box::new(move |input| parsers.iter().find_map(|parser| (parser.0)(input))))
+    }
+
+    fn optional(parser: Self) -> Self {
+        Parser(Box::new(move |input| Some((parser.0)(input).unwrap_or(0))))
+    }
+
+    fn many(parser: Self) -> Self {
+        Parser(Box::new(move |input| {
+            let mut used = 0;
+            while let Some(n @ 1..) = (parser.0)(&input[used..]) {
+                used += n;
+            }
+            Some(used)
+        }))
+    }
+
+    fn many1(parser: Self) -> Self {
+        let many = Self::many(Parser(Box::new(move |input| (parser.0)(input))));
+        Parser(Box::new(move |input| (many.0)(input).filter(|used| *used > 0)))
+    }
+}
+
+fn main() {
+    let input = std::env::args().nth(1).unwrap();
+    let input = input.split_whitespace().collect::<Vec<_>>();
+    let parser = generated::parse_s::<Parser>();
+    assert_eq!((parser.0)(&input), Some(input.len()));
+}
+"#;
+
+/// Compiles the generated parser with `harness` as its main file and runs it on `input`
+fn run_generated(style: &str, harness: &str, input: &str) -> std::process::Output {
+    let output = ungram(&["generate", "-", "--style", style], EXAMPLE);
+    assert!(output.status.success());
+
+    let dir = std::env::temp_dir().join(format!("ungram-{style}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("generated.rs"), output.stdout).unwrap();
+    std::fs::write(dir.join("main.rs"), harness).unwrap();
+
+    let compiled = Command::new("rustc")
+        .args(["--edition", "2021", "-o"])
+        .arg(dir.join("parser"))
+        .arg(dir.join("main.rs"))
+        .output()
+        .unwrap();
+    assert!(
+        compiled.status.success() && compiled.stderr.is_empty(),
+        "{}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+
+    let output = Command::new(dir.join("parser"))
+        .arg(input)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    output
+}
+
+#[test]
+fn test_generate_recursive_descent() {
+    let output = run_generated(
+        "recursive-descent",
+        RECURSIVE_DESCENT_HARNESS,
+        EXAMPLE_INPUT,
+    );
+    assert!(output.status.success());
+
+    let output = run_generated(
+        "recursive-descent",
+        RECURSIVE_DESCENT_HARNESS,
+        "fn name ( #",
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_generate_parser_combinator() {
+    let output = run_generated("parser-combinator", COMBINATOR_HARNESS, EXAMPLE_INPUT);
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_generate_left_recursive() {
+    let output = ungram(&["generate", "-"], "E = E '+' 'n' | 'n'");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr, "error: rule `E` is left recursive\n");
+}